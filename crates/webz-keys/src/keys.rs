@@ -5,6 +5,7 @@ use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 
 use bip0039::{Count, English, Mnemonic};
+use zcash_keys::keys::Era;
 use zcash_primitives::zip32::AccountId;
 
 use crate::error::Error;
@@ -40,6 +41,27 @@ impl UnifiedSpendingKey {
         })
     }
 
+    /// Encode the spending key as bytes
+    ///
+    /// This is how the key is handed to other modules, e.g. `WebWallet.create_proposed_transactions_with_usk` in webz-wallet.
+    /// The bytes are as sensitive as the seed the key was derived from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.inner.to_bytes(Era::Orchard)
+    }
+
+    /// Construct a spending key from the bytes returned by `to_bytes`
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The encoded spending key
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<UnifiedSpendingKey, Error> {
+        Ok(Self {
+            inner: zcash_keys::keys::UnifiedSpendingKey::from_bytes(Era::Orchard, bytes)
+                .map_err(|e| Error::KeyDecoding(format!("{:?}", e)))?,
+        })
+    }
+
     /// Obtain the UFVK corresponding to this spending key
     pub fn to_unified_full_viewing_key(&self) -> UnifiedFullViewingKey {
         UnifiedFullViewingKey {
@@ -1,2 +1,5 @@
 mod error;
 mod requests;
+
+pub use error::Error;
+pub use requests::{PaymentRequest, TransactionRequest};
@@ -65,6 +65,25 @@ impl TransactionRequest {
     }
 }
 
+impl TransactionRequest {
+    /// The underlying ZIP-321 request, for use by other crates
+    pub fn inner(&self) -> &zip321::TransactionRequest {
+        &self.0
+    }
+}
+
+impl From<zip321::TransactionRequest> for TransactionRequest {
+    fn from(request: zip321::TransactionRequest) -> Self {
+        TransactionRequest(request)
+    }
+}
+
+impl From<TransactionRequest> for zip321::TransactionRequest {
+    fn from(request: TransactionRequest) -> Self {
+        request.0
+    }
+}
+
 /// A ZIP-321 transaction request
 #[wasm_bindgen]
 pub struct PaymentRequest(zip321::Payment);
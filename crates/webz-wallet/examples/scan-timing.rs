@@ -0,0 +1,79 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Compare how long a sync takes when each batch is scanned on a single thread, as the sync loop used to, and when
+//! batches are split into chunks scanned in parallel.
+//!
+//! The same range of recent blocks is synced once per chunk size by a fresh wallet, so both runs download and scan the
+//! same blocks. Run with `RUST_LOG=info` to also see the time spent scanning each batch.
+//!
+//! ```sh
+//! cargo run -r --example scan-timing -- https://zec.rocks:443 20000
+//! ```
+
+use std::num::NonZeroU32;
+use std::time::Instant;
+
+use tonic::transport::{Channel, ClientTlsConfig};
+use webz_common::Network;
+use webz_wallet::birthday::Birthday;
+use webz_wallet::sync::DEFAULT_SCAN_CHUNK_SIZE;
+use webz_wallet::{Wallet, PRUNING_DEPTH};
+use zcash_client_backend::proto::service::{
+    compact_tx_streamer_client::CompactTxStreamerClient, ChainSpec,
+};
+use zcash_client_memory::MemoryWalletDb;
+
+const SEED_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = std::env::args().skip(1);
+    let url = args
+        .next()
+        .unwrap_or_else(|| "https://zec.rocks:443".to_string());
+    let blocks: u32 = args.next().map_or(Ok(20_000), |n| n.parse())?;
+
+    let channel = Channel::from_shared(url)?
+        .tls_config(ClientTlsConfig::new().with_webpki_roots())?
+        .connect()
+        .await?;
+    let tip = CompactTxStreamerClient::new(channel.clone())
+        .get_latest_block(ChainSpec::default())
+        .await?
+        .into_inner()
+        .height;
+    let birthday = u32::try_from(tip)?.saturating_sub(blocks);
+
+    for (name, chunk_size) in [
+        ("single thread", usize::MAX),
+        ("parallel chunks", DEFAULT_SCAN_CHUNK_SIZE),
+    ] {
+        let db = MemoryWalletDb::new(Network::MainNetwork, PRUNING_DEPTH);
+        let mut wallet = Wallet::new(
+            db,
+            channel.clone(),
+            Network::MainNetwork,
+            NonZeroU32::new(1).unwrap(),
+        )?;
+        wallet.set_scan_chunk_size(chunk_size);
+        // The bundled checkpoints may not cover the birthday, which is no secret here
+        wallet.allow_birthday_requests(true);
+        wallet
+            .create_account(SEED_PHRASE, 0, Birthday::Height(birthday))
+            .await?;
+
+        let started_at = Instant::now();
+        wallet.sync().await?;
+        println!(
+            "{}: synced {} blocks in {:.2}s",
+            name,
+            blocks,
+            started_at.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
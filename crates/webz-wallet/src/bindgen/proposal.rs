@@ -1,12 +1,20 @@
 use wasm_bindgen::prelude::*;
 
 use super::wallet::NoteRef;
+use crate::error::Error;
+use crate::proposal_encoding::encode_proposal;
+use crate::proposal_summary::ProposalSummary;
 use zcash_primitives::transaction::fees::zip317::FeeRule;
 
 /// A handler to an immutable proposal. This can be passed to `create_proposed_transactions` to prove/authorize the transactions
 /// before they are sent to the network.
 ///
-/// The proposal can be reviewed by calling `describe` which will return a JSON object with the details of the proposal.
+/// The proposal can be reviewed by calling `describe` which will return an object summarizing each transaction it creates: the notes and
+/// transparent outputs it spends, the payments with their pools and memos, the change, the ZIP-317 fee and logical action count, and the
+/// target and expiry heights.
+///
+/// `to_bytes` encodes the proposal so it can be stored, or authorized in another session or on another device after restoring it with
+/// `WebWallet.proposal_from_bytes`.
 #[wasm_bindgen]
 pub struct Proposal {
     inner: zcash_client_backend::proposal::Proposal<FeeRule, NoteRef>,
@@ -26,8 +34,24 @@ impl From<Proposal> for zcash_client_backend::proposal::Proposal<FeeRule, NoteRe
 
 #[wasm_bindgen]
 impl Proposal {
-    /// Returns a JSON object with the details of the proposal.
-    pub fn describe(&self) -> JsValue {
-        serde_wasm_bindgen::to_value(&self.inner).unwrap()
+    /// Returns an object summarizing the transactions of the proposal, for the user to review before authorizing it
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const { steps, total_fee } = proposal.describe();
+    /// for (const payment of steps[0].payments) {
+    ///   console.log(`${payment.amount} to ${payment.address} (${payment.pool})`);
+    /// }
+    /// ```
+    pub fn describe(&self) -> Result<JsValue, Error> {
+        Ok(serde_wasm_bindgen::to_value(&ProposalSummary::from(
+            &self.inner,
+        ))?)
+    }
+
+    /// Encode the proposal in the protobuf proposal format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        encode_proposal(&self.inner)
     }
 }
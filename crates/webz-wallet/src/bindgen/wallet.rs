@@ -1,24 +1,42 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
 use std::num::NonZeroU32;
+use std::rc::Rc;
 use std::str::FromStr;
+use std::time::Duration;
 
 use nonempty::NonEmpty;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tonic_web_wasm_client::Client;
 
+use crate::birthday::Birthday;
+use crate::block_cache::DEFAULT_INDEXED_DB_NAME;
+use crate::change::ChangeOptions;
+use crate::checkpoints::{Checkpoint, Checkpoints};
 use crate::error::Error;
-use crate::wallet::usk_from_seed_str;
+use crate::history::{HistoryFilter, Pool};
+use crate::input_selection::SelectionStrategy;
+use crate::sync::{ActiveSync, ProgressRatio, SyncEvent, DEFAULT_POLL_INTERVAL};
+use crate::wallet::{usk_from_seed_str, TransparentMaturity};
 use crate::{bindgen::proposal::Proposal, Wallet, PRUNING_DEPTH};
 use wasm_thread as thread;
 use webz_common::Network;
+use webz_requests::TransactionRequest;
 use zcash_address::ZcashAddress;
-use zcash_client_backend::data_api::{InputSource, WalletRead};
+use zcash_client_backend::data_api::{AccountPurpose, Balance, InputSource, WalletRead};
 use zcash_client_backend::proto::service::{
     compact_tx_streamer_client::CompactTxStreamerClient, ChainSpec,
 };
+use zcash_client_backend::wallet::NoteId;
+use zcash_client_backend::ShieldedProtocol;
 use zcash_client_memory::MemoryWalletDb;
-use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_keys::address::Address;
+use zcash_keys::keys::{Era, UnifiedFullViewingKey, UnifiedSpendingKey};
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::OutPoint;
 use zcash_primitives::transaction::TxId;
 
 pub type MemoryWallet<T> = Wallet<MemoryWalletDb<Network>, T>;
@@ -44,7 +62,8 @@ pub type NoteRef = <MemoryWalletDb<Network> as InputSource>::NoteRef;
 /// If you do import via a UFVK it is important that you also have access to the Unified Spending Key (USK) for that account otherwise the wallet will not be able to create transactions.
 ///
 /// When importing an account you can also specify the block height at which the account was created. This can significantly reduce the time it takes to sync the account as the wallet will only scan for transactions after this height.
-/// Failing to provide a birthday height will result in extremely slow sync times as the wallet will need to scan the entire blockchain.
+/// If the height isn't known, pass the date the account was created instead and a conservative height is estimated from it. Passing neither sets the birthday
+/// just before the current chain tip, which is only correct for brand new accounts.
 ///
 /// e.g.
 /// ```javascript
@@ -52,30 +71,64 @@ pub type NoteRef = <MemoryWalletDb<Network> as InputSource>::NoteRef;
 ///
 /// // OR
 ///
+/// const account_id = await wallet.create_account("...", 1, new Date("2024-09-01"))
+///
+/// // OR
+///
 /// const account_id = await wallet.import_ufvk("...", 2657762)
 /// ``
 ///
+/// The wallet sets an account's birthday from the nearest checkpoint (a known tree state) at or below the given height, so the height is never sent
+/// to lightwalletd. Checkpoints ship with the library and more can be added with `add_checkpoints`. Accounts can also be imported from a tree
+/// state directly with `create_account_from_tree_state` or `create_account_ufvk_from_tree_state`, which works without a connection to lightwalletd.
+/// If no checkpoint is at or below the birthday, the wallet asks lightwalletd for the tree state instead, which reveals the birthday to it.
+/// Calling `allow_birthday_requests(false)` stops this, and adding such an account then fails.
+///
 /// ## Synchronizing
 ///
 /// The wallet can be synchronized with the blockchain by calling the `sync` method. This will fetch compact blocks from the connected lightwalletd instance and scan them for transactions.
 /// The sync method uses a built-in strategy to determine which blocks is needs to download and scan in order to gain full knowledge of the balances for all accounts that are managed.
 ///
-/// Syncing is a long running process and so is delegated to a WebWorker to prevent from blocking the main thread. It is safe to call other methods on the wallet during syncing although they may take
-/// longer than usual while they wait for a write-lock to be released.
+/// Syncing is a long running process and so is delegated to a WebWorker to prevent from blocking the main thread. It is safe to call other methods on the wallet during syncing.
+/// The sync only locks the wallet for the short time it takes to commit each scanned batch, so these are never held up for longer than that.
 ///
 /// ```javascript
 /// await wallet.sync();
 /// ```
 ///
+/// A sync can be cancelled by passing an AbortSignal, and paused/resumed with `pause_sync` and `resume_sync`. In both cases the sync stops after
+/// the batch of blocks it is currently scanning has been committed so the wallet is always left in a consistent state.
+///
+/// Apps which want to stay up to date without polling `sync` can instead call `watch`, which keeps following the chain and reports new blocks,
+/// reorgs and detected notes until it is stopped.
+///
+/// By default downloaded blocks are only held in memory. Calling `enable_persistent_block_cache` before syncing stores them in IndexedDB as well,
+/// so a sync interrupted by the page closing can resume on the next load without downloading them again.
+///
 /// ## Transacting
 ///
 /// Sending a transaction is a three step process: proposing, authorizing, and sending.
 ///
 /// A transaction proposal is created by calling `propose_transfer` with the intended recipient and amount. This will create a proposal object that describes which notes will be spent in order to fulfil this request.
-/// The proposal should be presented to the user for review before being authorized.
+/// The proposal should be presented to the user for review before being authorized. `estimate_fee` gives the fee a request would cost, and `max_sendable`
+/// the most that can be sent to an address once the fee is paid. To pay several recipients at once or attach memos, pass a ZIP-321
+/// `TransactionRequest` to `propose_request` or a "zcash:" URI to `propose_uri` instead. Where change goes, and how many notes it is split into,
+/// is set with `set_change_options` or per proposal. Each proposal can also pick a strategy for choosing which notes it spends, e.g. to never mix Sapling
+/// and Orchard notes in one transaction. For coin control, `propose_from_notes` and `propose_shielding_from_utxos` spend exactly the notes or transparent
+/// outputs chosen from `list_unspent`.
+///
+/// When a proposal can't be made the error has a `details` object saying why. Its `kind` is one of `InsufficientFunds` and `NotYetSpendable`, both with
+/// the `available` and `required` amounts and the funds of each pool, `NotSynced`, `InvalidRecipient` or `MemoToTransparent`. `NotYetSpendable` also
+/// gives the `spendable_at` height when enough funds will have the confirmations to be spent.
+///
+/// To authorize the transaction the caller must provide either the seed phrase and account index of the account that will be used to sign the transaction
+/// to `create_proposed_transactions`, or its encoded unified spending key to `create_proposed_transactions_with_usk`. A key made with webz-keys can be
+/// passed across with `UnifiedSpendingKey.to_bytes`. This method also perform the SNARK proving which is an expensive operation and performed in parallel by a series of WebWorkers.
+/// The key is checked against the account the proposal spends from before any proving starts. Proving and signing happen in the same call, so the
+/// key has to be handed to the wallet doing the proving. Handling the sensitive key material this way is not recommended for production applications.
 ///
-/// To authorize the transaction the caller must currently provide the seed phrase and account index of the account that will be used to sign the transaction. This method also perform the SNARK proving which is an expensive operation and performed in parallel by a series of WebWorkers.
-/// Note: Handing the sensitive key material this way is not recommended for production applications. Upcoming changes to how proposals are authorized will allow separation of proof generation and signing but currently these are coupled.
+/// Transparent funds are moved into the shielded pool by proposing to shield them with `propose_shielding`, which returns a proposal that is authorized
+/// and sent the same way. Calling `set_auto_shield` makes the wallet propose this on its own after each sync.
 ///
 /// Finally, A transaction can be sent to the network by calling `send_authorized_transactions` with the list of transaction IDs that were generated by the authorization step.
 ///
@@ -90,6 +143,16 @@ pub type NoteRef = <MemoryWalletDb<Network> as InputSource>::NoteRef;
 #[derive(Clone)]
 pub struct WebWallet {
     inner: MemoryWallet<tonic_web_wasm_client::Client>,
+    auto_shield: Rc<RefCell<Option<AutoShield>>>,
+    /// Set while auto-shielding proposals are being made, so a new chain tip doesn't start another round
+    auto_shielding: Rc<Cell<bool>>,
+}
+
+/// The policy set by `set_auto_shield`
+#[derive(Clone)]
+struct AutoShield {
+    threshold: u64,
+    on_proposal: js_sys::Function,
 }
 
 impl WebWallet {
@@ -100,6 +163,155 @@ impl WebWallet {
     pub fn inner_mut(&mut self) -> &mut MemoryWallet<tonic_web_wasm_client::Client> {
         &mut self.inner
     }
+
+    /// Run a sync task on a new webworker and pass the events it sends back to `on_event` on this thread.
+    ///
+    /// Fails with [`Error::SyncAlreadyRunning`] if a sync or watch of the wallet is still running. Otherwise the
+    /// wallet's sync control is reset before the worker starts and the sync is cancelled if `signal` fires.
+    async fn spawn_sync_worker<T, Fut>(
+        &self,
+        name: &str,
+        signal: Option<web_sys::AbortSignal>,
+        task: T,
+        mut on_event: impl FnMut(SyncEvent) -> Result<(), Error>,
+    ) -> Result<(), Error>
+    where
+        T: FnOnce(MemoryWallet<Client>, ActiveSync, UnboundedSender<SyncEvent>) -> Fut
+            + Send
+            + 'static,
+        Fut: Future<Output = Result<(), Error>> + 'static,
+    {
+        assert!(!thread::is_web_worker_thread());
+
+        let control = self.inner.sync_control().clone();
+        // Claimed before resetting so a second call can't clear a pause or cancel meant for the running sync
+        let active = control.start()?;
+        control.reset();
+
+        // The listener is removed again once the worker has finished
+        let on_abort = Closure::<dyn FnMut()>::new({
+            let control = control.clone();
+            move || control.cancel()
+        });
+        if let Some(signal) = &signal {
+            if signal.aborted() {
+                return Err(Error::SyncCancelled);
+            }
+            signal.add_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref())?;
+        }
+
+        let db = self.inner.clone();
+        let (events_tx, mut events_rx) = unbounded_channel();
+
+        let sync_handler = thread::Builder::new()
+            .name(name.to_string())
+            .spawn_async(move || {
+                assert!(thread::is_web_worker_thread());
+                tracing::debug!(
+                    "Current num threads (wasm_thread) {}",
+                    rayon::current_num_threads()
+                );
+
+                let task = task(db, active, events_tx);
+                // Errors can hold JS values which cannot leave the worker
+                async move { task.await.map_err(|e| e.to_string()) }
+            })
+            .unwrap_throw()
+            .join_async();
+
+        // JS callbacks can only be called from the thread that created them, so events are forwarded from the
+        // worker until it drops its end of the channel
+        let mut callback_result = Ok(());
+        while let Some(event) = events_rx.recv().await {
+            if callback_result.is_ok() {
+                callback_result = on_event(event);
+            }
+        }
+        let result = sync_handler.await.unwrap();
+
+        if let Some(signal) = &signal {
+            signal
+                .remove_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref())?;
+        }
+
+        match result {
+            Ok(()) => callback_result,
+            Err(_) if control.is_cancelled() => Err(Error::SyncCancelled),
+            Err(e) => Err(Error::BackgroundTask(e)),
+        }
+    }
+
+    /// Prove and sign the transactions of `proposal` in a web worker
+    async fn authorize(
+        &self,
+        proposal: Proposal,
+        usk: UnifiedSpendingKey,
+    ) -> Result<JsValue, Error> {
+        assert!(!thread::is_web_worker_thread());
+
+        let db = self.inner.clone();
+
+        let sync_handler = thread::Builder::new()
+            .name("create_proposed_transaction".to_string())
+            .spawn_async(|| async move {
+                assert!(thread::is_web_worker_thread());
+                tracing::debug!(
+                    "Current num threads (wasm_thread) {}",
+                    rayon::current_num_threads()
+                );
+
+                let db = db;
+                // Errors can hold JS values which cannot leave the worker
+                db.create_proposed_transactions(proposal.into(), &usk)
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+            .unwrap_throw()
+            .join_async();
+        let txids = sync_handler.await.unwrap().map_err(Error::BackgroundTask)?;
+
+        Ok(serde_wasm_bindgen::to_value(&txids)?)
+    }
+
+    /// Change options given to a proposal, or the wallet's defaults if none were
+    fn change_options_from_js(&self, change: JsValue) -> Result<ChangeOptions, Error> {
+        if change.is_undefined() || change.is_null() {
+            Ok(self.inner.change_options())
+        } else {
+            Ok(serde_wasm_bindgen::from_value(change)?)
+        }
+    }
+
+    /// Propose shielding for every account over the auto-shield threshold and hand the proposals to the callback.
+    ///
+    /// Does nothing if a previous round is still running.
+    async fn run_auto_shield(&self) -> Result<(), Error> {
+        let Some(auto_shield) = self.auto_shield.borrow().clone() else {
+            return Ok(());
+        };
+        if self.auto_shielding.replace(true) {
+            tracing::debug!("Auto-shielding is already in progress");
+            return Ok(());
+        }
+        let result = self.shield_over_threshold(&auto_shield).await;
+        self.auto_shielding.set(false);
+        result
+    }
+
+    async fn shield_over_threshold(&self, auto_shield: &AutoShield) -> Result<(), Error> {
+        for (account_id, proposal) in self
+            .inner
+            .propose_auto_shielding(auto_shield.threshold)
+            .await?
+        {
+            auto_shield.on_proposal.call2(
+                &JsValue::NULL,
+                &JsValue::from(*account_id),
+                &Proposal::from(proposal).into(),
+            )?;
+        }
+        Ok(())
+    }
 }
 
 #[wasm_bindgen]
@@ -130,139 +342,822 @@ impl WebWallet {
             .map_err(|_| Error::InvalidMinConformations(min_confirmations))?;
         let client = Client::new(lightwalletd_url.to_string());
 
-        let db = match db_bytes {
-            Some(bytes) => {
-                tracing::info!(
-                    "Serialized db was provided to constructor. Attempting to deserialize"
-                );
-                postcard::from_bytes(&bytes)?
-            }
-            None => MemoryWalletDb::new(network, PRUNING_DEPTH),
-        };
+        let inner = match db_bytes {
+            Some(bytes) => {
+                tracing::info!(
+                    "Serialized db was provided to constructor. Attempting to deserialize"
+                );
+                Wallet::from_bytes(&bytes, client, network, min_confirmations)?
+            }
+            None => Wallet::new(
+                MemoryWalletDb::new(network, PRUNING_DEPTH),
+                client,
+                network,
+                min_confirmations,
+            )?,
+        };
+
+        Ok(Self {
+            inner,
+            auto_shield: Rc::new(RefCell::new(None)),
+            auto_shielding: Rc::new(Cell::new(false)),
+        })
+    }
+
+    /// Add a new account to the wallet using a given seed phrase
+    ///
+    /// # Arguments
+    ///
+    /// * `seed_phrase` - 24 word mnemonic seed phrase
+    /// * `account_hd_index` - [ZIP32](https://zips.z.cash/zip-0032) hierarchical deterministic index of the account
+    /// * `birthday` - Block height or `Date` at which the account was created. The sync logic will assume no funds are send or received prior to this height which can VERY significantly reduce sync time.
+    ///   A height is estimated conservatively from a date. If undefined, the birthday is set just before the current chain tip which is only correct for new accounts
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const wallet = new WebWallet("main", "https://zcash-mainnet.chainsafe.dev", 10);
+    /// const account_id = await wallet.create_account("...", 1, 2657762)
+    /// ```
+    pub async fn create_account(
+        &self,
+        seed_phrase: &str,
+        account_hd_index: u32,
+        birthday: JsValue,
+    ) -> Result<u32, Error> {
+        tracing::info!("Create account called");
+        self.inner
+            .create_account(seed_phrase, account_hd_index, birthday_from_js(birthday)?)
+            .await
+            .map(|id| *id)
+    }
+
+    /// Add a new account to the wallet by directly importing a Unified Full Viewing Key (UFVK)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - [ZIP316](https://zips.z.cash/zip-0316) encoded UFVK
+    /// * `birthday` - Block height or `Date` at which the account was created. The sync logic will assume no funds are send or received prior to this height which can VERY significantly reduce sync time.
+    ///   A height is estimated conservatively from a date. If undefined, the birthday is set just before the current chain tip which is only correct for new accounts
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const wallet = new WebWallet("main", "https://zcash-mainnet.chainsafe.dev", 10);
+    /// const account_id = await wallet.import_ufvk("...", 2657762)
+    /// ```
+    pub async fn create_account_ufvk(
+        &self,
+        encoded_ufvk: &str,
+        birthday: JsValue,
+    ) -> Result<u32, Error> {
+        let ufvk = UnifiedFullViewingKey::decode(&self.inner.network, encoded_ufvk)
+            .map_err(Error::KeyParse)?;
+
+        self.inner
+            .import_ufvk(&ufvk, birthday_from_js(birthday)?)
+            .await
+            .map(|id| *id)
+    }
+
+    /// Estimate a safe birthday height for an account created on a given date
+    ///
+    /// The estimate errs on the early side so no funds are missed. It uses the bundled checkpoints where possible, otherwise the times of a few blocks
+    /// around the date are fetched from lightwalletd.
+    ///
+    /// # Arguments
+    ///
+    /// * `date` - When the account was created
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const height = await wallet.estimate_birthday_height(new Date("2024-09-01"));
+    /// ```
+    pub async fn estimate_birthday_height(&self, date: js_sys::Date) -> Result<u32, Error> {
+        self.inner
+            .estimate_birthday_height(unix_seconds(&date)?)
+            .await
+    }
+
+    /// Add a new account to the wallet using a given seed phrase, with a birthday given by a tree state
+    ///
+    /// # Arguments
+    ///
+    /// * `seed_phrase` - 24 word mnemonic seed phrase
+    /// * `account_hd_index` - [ZIP32](https://zips.z.cash/zip-0032) hierarchical deterministic index of the account
+    /// * `tree_state` - Tree state as of the block before the account's birthday. An object with the fields `height`, `hash`, `time`, `saplingTree` and `orchardTree`
+    ///   as returned by lightwalletd's `GetTreeState`
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const tree_state = { height: 2657761, hash: "...", time: 1726000000, saplingTree: "...", orchardTree: "..." };
+    /// const account_id = await wallet.create_account_from_tree_state("...", 1, tree_state)
+    /// ```
+    pub async fn create_account_from_tree_state(
+        &self,
+        seed_phrase: &str,
+        account_hd_index: u32,
+        tree_state: JsValue,
+    ) -> Result<u32, Error> {
+        let checkpoint: Checkpoint = serde_wasm_bindgen::from_value(tree_state)?;
+        self.inner
+            .create_account_from_tree_state(
+                seed_phrase,
+                account_hd_index,
+                checkpoint.to_tree_state(&self.inner.network),
+            )
+            .await
+            .map(|id| *id)
+    }
+
+    /// Add a new account to the wallet by importing a Unified Full Viewing Key (UFVK), with a birthday given by a tree state
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - [ZIP316](https://zips.z.cash/zip-0316) encoded UFVK
+    /// * `tree_state` - Tree state as of the block before the account's birthday, as for `create_account_from_tree_state`
+    pub async fn create_account_ufvk_from_tree_state(
+        &self,
+        encoded_ufvk: &str,
+        tree_state: JsValue,
+    ) -> Result<u32, Error> {
+        let ufvk = UnifiedFullViewingKey::decode(&self.inner.network, encoded_ufvk)
+            .map_err(Error::KeyParse)?;
+        let checkpoint: Checkpoint = serde_wasm_bindgen::from_value(tree_state)?;
+
+        self.inner
+            .import_account_from_tree_state(
+                &ufvk,
+                checkpoint.to_tree_state(&self.inner.network),
+                AccountPurpose::ViewOnly,
+            )
+            .await
+            .map(|id| *id)
+    }
+
+    /// Whether the wallet may ask lightwalletd for the tree state at an account's birthday when no checkpoint is at or below it.
+    ///
+    /// Allowed by default, since the bundled checkpoints don't cover every birthday yet. The request reveals to lightwalletd when the account was
+    /// created, so wallets that add the checkpoints they need can turn it off. While off, adding an account with such a birthday fails instead.
+    pub fn allow_birthday_requests(&self, allow: bool) {
+        self.inner.allow_birthday_requests(allow);
+    }
+
+    /// Add checkpoints used to set account birthdays, on top of the ones bundled with the library
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoints_json` - JSON array of tree states in the format accepted by `create_account_from_tree_state`, e.g. the contents of a file
+    ///   generated by `scripts/update_checkpoints.sh`
+    pub async fn add_checkpoints(&self, checkpoints_json: &str) -> Result<(), Error> {
+        self.inner
+            .add_checkpoints(Checkpoints::from_json(checkpoints_json)?)
+            .await;
+        Ok(())
+    }
+
+    ///
+    /// Start a background sync task which will fetch and scan blocks from the connected lighwalletd server
+    ///
+    /// IMPORTANT: This will spawn a new webworker which will handle the sync task. The sync task will continue to run in the background until the sync process is complete.
+    /// During this time the main thread will not block. Other wallet methods can be used as normal, waiting at most for the current batch to be committed.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_progress` - (Optional) callback which is called on the main thread each time a range of blocks has been downloaded or scanned.
+    ///   It receives an object with the fields
+    ///     - `scan_range` - `[start, end)` heights of the range being processed
+    ///     - `scan_priority` - Priority of that range (e.g. "ChainTip", "Historic")
+    ///     - `blocks_downloaded` / `blocks_scanned` - Number of blocks processed since the sync started
+    ///     - `scan_progress` / `recovery_progress` - `{ numerator, denominator }` or undefined if not yet known
+    ///     - `estimated_seconds_remaining` - Extrapolated from the scan rate so far, undefined until the first batch is scanned
+    ///     - `chain_tip_height` - Chain tip as reported by lightwalletd
+    /// * `signal` - (Optional) AbortSignal. When it fires the sync stops at the next batch boundary, leaving the wallet database consistent, and the returned promise rejects
+    ///
+    /// Once scanning reaches the chain tip the wallet's transparent addresses are checked for funds, and the full transactions the wallet needs are fetched from lightwalletd, filling in memos, outgoing
+    /// recipients and transparent details that compact blocks leave out. Requests that fail are retried a few times and then left for the next sync.
+    /// If auto-shielding is enabled with `set_auto_shield`, shielding proposals are then made before the returned promise resolves.
+    ///
+    /// A running sync can also be paused and resumed with `pause_sync` and `resume_sync`. Only one sync or watch can run at a time, the returned
+    /// promise rejects straight away if the wallet is already being synced or watched.
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const controller = new AbortController();
+    /// await wallet.sync((progress) => {
+    ///   const { numerator, denominator } = progress.scan_progress ?? { numerator: 0, denominator: 1 };
+    ///   console.log(`Synced ${(100 * numerator / denominator).toFixed(1)}% of ${progress.chain_tip_height}`);
+    /// }, controller.signal);
+    /// ```
+    pub async fn sync(
+        &self,
+        on_progress: Option<js_sys::Function>,
+        signal: Option<web_sys::AbortSignal>,
+    ) -> Result<(), Error> {
+        self.spawn_sync_worker(
+            "sync",
+            signal,
+            |wallet, active, events| async move {
+                wallet
+                    .run_sync(active, |event| {
+                        // The receiver only goes away if the main thread stopped listening
+                        let _ = events.send(event);
+                    })
+                    .await
+            },
+            |event| {
+                if let (SyncEvent::Progress(progress), Some(on_progress)) = (event, &on_progress) {
+                    on_progress.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&progress)?)?;
+                }
+                Ok(())
+            },
+        )
+        .await?;
+        self.run_auto_shield().await
+    }
+
+    ///
+    /// Start following the chain in the background. The wallet first syncs to the chain tip and then keeps scanning new blocks as soon
+    /// as they are mined, rewinding and rescanning if a reorg is detected. This runs until it is stopped with the AbortSignal.
+    ///
+    /// Like `sync` this runs in a separate webworker and can be paused and resumed with `pause_sync` and `resume_sync`. It cannot be started while
+    /// a sync or another watch of the wallet is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_event` - Callback which is called on the main thread with each event. Events are objects with a `type` field which is one of
+    ///     - `"Progress"` - Same fields as the progress object passed to the `sync` callback
+    ///     - `"NewBlock"` - lightwalletd reported a new chain tip `height`
+    ///     - `"Reorg"` - The chain was reorganized at `detected_at`. Wallet data above `rewound_to` was discarded and is being rescanned
+    ///     - `"NotesDetected"` - Scanning `scan_range` found notes. Has `received_sapling_notes`, `received_orchard_notes`, `spent_sapling_notes` and `spent_orchard_notes` counts
+    ///     - `"TransparentUtxosDetected"` - Checking the wallet's transparent addresses found `new_utxos` new unspent outputs and `spent_utxos` that have been spent
+    ///     - `"Enhancement"` - `completed` of `total` full transactions requested by the wallet have been fetched, of which `failed` will be retried later
+    ///     - `"Synced"` - The wallet has caught up with the chain tip at `height`. If auto-shielding is enabled with `set_auto_shield`, shielding proposals are made after this
+    /// * `signal` - AbortSignal used to stop watching. The returned promise resolves once the wallet has stopped
+    /// * `poll_interval_ms` - (Optional) How often to check for new blocks. Defaults to 20 seconds
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const controller = new AbortController();
+    /// wallet.watch((event) => {
+    ///   if (event.type === "NotesDetected") {
+    ///     refreshBalance();
+    ///   }
+    /// }, controller.signal);
+    /// // later
+    /// controller.abort();
+    /// ```
+    pub async fn watch(
+        &self,
+        on_event: js_sys::Function,
+        signal: web_sys::AbortSignal,
+        poll_interval_ms: Option<u32>,
+    ) -> Result<(), Error> {
+        let poll_interval = poll_interval_ms
+            .map(|ms| Duration::from_millis(ms.into()))
+            .unwrap_or(DEFAULT_POLL_INTERVAL);
+
+        self.spawn_sync_worker(
+            "watch",
+            Some(signal),
+            move |wallet, active, events| async move {
+                wallet
+                    .run_watch(active, poll_interval, |event| {
+                        let _ = events.send(event);
+                    })
+                    .await
+            },
+            |event| {
+                on_event.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&event)?)?;
+                if let SyncEvent::Synced { .. } = event {
+                    // Proposing can take a while, so the wallet keeps following the chain meanwhile. A round that
+                    // is still running when the next tip is synced makes the new one a no-op.
+                    let wallet = self.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(e) = wallet.run_auto_shield().await {
+                            tracing::warn!("Auto-shielding failed: {}", e);
+                        }
+                    });
+                }
+                Ok(())
+            },
+        )
+        .await
+    }
+
+    /// Pause a running sync once the batch currently being scanned has been committed.
+    ///
+    /// This frees up the CPU and network for other work such as proving.
+    pub fn pause_sync(&self) {
+        self.inner.sync_control().pause();
+    }
+
+    /// Resume a sync previously paused with `pause_sync`
+    pub fn resume_sync(&self) {
+        self.inner.sync_control().resume();
+    }
+
+    /// Keep downloaded compact blocks in IndexedDB until they have been scanned.
+    ///
+    /// Blocks cached by a previous session are read back as the next sync reaches them instead of being downloaded again. Call this once, before the
+    /// first sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_name` - (Optional) name of the IndexedDB database to use. Defaults to "webz-block-cache". Blocks are kept per network, so wallets for
+    ///   different networks can share a database
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const wallet = new WebWallet("main", "https://zcash-mainnet.chainsafe.dev", 10);
+    /// await wallet.enable_persistent_block_cache();
+    /// await wallet.sync();
+    /// ```
+    pub async fn enable_persistent_block_cache(
+        &self,
+        db_name: Option<String>,
+    ) -> Result<(), Error> {
+        let db_name = db_name.unwrap_or_else(|| DEFAULT_INDEXED_DB_NAME.to_string());
+        Ok(self
+            .inner
+            .block_cache()
+            .persist_to_indexed_db(&db_name, self.inner.network)
+            .await?)
+    }
+
+    /// Get the balances of every account along with the scan progress of the wallet
+    ///
+    /// Each account balance breaks the Sapling and Orchard pools down into `spendable`, `change_pending_confirmation` and
+    /// `value_pending_spendability`, and the transparent pool into `mature` and `immature`. Right after sending, the change
+    /// from the spent notes shows up as pending until the transaction is mined, so the spendable balance drops by more than
+    /// the amount sent while `total` does not.
+    ///
+    /// Returns undefined if the wallet doesn't know the chain tip yet.
+    pub async fn get_wallet_summary(&self) -> Result<Option<WalletSummary>, Error> {
+        let Some(summary) = self.inner.get_wallet_summary().await? else {
+            return Ok(None);
+        };
+        let maturity = self.inner.transparent_maturity().await?;
+        let mut summary = WalletSummary::from(summary);
+        for (account_id, balance) in &mut summary.account_balances {
+            if let Some(maturity) = maturity.get(&AccountId::from(*account_id)) {
+                balance.transparent = balance.transparent.with_maturity(*maturity);
+            }
+        }
+        Ok(Some(summary))
+    }
+
+    /// Create a new transaction proposal to send funds to a given address
+    ///
+    /// Not this does NOT sign, generate a proof, or send the transaction. It will only craft the proposal which designates how notes from this account can be spent to realize the requested transfer.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `to_address` - [ZIP316](https://zips.z.cash/zip-0316) encoded address to send funds to
+    /// * `value` - Amount to send in Zatoshis (1 ZEC = 100_000_000 Zatoshis)
+    /// * `change` - (Optional) Change options for this proposal, with the same fields as `set_change_options`. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend, as an object with a `type` of
+    ///     - `"Greedy"` - The wallet's own order. This is the default
+    ///     - `"MinimizeInputs"` - As few notes as possible
+    ///     - `"SinglePool"` - Notes from only Sapling or only Orchard, so no value crosses between the pools
+    ///     - `"OldestFirst"` - The notes mined longest ago first
+    ///     - `"PreferConfirmed"` - Notes with at least `confirmations` confirmations first
+    ///
+    /// # Returns
+    ///
+    /// A proposal object which can be inspected and later used to generate a valid transaction
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const proposal = await wallet.propose_transfer(1, "u18rakpts0de589sx9dkamcjms3apruqqax9k2s6e7zjxx9vv5kc67pks2trg9d3nrgd5acu8w8arzjjuepakjx38dyxl6ahd948w0mhdt9jxqsntan6px3ysz80s04a87pheg2mqvlzpehrgup7568nfd6ez23xd69ley7802dfvplnfn7c07vlyumcnfjul4pvv630ac336rjhjyak5", 100000000);
+    /// ```
+    pub async fn propose_transfer(
+        &self,
+        account_id: u32,
+        to_address: String,
+        value: u64,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<Proposal, Error> {
+        let to_address = ZcashAddress::try_from_encoded(&to_address)?;
+        let request = zip321::TransactionRequest::new(vec![zip321::Payment::without_memo(
+            to_address,
+            NonNegativeAmount::from_u64(value)?,
+        )])?;
+        let proposal = self
+            .inner
+            .propose_request_with(
+                AccountId::from(account_id),
+                request,
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
+            .await?;
+        Ok(proposal.into())
+    }
+
+    /// Create a new transaction proposal that makes every payment in a [ZIP321](https://zips.z.cash/zip-0321) transaction request
+    ///
+    /// Unlike `propose_transfer` this can pay several recipients at once and attach a memo to each payment.
+    /// Labels and messages in the request are for display only and are not included in the transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `request` - The transaction request. Payments with a memo must be to shielded addresses
+    /// * `change` - (Optional) Change options for this proposal. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend. Same as for `propose_transfer`
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const request = new TransactionRequest([
+    ///   new PaymentRequest("u1...", 100000, new TextEncoder().encode("order 1234"), "Coffee", null, {}),
+    ///   PaymentRequest.simple_payment("t1...", 50000),
+    /// ]);
+    /// const proposal = await wallet.propose_request(0, request);
+    /// ```
+    pub async fn propose_request(
+        &self,
+        account_id: u32,
+        request: TransactionRequest,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<Proposal, Error> {
+        let proposal = self
+            .inner
+            .propose_request_with(
+                AccountId::from(account_id),
+                request.into(),
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
+            .await?;
+        Ok(proposal.into())
+    }
+
+    /// Create a new transaction proposal from a [ZIP321](https://zips.z.cash/zip-0321) "zcash:" payment URI
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `uri` - The payment URI, e.g. scanned from a QR code
+    /// * `change` - (Optional) Change options for this proposal. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend. Same as for `propose_transfer`
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const proposal = await wallet.propose_uri(0, "zcash:u1...?amount=0.001&memo=VGhhbmtzIQ");
+    /// ```
+    pub async fn propose_uri(
+        &self,
+        account_id: u32,
+        uri: &str,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<Proposal, Error> {
+        let request = zip321::TransactionRequest::from_uri(uri)?;
+        let proposal = self
+            .inner
+            .propose_request_with(
+                AccountId::from(account_id),
+                request,
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
+            .await?;
+        Ok(proposal.into())
+    }
+
+    /// Create a transaction proposal that spends exactly the chosen notes, for coin control
+    ///
+    /// All of the notes are spent and no others. Whatever is left after the payments and the fee is returned as change.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account in this wallet the notes belong to
+    /// * `request` - The payments to make
+    /// * `notes` - Array of the notes to spend, each an object with the `txid`, `pool` and `output_index` fields that `list_unspent` returns.
+    ///   Every note must be unspent and have enough confirmations to be spent
+    /// * `change` - (Optional) Change options for this proposal. Defaults to the wallet's change options
+    ///
+    /// The returned promise rejects with an error naming the note if one can't be spent, or stating the shortfall if the notes don't cover the payments and fee.
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const notes = (await wallet.list_unspent(0)).filter(note => note.spendable && note.pool === "Orchard").slice(0, 2);
+    /// const proposal = await wallet.propose_from_notes(0, request, notes);
+    /// ```
+    pub async fn propose_from_notes(
+        &self,
+        account_id: u32,
+        request: TransactionRequest,
+        notes: JsValue,
+        change: JsValue,
+    ) -> Result<Proposal, Error> {
+        let notes = serde_wasm_bindgen::from_value::<Vec<OutputRef>>(notes)?
+            .iter()
+            .map(OutputRef::note_id)
+            .collect::<Result<_, _>>()?;
+        let proposal = self
+            .inner
+            .propose_request_from_notes(
+                AccountId::from(account_id),
+                request.into(),
+                notes,
+                &self.change_options_from_js(change)?,
+            )
+            .await?;
+        Ok(proposal.into())
+    }
+
+    /// Create a proposal to shield exactly the chosen transparent outputs, for coin control
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account in this wallet the outputs belong to
+    /// * `utxos` - Array of the transparent outputs to shield, each an object with the `txid` and `output_index` fields that `list_unspent` returns.
+    ///   Every output must be unspent and have enough confirmations to be spent
+    /// * `change` - (Optional) Change options for this proposal. Defaults to the wallet's change options
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const utxos = (await wallet.list_unspent(0)).filter(output => output.pool === "Transparent" && output.spendable);
+    /// const proposal = await wallet.propose_shielding_from_utxos(0, utxos);
+    /// ```
+    pub async fn propose_shielding_from_utxos(
+        &self,
+        account_id: u32,
+        utxos: JsValue,
+        change: JsValue,
+    ) -> Result<Proposal, Error> {
+        let utxos = serde_wasm_bindgen::from_value::<Vec<OutputRef>>(utxos)?
+            .iter()
+            .map(OutputRef::outpoint)
+            .collect::<Result<_, _>>()?;
+        let proposal = self
+            .inner
+            .propose_shielding_from_utxos(
+                AccountId::from(account_id),
+                utxos,
+                &self.change_options_from_js(change)?,
+            )
+            .await?;
+        Ok(proposal.into())
+    }
+
+    /// Restore a proposal from the bytes returned by its `to_bytes` method
+    ///
+    /// The proposal may have been made in another session or by another wallet instance with the same accounts, e.g. an online one
+    /// preparing transactions for an offline one to authorize. Every note and transparent output it spends must still be unspent in this wallet.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The proposal encoded in the protobuf proposal format
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const bytes = proposal.to_bytes();
+    /// // ...later, or on another device
+    /// const restored = await wallet.proposal_from_bytes(bytes);
+    /// ```
+    pub async fn proposal_from_bytes(&self, bytes: &[u8]) -> Result<Proposal, Error> {
+        let proposal = self.inner.decode_proposal(bytes).await?;
+        Ok(proposal.into())
+    }
+
+    /// Set how the change of proposed transactions is paid back when a proposal doesn't give its own change options
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Object with any of
+    ///     - `pool` - "Sapling" or "Orchard". If not set, change stays in the pool the spent notes came from
+    ///     - `fallback_pool` - Pool change goes to when `pool` can't be used or the spent notes don't determine one. Defaults to "Orchard"
+    ///     - `split_count` - Number of notes to split change into so that several transactions can be sent without waiting for the change
+    ///       of the previous one to be mined. Fewer are made if the change is too small. Defaults to 1
+    ///     - `dust_threshold` - Change below this many Zatoshis is dust. Defaults to the ZIP-317 marginal fee of 5000
+    ///     - `dust_handling` - "Reject" (the default) to fail the proposal, "AllowDustChange" to create the dust output anyway
+    ///       or "AddDustToFee" to pay it to the miner
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// wallet.set_change_options({ pool: "Orchard", split_count: 4 });
+    /// ```
+    pub fn set_change_options(&self, options: JsValue) -> Result<(), Error> {
+        let options = serde_wasm_bindgen::from_value(options)?;
+        self.inner.set_change_options(options);
+        Ok(())
+    }
 
-        Ok(Self {
-            inner: Wallet::new(db, client, network, min_confirmations)?,
-        })
+    /// Get the change options used by proposals that don't give their own
+    pub fn get_change_options(&self) -> Result<JsValue, Error> {
+        Ok(serde_wasm_bindgen::to_value(&self.inner.change_options())?)
     }
 
-    /// Add a new account to the wallet using a given seed phrase
+    /// Estimate the fee in Zatoshis of making every payment in a transaction request, without building any proofs
+    ///
+    /// The estimate comes from the same proposal `propose_request` would make with the same options, so it is exact
+    /// as long as the wallet's notes don't change before proposing.
     ///
     /// # Arguments
     ///
-    /// * `seed_phrase` - 24 word mnemonic seed phrase
-    /// * `account_hd_index` - [ZIP32](https://zips.z.cash/zip-0032) hierarchical deterministic index of the account
-    /// * `birthday_height` - Block height at which the account was created. The sync logic will assume no funds are send or received prior to this height which can VERY significantly reduce sync time
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `request` - The transaction request to estimate the fee of
+    /// * `change` - (Optional) Change options for the proposal. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend. Same as for `propose_transfer`
     ///
     /// # Examples
     ///
     /// ```javascript
-    /// const wallet = new WebWallet("main", "https://zcash-mainnet.chainsafe.dev", 10);
-    /// const account_id = await wallet.create_account("...", 1, 2657762)
+    /// const fee = await wallet.estimate_fee(0, new TransactionRequest([PaymentRequest.simple_payment("u1...", 100000)]));
     /// ```
-    pub async fn create_account(
+    pub async fn estimate_fee(
         &self,
-        seed_phrase: &str,
-        account_hd_index: u32,
-        birthday_height: Option<u32>,
-    ) -> Result<u32, Error> {
-        tracing::info!("Create account called");
+        account_id: u32,
+        request: TransactionRequest,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<u64, Error> {
         self.inner
-            .create_account(seed_phrase, account_hd_index, birthday_height)
+            .estimate_fee_with(
+                AccountId::from(account_id),
+                request.into(),
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
             .await
-            .map(|id| *id)
     }
 
-    /// Add a new account to the wallet by directly importing a Unified Full Viewing Key (UFVK)
+    /// Get the largest amount in Zatoshis an account can send to an address once the fee is paid
+    ///
+    /// Proposing a transfer of this amount to the same address with the same options spends the account's whole
+    /// spendable balance.
     ///
     /// # Arguments
     ///
-    /// * `key` - [ZIP316](https://zips.z.cash/zip-0316) encoded UFVK
-    /// * `birthday_height` - Block height at which the account was created. The sync logic will assume no funds are send or received prior to this height which can VERY significantly reduce sync time
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `to_address` - [ZIP316](https://zips.z.cash/zip-0316) encoded address to send funds to
+    /// * `change` - (Optional) Change options for the proposal. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend. Same as for `propose_transfer`
     ///
     /// # Examples
     ///
     /// ```javascript
-    /// const wallet = new WebWallet("main", "https://zcash-mainnet.chainsafe.dev", 10);
-    /// const account_id = await wallet.import_ufvk("...", 2657762)
+    /// const max = await wallet.max_sendable(0, "u1...");
+    /// const proposal = await wallet.propose_transfer(0, "u1...", max);
     /// ```
-    pub async fn create_account_ufvk(
+    pub async fn max_sendable(
         &self,
-        encoded_ufvk: &str,
-        birthday_height: Option<u32>,
-    ) -> Result<u32, Error> {
-        let ufvk = UnifiedFullViewingKey::decode(&self.inner.network, encoded_ufvk)
-            .map_err(Error::KeyParse)?;
-
+        account_id: u32,
+        to_address: String,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<u64, Error> {
+        let to_address = ZcashAddress::try_from_encoded(&to_address)?;
+        let request = zip321::TransactionRequest::new(vec![zip321::Payment::without_memo(
+            to_address,
+            NonNegativeAmount::ZERO,
+        )])?;
         self.inner
-            .import_ufvk(&ufvk, birthday_height)
+            .max_sendable_with(
+                AccountId::from(account_id),
+                request,
+                0,
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
             .await
-            .map(|id| *id)
     }
 
+    /// Get the largest amount in Zatoshis one payment of a transaction request can have while the account can still
+    /// make the other payments and pay the fee
     ///
-    /// Start a background sync task which will fetch and scan blocks from the connected lighwalletd server
-    ///
-    /// IMPORTANT: This will spawn a new webworker which will handle the sync task. The sync task will continue to run in the background until the sync process is complete.
-    /// During this time the main thread will not block but certain wallet methods may temporarily block while the wallet is being written to during the sync.
+    /// # Arguments
     ///
-    pub async fn sync(&self) -> Result<(), Error> {
-        assert!(!thread::is_web_worker_thread());
-
-        let db = self.inner.clone();
-
-        let sync_handler = thread::Builder::new()
-            .name("sync".to_string())
-            .spawn_async(|| async {
-                assert!(thread::is_web_worker_thread());
-                tracing::debug!(
-                    "Current num threads (wasm_thread) {}",
-                    rayon::current_num_threads()
-                );
-
-                let db = db;
-                db.sync().await.unwrap_throw();
-            })
-            .unwrap_throw()
-            .join_async();
-        sync_handler.await.unwrap();
-        Ok(())
-    }
-
-    pub async fn get_wallet_summary(&self) -> Result<Option<WalletSummary>, Error> {
-        Ok(self.inner.get_wallet_summary().await?.map(Into::into))
+    /// * `account_id` - The ID of the account in this wallet to send funds from
+    /// * `request` - The transaction request. The amount of the payment at `payment_index` is ignored
+    /// * `payment_index` - Index of the payment to maximize
+    /// * `change` - (Optional) Change options for the proposal. Defaults to the wallet's change options
+    /// * `selection` - (Optional) How to choose the notes to spend. Same as for `propose_transfer`
+    pub async fn max_sendable_for_request(
+        &self,
+        account_id: u32,
+        request: TransactionRequest,
+        payment_index: u32,
+        change: JsValue,
+        selection: JsValue,
+    ) -> Result<u64, Error> {
+        self.inner
+            .max_sendable_with(
+                AccountId::from(account_id),
+                request.into(),
+                payment_index as usize,
+                &self.change_options_from_js(change)?,
+                selection_from_js(selection)?,
+            )
+            .await
     }
 
-    /// Create a new transaction proposal to send funds to a given address
+    /// Create a proposal to move the confirmed transparent funds of an account into its shielded Orchard pool
     ///
-    /// Not this does NOT sign, generate a proof, or send the transaction. It will only craft the proposal which designates how notes from this account can be spent to realize the requested transfer.
+    /// The proposal is authorized and sent the same way as a transfer. The shielded funds go to the account's own
+    /// internal address, less the fee.
     ///
     /// # Arguments
     ///
-    /// * `account_id` - The ID of the account in this wallet to send funds from
-    /// * `to_address` - [ZIP316](https://zips.z.cash/zip-0316) encoded address to send funds to
-    /// * `value` - Amount to send in Zatoshis (1 ZEC = 100_000_000 Zatoshis)
+    /// * `account_id` - The ID of the account in this wallet to shield funds for
+    /// * `threshold` - Minimum amount in Zatoshis worth shielding
+    /// * `addresses` - (Optional) Transparent addresses of the account to shield funds from. Defaults to all of them
     ///
     /// # Returns
     ///
-    /// A proposal object which can be inspected and later used to generate a valid transaction
+    /// A proposal, or undefined if the selected addresses hold less than `threshold` with enough confirmations
     ///
     /// # Examples
     ///
     /// ```javascript
-    /// const proposal = await wallet.propose_transfer(1, "u18rakpts0de589sx9dkamcjms3apruqqax9k2s6e7zjxx9vv5kc67pks2trg9d3nrgd5acu8w8arzjjuepakjx38dyxl6ahd948w0mhdt9jxqsntan6px3ysz80s04a87pheg2mqvlzpehrgup7568nfd6ez23xd69ley7802dfvplnfn7c07vlyumcnfjul4pvv630ac336rjhjyak5", 100000000);
+    /// const proposal = await wallet.propose_shielding(0, 100000);
+    /// if (proposal) {
+    ///   const txids = await wallet.create_proposed_transactions(proposal, "...", 0);
+    ///   await wallet.send_authorized_transactions(txids);
+    /// }
     /// ```
-    pub async fn propose_transfer(
+    pub async fn propose_shielding(
         &self,
         account_id: u32,
-        to_address: String,
-        value: u64,
-    ) -> Result<Proposal, Error> {
-        let to_address = ZcashAddress::try_from_encoded(&to_address)?;
+        threshold: u64,
+        addresses: Option<Vec<String>>,
+    ) -> Result<Option<Proposal>, Error> {
+        let addresses = addresses
+            .map(|addresses| {
+                addresses
+                    .into_iter()
+                    .map(
+                        |address| match Address::decode(&self.inner.network, &address) {
+                            Some(Address::Transparent(address)) => Ok(address),
+                            _ => Err(Error::UnknownTransparentAddress(address)),
+                        },
+                    )
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
         let proposal = self
             .inner
-            .propose_transfer(AccountId::from(account_id), to_address, value)
+            .propose_shielding(AccountId::from(account_id), threshold, addresses)
             .await?;
-        Ok(proposal.into())
+        Ok(proposal.map(Into::into))
+    }
+
+    /// Propose shielding automatically each time a sync or watch pass reaches the chain tip
+    ///
+    /// After each pass, every account with at least `threshold` Zatoshis of confirmed transparent funds gets a shielding
+    /// proposal, which is passed to `on_proposal` along with the account ID. Nothing is authorized or sent without the
+    /// caller doing so.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - Minimum transparent balance in Zatoshis to propose shielding for. Undefined turns auto-shielding off
+    /// * `on_proposal` - Called with `(account_id, proposal)` for each proposal. Required when `threshold` is given
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// wallet.set_auto_shield(100000, (account_id, proposal) => {
+    ///   pendingShields.push({ account_id, proposal });
+    /// });
+    /// await wallet.sync();
+    /// ```
+    pub fn set_auto_shield(
+        &self,
+        threshold: Option<u64>,
+        on_proposal: Option<js_sys::Function>,
+    ) -> Result<(), Error> {
+        let auto_shield = match (threshold, on_proposal) {
+            (Some(threshold), Some(on_proposal)) => Some(AutoShield {
+                threshold,
+                on_proposal,
+            }),
+            (Some(_), None) => {
+                return Err(Error::Generic(
+                    "An on_proposal callback is required to enable auto-shielding".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+        *self.auto_shield.borrow_mut() = auto_shield;
+        Ok(())
     }
 
     /// Generate a valid Zcash transaction from a given proposal
@@ -272,8 +1167,10 @@ impl WebWallet {
     /// # Arguments
     ///
     /// * `proposal` - A proposal object generated by `propose_transfer`
-    /// * `seed_phrase` - 24 word mnemonic seed phrase. This MUST correspond to the accountID used when creating the proposal.
-    /// * `account_hd_index` - [ZIP32](https://zips.z.cash/zip-0032) hierarchical deterministic index of the account. This MUST correspond to the accountID used when creating the proposal.
+    /// * `seed_phrase` - 24 word mnemonic seed phrase of the account the proposal spends from
+    /// * `account_hd_index` - [ZIP32](https://zips.z.cash/zip-0032) hierarchical deterministic index of that account.
+    ///   The spending key derived from these is checked against the account's viewing key before proving starts and the promise rejects if it doesn't match.
+    ///   Use `create_proposed_transactions_with_usk` to pass the spending key itself instead.
     ///
     /// # Returns
     ///
@@ -292,32 +1189,39 @@ impl WebWallet {
         seed_phrase: &str,
         account_hd_index: u32,
     ) -> Result<JsValue, Error> {
-        assert!(!thread::is_web_worker_thread());
-
         let usk = usk_from_seed_str(seed_phrase, account_hd_index, &self.inner.network)?;
-        let db = self.inner.clone();
-
-        let sync_handler = thread::Builder::new()
-            .name("create_proposed_transaction".to_string())
-            .spawn_async(|| async move {
-                assert!(thread::is_web_worker_thread());
-                tracing::debug!(
-                    "Current num threads (wasm_thread) {}",
-                    rayon::current_num_threads()
-                );
-
-                let db = db;
-                let txids = db
-                    .create_proposed_transactions(proposal.into(), &usk)
-                    .await
-                    .unwrap_throw();
-                return txids;
-            })
-            .unwrap_throw()
-            .join_async();
-        let txids = sync_handler.await.unwrap();
+        self.authorize(proposal, usk).await
+    }
 
-        Ok(serde_wasm_bindgen::to_value(&txids)?)
+    /// Generate a valid Zcash transaction from a given proposal, signing with a unified spending key instead of a seed phrase
+    ///
+    /// IMPORTANT: This will spawn a new webworker which will handle the proving task which may take 10s of seconds
+    ///
+    /// # Arguments
+    ///
+    /// * `proposal` - A proposal object generated by `propose_transfer`
+    /// * `usk` - The encoded unified spending key of the account the proposal spends from, e.g. from `UnifiedSpendingKey.to_bytes` in webz-keys.
+    ///   The key is checked against the account's viewing key before proving starts and the promise rejects if it doesn't match.
+    ///
+    /// # Returns
+    ///
+    /// A list of transaction IDs which can be used to track the status of the transaction on the network.
+    /// The transactions themselves are stored within the wallet
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const usk = new UnifiedSpendingKey("main", seed, 0);
+    /// const txids = await wallet.create_proposed_transactions_with_usk(proposal, usk.to_bytes());
+    /// ```
+    pub async fn create_proposed_transactions_with_usk(
+        &self,
+        proposal: Proposal,
+        usk: &[u8],
+    ) -> Result<JsValue, Error> {
+        let usk = UnifiedSpendingKey::from_bytes(Era::Orchard, usk)
+            .map_err(|e| Error::KeyDecoding(format!("{:?}", e)))?;
+        self.authorize(proposal, usk).await
     }
 
     /// Serialize the internal wallet database to bytes
@@ -327,7 +1231,7 @@ impl WebWallet {
     ///
     /// # Returns
     ///
-    /// A postcard encoded byte array of the wallet database
+    /// A postcard encoded byte array of the wallet database, followed by the index the transaction history is built from
     ///
     pub async fn db_to_bytes(&self) -> Result<Box<[u8]>, Error> {
         let bytes = self.inner.db_to_bytes().await?;
@@ -369,6 +1273,121 @@ impl WebWallet {
         }
     }
 
+    /// Get a page of the transaction history of an account, newest first with unmined transactions at the top
+    ///
+    /// Only the transactions on the page are decrypted, unless `memo_contains` is set. Block times the wallet didn't
+    /// record while scanning are fetched from lightwalletd.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account to get the history of
+    /// * `filter` - Optional object with any of `from_height`, `to_height`, `from_time`, `to_time` (unix seconds),
+    ///   `direction` ("Received", "Sent" or "SelfTransfer"), `min_value` (Zatoshis) and `memo_contains`
+    /// * `offset` - Number of matching entries to skip
+    /// * `limit` - Maximum number of entries to return
+    ///
+    /// # Returns
+    ///
+    /// An object with the `entries` on this page and the `total` number of matching entries
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const { entries, total } = await wallet.get_transaction_history(0, { direction: "Sent" }, 0, 20);
+    /// ```
+    pub async fn get_transaction_history(
+        &self,
+        account_id: u32,
+        filter: JsValue,
+        offset: Option<u32>,
+        limit: Option<u32>,
+    ) -> Result<JsValue, Error> {
+        let filter: HistoryFilter = if filter.is_undefined() || filter.is_null() {
+            HistoryFilter::default()
+        } else {
+            serde_wasm_bindgen::from_value(filter)?
+        };
+        let page = self
+            .inner
+            .get_transaction_history(
+                account_id.into(),
+                &filter,
+                offset.unwrap_or(0) as usize,
+                limit.map(|limit| limit as usize),
+            )
+            .await?;
+        Ok(serde_wasm_bindgen::to_value(&page)?)
+    }
+
+    /// List the unspent notes that make up an account's balance
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account to list the notes of
+    ///
+    /// # Returns
+    ///
+    /// A list of notes, each with its `txid`, `pool`, `output_index`, `value` in Zatoshis, `mined_height`, `confirmations`,
+    /// whether it is `spendable` now under the wallet's min_confirmations, whether it `is_change` and its `memo` if known.
+    /// Notes which aren't spendable yet are still waiting for confirmations.
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const notes = await wallet.list_unspent(0);
+    /// const pending = notes.filter(note => !note.spendable).reduce((sum, note) => sum + note.value, 0);
+    /// ```
+    pub async fn list_unspent(&self, account_id: u32) -> Result<JsValue, Error> {
+        let outputs = self.inner.list_unspent(account_id.into()).await?;
+        Ok(serde_wasm_bindgen::to_value(&outputs)?)
+    }
+
+    /// Get the decrypted memos of every note an account received or sent, grouped by transaction
+    ///
+    /// Compact blocks don't include memos so the full transactions are fetched from lightwalletd the first time
+    /// this is called for them. They are stored in the wallet after that.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account to get the memos of
+    ///
+    /// # Returns
+    ///
+    /// A list of `{ txid, outputs }`, newest transaction first. Each output has its `pool`, `output_index`,
+    /// `direction` ("Received", "Sent" or "Internal"), `value` and a `memo` whose `type` is "Empty", "Text",
+    /// "Arbitrary" or "Future". Text memos have a `text` field and the others have hex encoded `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```javascript
+    /// const memos = await wallet.get_memos(0);
+    /// const order_payments = memos.filter(tx => tx.outputs.some(o => o.memo.type === "Text" && o.memo.text.startsWith("order:")));
+    /// ```
+    pub async fn get_memos(&self, account_id: u32) -> Result<JsValue, Error> {
+        let memos = self.inner.get_account_memos(account_id.into()).await?;
+        Ok(serde_wasm_bindgen::to_value(&memos)?)
+    }
+
+    /// Get the decrypted memos of a single transaction that an account can see
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The ID of the account to decrypt the transaction with
+    /// * `txid` - Hex encoded transaction ID, in the byte order used by block explorers
+    ///
+    pub async fn get_transaction_memos(
+        &self,
+        account_id: u32,
+        txid: &str,
+    ) -> Result<JsValue, Error> {
+        let txid = parse_txid(txid)?;
+        let memos = self
+            .inner
+            .get_transaction_memos(account_id.into(), txid)
+            .await?;
+        Ok(serde_wasm_bindgen::to_value(&memos)?)
+    }
+
     ///////////////////////////////////////////////////////////////////////////////////////
     // lightwalletd gRPC methods
     ///////////////////////////////////////////////////////////////////////////////////////
@@ -391,7 +1410,8 @@ pub struct WalletSummary {
     account_balances: Vec<(u32, AccountBalance)>,
     pub chain_tip_height: u32,
     pub fully_scanned_height: u32,
-    // scan_progress: Option<Ratio<u64>>,
+    scan_progress: Option<ProgressRatio>,
+    recovery_progress: Option<ProgressRatio>,
     pub next_sapling_subtree_index: u64,
     pub next_orchard_subtree_index: u64,
 }
@@ -402,13 +1422,89 @@ impl WalletSummary {
     pub fn account_balances(&self) -> JsValue {
         serde_wasm_bindgen::to_value(&self.account_balances).unwrap()
     }
+
+    /// Fraction of the wallet's blocks of interest that have been scanned, as `{ numerator, denominator }`
+    #[wasm_bindgen(getter)]
+    pub fn scan_progress(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.scan_progress).unwrap()
+    }
+
+    /// Fraction of the blocks between the wallet birthday and the recovery height that have been scanned,
+    /// as `{ numerator, denominator }`. Undefined once the wallet has recovered.
+    #[wasm_bindgen(getter)]
+    pub fn recovery_progress(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.recovery_progress).unwrap()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccountBalance {
+    /// Spendable Sapling value. Same as `sapling.spendable`
     pub sapling_balance: u64,
+    /// Spendable Orchard value. Same as `orchard.spendable`
     pub orchard_balance: u64,
+    /// Total transparent value. Same as `transparent.total`
     pub unshielded_balance: u64,
+    /// Total value across all pools, including value that isn't spendable yet
+    pub total: u64,
+    pub sapling: PoolBalance,
+    pub orchard: PoolBalance,
+    pub transparent: TransparentBalance,
+}
+
+/// The balance of a shielded pool, split by whether it can be spent yet
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolBalance {
+    pub total: u64,
+    /// Value that can be spent now
+    pub spendable: u64,
+    /// Change from the account's own transactions that is waiting for confirmations. This is why the balance appears
+    /// to drop by more than the amount sent until the transaction is mined.
+    pub change_pending_confirmation: u64,
+    /// Other received value that is waiting for confirmations or for the wallet to finish scanning
+    pub value_pending_spendability: u64,
+}
+
+impl From<&Balance> for PoolBalance {
+    fn from(balance: &Balance) -> Self {
+        PoolBalance {
+            total: balance.total().into(),
+            spendable: balance.spendable_value().into(),
+            change_pending_confirmation: balance.change_pending_confirmation().into(),
+            value_pending_spendability: balance.value_pending_spendability().into(),
+        }
+    }
+}
+
+/// The transparent balance, split by whether it can be shielded yet
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransparentBalance {
+    pub total: u64,
+    /// Value that can be shielded now: it has the wallet's `min_confirmations` and isn't an immature coinbase output
+    pub mature: u64,
+    /// Coinbase outputs that can't be spent until they have 100 confirmations. Whatever is in neither `mature` nor
+    /// `immature` is waiting for confirmations.
+    pub immature: u64,
+}
+
+impl TransparentBalance {
+    fn with_maturity(self, maturity: TransparentMaturity) -> Self {
+        TransparentBalance {
+            total: self.total,
+            mature: maturity.spendable.min(self.total),
+            immature: maturity.immature.min(self.total),
+        }
+    }
+}
+
+impl From<&Balance> for TransparentBalance {
+    fn from(balance: &Balance) -> Self {
+        TransparentBalance {
+            total: balance.total().into(),
+            mature: balance.spendable_value().into(),
+            immature: 0,
+        }
+    }
 }
 
 impl From<zcash_client_backend::data_api::AccountBalance> for AccountBalance {
@@ -416,7 +1512,11 @@ impl From<zcash_client_backend::data_api::AccountBalance> for AccountBalance {
         AccountBalance {
             sapling_balance: balance.sapling_balance().spendable_value().into(),
             orchard_balance: balance.orchard_balance().spendable_value().into(),
-            unshielded_balance: balance.unshielded().into(),
+            unshielded_balance: balance.unshielded_balance().total().into(),
+            total: balance.total().into(),
+            sapling: balance.sapling_balance().into(),
+            orchard: balance.orchard_balance().into(),
+            transparent: balance.unshielded_balance().into(),
         }
     }
 }
@@ -438,8 +1538,108 @@ where
             account_balances,
             chain_tip_height: summary.chain_tip_height().into(),
             fully_scanned_height: summary.fully_scanned_height().into(),
+            scan_progress: summary.scan_progress().as_ref().map(Into::into),
+            recovery_progress: summary.recovery_progress().as_ref().map(Into::into),
             next_sapling_subtree_index: summary.next_sapling_subtree_index(),
             next_orchard_subtree_index: summary.next_orchard_subtree_index(),
         }
     }
 }
+
+/// An unspent output chosen from the ones `list_unspent` returns
+#[derive(Deserialize)]
+struct OutputRef {
+    txid: String,
+    #[serde(default = "transparent_pool")]
+    pool: Pool,
+    output_index: u32,
+}
+
+fn transparent_pool() -> Pool {
+    Pool::Transparent
+}
+
+impl OutputRef {
+    fn note_id(&self) -> Result<NoteId, Error> {
+        let txid = parse_txid(&self.txid)?;
+        let protocol = match self.pool {
+            Pool::Sapling => ShieldedProtocol::Sapling,
+            Pool::Orchard => ShieldedProtocol::Orchard,
+            Pool::Transparent => {
+                return Err(Error::InputNotSpendable {
+                    output: format!(
+                        "transparent output {} of transaction {}",
+                        self.output_index, txid
+                    ),
+                    reason: "transparent outputs can only be spent by shielding them",
+                })
+            }
+        };
+        let output_index =
+            u16::try_from(self.output_index).map_err(|_| Error::InputNotSpendable {
+                output: format!(
+                    "{:?} output {} of transaction {}",
+                    protocol, self.output_index, txid
+                ),
+                reason: "it is not an unspent note of this account",
+            })?;
+        Ok(NoteId::new(txid, protocol, output_index))
+    }
+
+    fn outpoint(&self) -> Result<OutPoint, Error> {
+        let txid = parse_txid(&self.txid)?;
+        if self.pool != Pool::Transparent {
+            return Err(Error::InputNotSpendable {
+                output: format!(
+                    "{:?} output {} of transaction {}",
+                    self.pool, self.output_index, txid
+                ),
+                reason: "only transparent outputs can be shielded",
+            });
+        }
+        Ok(OutPoint::new(*txid.as_ref(), self.output_index))
+    }
+}
+
+/// A note selection strategy given to a proposal, or the default one if none was
+fn selection_from_js(selection: JsValue) -> Result<SelectionStrategy, Error> {
+    if selection.is_undefined() || selection.is_null() {
+        Ok(SelectionStrategy::default())
+    } else {
+        Ok(serde_wasm_bindgen::from_value(selection)?)
+    }
+}
+
+/// Interpret a birthday passed from JS, which can be a block height, a `Date` or undefined
+fn birthday_from_js(birthday: JsValue) -> Result<Birthday, Error> {
+    if birthday.is_undefined() || birthday.is_null() {
+        Ok(Birthday::ChainTip)
+    } else if let Some(date) = birthday.dyn_ref::<js_sys::Date>() {
+        Ok(Birthday::Date(unix_seconds(date)?))
+    } else if let Some(height) = birthday.as_f64() {
+        if height.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&height) {
+            return Err(Error::InvalidBirthday);
+        }
+        Ok(Birthday::Height(height as u32))
+    } else {
+        Err(Error::InvalidBirthday)
+    }
+}
+
+fn unix_seconds(date: &js_sys::Date) -> Result<u64, Error> {
+    let millis = date.get_time();
+    if millis.is_nan() || millis < 0.0 {
+        return Err(Error::InvalidBirthday);
+    }
+    Ok((millis / 1000.0) as u64)
+}
+
+/// Parse a transaction ID in the byte order used by block explorers
+fn parse_txid(txid: &str) -> Result<TxId, Error> {
+    let mut bytes: [u8; 32] = hex::decode(txid)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidTxId(txid.to_string()))?;
+    bytes.reverse();
+    Ok(TxId::from_bytes(bytes))
+}
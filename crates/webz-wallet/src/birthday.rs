@@ -0,0 +1,105 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Estimating account birthday heights from calendar dates
+//!
+//! A first estimate is made from the genesis block time and the target block spacing, which changed from 150 to 75
+//! seconds at Blossom activation. Real block times drift from the target so this is refined against the times of
+//! known blocks: the nearest checkpoint before the date if there is one, otherwise blocks fetched from lightwalletd.
+//! A margin is then taken off so the result errs towards scanning a little more rather than missing funds.
+
+use webz_common::Network;
+use zcash_primitives::consensus::{BlockHeight, NetworkUpgrade, Parameters};
+
+/// Timestamp of the mainnet genesis block
+const MAINNET_GENESIS_TIME: u64 = 1477641360;
+/// Timestamp of the testnet genesis block
+const TESTNET_GENESIS_TIME: u64 = 1477648033;
+
+const PRE_BLOSSOM_BLOCK_SPACING: i64 = 150;
+const POST_BLOSSOM_BLOCK_SPACING: i64 = 75;
+
+/// Margin taken off estimates made from block spacing alone. The chain has drifted from the target spacing by days
+/// at times so this is generous.
+pub const UNREFINED_MARGIN_SECS: u64 = 30 * 24 * 60 * 60;
+/// Margin taken off estimates refined with real block times. Block timestamps are only loosely ordered and the
+/// spacing between the reference block and the date is still extrapolated.
+pub const REFINED_MARGIN_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// A reference block within this long of the date is close enough to extrapolate from without refining further
+pub const REFERENCE_WINDOW_SECS: u64 = 14 * 24 * 60 * 60;
+
+/// The most blocks fetched from lightwalletd to refine an estimate
+pub const MAX_REFINEMENTS: usize = 4;
+
+/// How an account birthday is specified when adding an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Birthday {
+    /// The height of the first block that could contain transactions for the account
+    Height(u32),
+    /// When the account was created, as a unix timestamp in seconds. A conservative height is estimated from it.
+    ///
+    /// If no checkpoint is close to the date, a few blocks around it are fetched from lightwalletd to refine the
+    /// estimate, which gives the server a rough idea of the date.
+    Date(u64),
+    /// Shortly before the current chain tip. Only suitable for brand new accounts.
+    ChainTip,
+}
+
+impl From<Option<u32>> for Birthday {
+    fn from(height: Option<u32>) -> Self {
+        height.map_or(Birthday::ChainTip, Birthday::Height)
+    }
+}
+
+/// Estimate the height of the block mined at `unix_time` by extrapolating from a block with a known height and time
+pub fn extrapolate_height(
+    network: &Network,
+    (known_height, known_time): (u32, u64),
+    unix_time: u64,
+) -> u32 {
+    let blossom = network
+        .activation_height(NetworkUpgrade::Blossom)
+        .map_or(i64::MAX, |h| u32::from(h) as i64);
+    let height = known_height as i64;
+    let delta = unix_time as i64 - known_time as i64;
+
+    let estimate = if delta >= 0 {
+        // Time until Blossom activates, if it is still ahead
+        let to_blossom = (blossom - height)
+            .max(0)
+            .saturating_mul(PRE_BLOSSOM_BLOCK_SPACING);
+        if delta < to_blossom {
+            height + delta / PRE_BLOSSOM_BLOCK_SPACING
+        } else {
+            height.max(blossom) + (delta - to_blossom) / POST_BLOSSOM_BLOCK_SPACING
+        }
+    } else {
+        let delta = -delta;
+        // Time back to Blossom activation, if it is behind
+        let from_blossom = (height - blossom).max(0) * POST_BLOSSOM_BLOCK_SPACING;
+        if delta < from_blossom {
+            height - delta / POST_BLOSSOM_BLOCK_SPACING
+        } else {
+            height.min(blossom) - (delta - from_blossom) / PRE_BLOSSOM_BLOCK_SPACING
+        }
+    };
+
+    estimate.clamp(0, u32::MAX as i64) as u32
+}
+
+/// The height and time of the genesis block, from which heights can be estimated with [`extrapolate_height`]
+pub fn genesis_reference(network: &Network) -> (u32, u64) {
+    let genesis_time = match network {
+        Network::MainNetwork => MAINNET_GENESIS_TIME,
+        Network::TestNetwork => TESTNET_GENESIS_TIME,
+    };
+    (0, genesis_time)
+}
+
+/// Accounts can't receive shielded funds before Sapling activated so there is no point in scanning earlier blocks
+pub fn earliest_birthday(network: &Network) -> u32 {
+    network
+        .activation_height(NetworkUpgrade::Sapling)
+        .map_or(0, |h: BlockHeight| h.into())
+}
@@ -0,0 +1,390 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Cache for compact blocks which have been downloaded but not yet scanned
+//!
+//! Blocks are held in memory so they can be handed to the scanner synchronously. In the browser the cache can
+//! additionally be mirrored to IndexedDB with [`CompactBlockCache::persist_to_indexed_db`]. Blocks downloaded by a
+//! sync that was interrupted, for example by the tab closing, are then read back range by range as the next sync
+//! reaches them instead of being downloaded from lightwalletd a second time. Only the batches a sync is working on are
+//! ever in memory.
+
+use std::collections::BTreeMap;
+
+use wasm_sync::RwLock;
+#[cfg(feature = "wasm")]
+use webz_common::Network;
+use zcash_client_backend::data_api::chain::{error::Error as ChainError, BlockCache, BlockSource};
+use zcash_client_backend::data_api::scanning::ScanRange;
+use zcash_client_backend::proto::compact_formats::CompactBlock;
+use zcash_primitives::consensus::BlockHeight;
+
+/// Name of the IndexedDB database used when none is given
+pub const DEFAULT_INDEXED_DB_NAME: &str = "webz-block-cache";
+
+#[derive(thiserror::Error, Debug)]
+pub enum BlockCacheError {
+    #[error("Block cache lock was poisoned")]
+    Poisoned,
+    #[error("IndexedDB error: {0}")]
+    IndexedDb(String),
+}
+
+/// A [`BlockCache`] keyed by block height, optionally persisted to IndexedDB
+#[derive(Default)]
+pub struct CompactBlockCache {
+    blocks: RwLock<BTreeMap<BlockHeight, CompactBlock>>,
+    /// Where in IndexedDB the cache is mirrored to, if anywhere
+    #[cfg(feature = "wasm")]
+    indexed_db: RwLock<Option<indexed_db::Store>>,
+}
+
+impl CompactBlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert_in_memory(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), BlockCacheError> {
+        let mut blocks = self.blocks.write().map_err(|_| BlockCacheError::Poisoned)?;
+        for block in compact_blocks {
+            blocks.insert(block.height(), block);
+        }
+        Ok(())
+    }
+
+    /// Remove the blocks in `[start, end)` from memory, returning `true` if there were any
+    fn delete_in_memory(
+        &self,
+        start: BlockHeight,
+        end: BlockHeight,
+    ) -> Result<bool, BlockCacheError> {
+        let mut blocks = self.blocks.write().map_err(|_| BlockCacheError::Poisoned)?;
+        let removed: Vec<_> = blocks
+            .range(start..end)
+            .map(|(height, _)| *height)
+            .collect();
+        for height in &removed {
+            blocks.remove(height);
+        }
+        Ok(!removed.is_empty())
+    }
+
+    #[cfg(feature = "wasm")]
+    fn indexed_db_store(&self) -> Result<Option<indexed_db::Store>, BlockCacheError> {
+        Ok(self
+            .indexed_db
+            .read()
+            .map_err(|_| BlockCacheError::Poisoned)?
+            .clone())
+    }
+
+    /// Bring the blocks of `range` which are only in IndexedDB into memory
+    #[cfg(feature = "wasm")]
+    async fn load_from_indexed_db(&self, range: &ScanRange) -> Result<(), BlockCacheError> {
+        let Some(store) = self.indexed_db_store()? else {
+            return Ok(());
+        };
+        let in_memory = self
+            .blocks
+            .read()
+            .map_err(|_| BlockCacheError::Poisoned)?
+            .range(range.block_range().clone())
+            .count();
+        if in_memory == range.len() {
+            return Ok(());
+        }
+        let start = range.block_range().start.into();
+        let end = range.block_range().end.into();
+        let stored = indexed_db::run_local(indexed_db::load(store, start, end)).await?;
+        if !stored.is_empty() {
+            tracing::info!(
+                "Loaded {} cached compact blocks from IndexedDB",
+                stored.len()
+            );
+        }
+        // Blocks downloaded since are newer than the stored ones
+        let mut blocks = self.blocks.write().map_err(|_| BlockCacheError::Poisoned)?;
+        for block in stored {
+            blocks.entry(block.height()).or_insert(block);
+        }
+        Ok(())
+    }
+}
+
+impl BlockSource for CompactBlockCache {
+    type Error = BlockCacheError;
+
+    fn with_blocks<F, WalletErrT>(
+        &self,
+        from_height: Option<BlockHeight>,
+        limit: Option<usize>,
+        mut with_block: F,
+    ) -> Result<(), ChainError<WalletErrT, Self::Error>>
+    where
+        F: FnMut(CompactBlock) -> Result<(), ChainError<WalletErrT, Self::Error>>,
+    {
+        // Copy the blocks out so the lock is not held while the scanner runs
+        let blocks: Vec<CompactBlock> = self
+            .blocks
+            .read()
+            .map_err(|_| ChainError::BlockSource(BlockCacheError::Poisoned))?
+            .range(from_height.unwrap_or_else(|| BlockHeight::from_u32(0))..)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(_, block)| block.clone())
+            .collect();
+
+        for block in blocks {
+            with_block(block)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockCache for CompactBlockCache {
+    fn get_tip_height(
+        &self,
+        range: Option<&ScanRange>,
+    ) -> Result<Option<BlockHeight>, Self::Error> {
+        let blocks = self.blocks.read().map_err(|_| BlockCacheError::Poisoned)?;
+        Ok(match range {
+            Some(range) => blocks.range(range.block_range().clone()).next_back(),
+            None => blocks.iter().next_back(),
+        }
+        .map(|(height, _)| *height))
+    }
+
+    async fn read(&self, range: &ScanRange) -> Result<Vec<CompactBlock>, Self::Error> {
+        #[cfg(feature = "wasm")]
+        self.load_from_indexed_db(range).await?;
+
+        Ok(self
+            .blocks
+            .read()
+            .map_err(|_| BlockCacheError::Poisoned)?
+            .range(range.block_range().clone())
+            .map(|(_, block)| block.clone())
+            .collect())
+    }
+
+    async fn insert(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), Self::Error> {
+        #[cfg(feature = "wasm")]
+        if let Some(store) = self.indexed_db_store()? {
+            // Write to IndexedDB first so a block in memory is always also persisted
+            indexed_db::run_local(indexed_db::put(store, compact_blocks.clone())).await?;
+        }
+        self.insert_in_memory(compact_blocks)
+    }
+
+    async fn delete(&self, range: ScanRange) -> Result<(), Self::Error> {
+        let start = range.block_range().start;
+        let end = range.block_range().end;
+        self.delete_in_memory(start, end)?;
+
+        // Blocks which haven't been loaded yet are only in IndexedDB, so it is cleared even if none were in memory
+        #[cfg(feature = "wasm")]
+        if let Some(store) = self.indexed_db_store()? {
+            indexed_db::run_local(indexed_db::delete(store, start.into(), Some(end.into())))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn truncate(&self, block_height: BlockHeight) -> Result<(), Self::Error> {
+        let start = block_height + 1;
+        self.delete_in_memory(start, BlockHeight::from_u32(u32::MAX))?;
+
+        #[cfg(feature = "wasm")]
+        if let Some(store) = self.indexed_db_store()? {
+            indexed_db::run_local(indexed_db::delete(store, start.into(), None)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl CompactBlockCache {
+    /// Mirror this cache to the IndexedDB database `name`, creating it if needed. Blocks of each network are kept
+    /// apart, so wallets for different networks can share a database.
+    ///
+    /// From then on every insert and eviction is also applied to the database. Blocks left in it by a previous
+    /// session stay there until a sync reads their range. This must be called from a thread with access to IndexedDB.
+    pub async fn persist_to_indexed_db(
+        &self,
+        name: &str,
+        network: Network,
+    ) -> Result<(), BlockCacheError> {
+        let store = indexed_db::Store::new(name, network);
+
+        // Blocks only held in memory so far have to be written out
+        let unsaved = {
+            let blocks = self.blocks.read().map_err(|_| BlockCacheError::Poisoned)?;
+            blocks.values().cloned().collect::<Vec<_>>()
+        };
+        indexed_db::put(store.clone(), unsaved)
+            .await
+            .map_err(|e| BlockCacheError::IndexedDb(e.to_string()))?;
+
+        *self
+            .indexed_db
+            .write()
+            .map_err(|_| BlockCacheError::Poisoned)? = Some(store);
+        Ok(())
+    }
+}
+
+/// IndexedDB access. Blocks are stored as protobuf encoded bytes keyed by height, in one object store per network.
+///
+/// Handles to IndexedDB cannot be sent between threads so the database is opened for each operation on whichever
+/// thread the cache is being used from.
+#[cfg(feature = "wasm")]
+mod indexed_db {
+    use std::future::Future;
+
+    use indexed_db_futures::prelude::*;
+    use indexed_db_futures::web_sys::{IdbKeyRange, IdbTransactionMode};
+    use js_sys::Uint8Array;
+    use prost::Message;
+    use tokio::sync::oneshot;
+    use wasm_bindgen::JsValue;
+    use webz_common::Network;
+    use zcash_client_backend::proto::compact_formats::CompactBlock;
+
+    use super::BlockCacheError;
+    use crate::error::Error;
+
+    /// Version 1 kept the blocks of every network in a single store
+    const VERSION: u32 = 2;
+    const LEGACY_STORE_NAME: &str = "compact_blocks";
+    const MAINNET_STORE_NAME: &str = "compact_blocks_main";
+    const TESTNET_STORE_NAME: &str = "compact_blocks_test";
+
+    /// The object store holding the blocks of one network
+    #[derive(Debug, Clone)]
+    pub(super) struct Store {
+        db_name: String,
+        store_name: &'static str,
+    }
+
+    impl Store {
+        pub(super) fn new(db_name: &str, network: Network) -> Self {
+            Store {
+                db_name: db_name.to_string(),
+                store_name: match network {
+                    Network::MainNetwork => MAINNET_STORE_NAME,
+                    Network::TestNetwork => TESTNET_STORE_NAME,
+                },
+            }
+        }
+    }
+
+    /// Run an IndexedDB operation on the current thread's executor.
+    ///
+    /// The operation is started straight away and only the receiving end of a channel is held by the returned future,
+    /// which means it can be awaited from the `Send` futures the [`super::BlockCache`] trait requires.
+    pub(super) fn run_local<T, Fut>(
+        task: Fut,
+    ) -> impl Future<Output = Result<T, BlockCacheError>> + Send
+    where
+        T: Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = tx.send(task.await.map_err(|e| e.to_string()));
+        });
+        async move {
+            rx.await
+                .map_err(|_| BlockCacheError::IndexedDb("Operation was dropped".to_string()))?
+                .map_err(BlockCacheError::IndexedDb)
+        }
+    }
+
+    async fn open(name: &str) -> Result<IdbDatabase, Error> {
+        let mut request = IdbDatabase::open_u32(name, VERSION)?;
+        request.set_on_upgrade_needed(Some(
+            |event: &IdbVersionChangeEvent| -> Result<(), JsValue> {
+                let db = event.db();
+                // Blocks cached before stores were split by network can't be told apart, so they are dropped
+                if db.object_store_names().any(|n| n == LEGACY_STORE_NAME) {
+                    db.delete_object_store(LEGACY_STORE_NAME)?;
+                }
+                for store_name in [MAINNET_STORE_NAME, TESTNET_STORE_NAME] {
+                    if !db.object_store_names().any(|n| n == store_name) {
+                        db.create_object_store(store_name)?;
+                    }
+                }
+                Ok(())
+            },
+        ));
+        Ok(request.await?)
+    }
+
+    /// Keys cover heights in `[start, end)`, or everything from `start` upwards if `end` is `None`
+    fn key_range(start: u32, end: Option<u32>) -> Result<IdbKeyRange, JsValue> {
+        match end {
+            Some(end) => IdbKeyRange::bound_with_lower_open_and_upper_open(
+                &JsValue::from(start),
+                &JsValue::from(end),
+                false,
+                true,
+            ),
+            None => IdbKeyRange::lower_bound(&JsValue::from(start)),
+        }
+    }
+
+    /// Read the blocks at heights in `[start, end)`
+    pub(super) async fn load(
+        store: Store,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<CompactBlock>, Error> {
+        let db = open(&store.db_name).await?;
+        let tx = db.transaction_on_one_with_mode(store.store_name, IdbTransactionMode::Readonly)?;
+        let object_store = tx.object_store(store.store_name)?;
+
+        object_store
+            .get_all_with_key(&key_range(start, Some(end))?)?
+            .await?
+            .iter()
+            .map(|value| {
+                let bytes = Uint8Array::new(&value).to_vec();
+                CompactBlock::decode(bytes.as_slice())
+                    .map_err(|e| Error::Generic(format!("Corrupt cached compact block: {e}")))
+            })
+            .collect()
+    }
+
+    pub(super) async fn put(store: Store, blocks: Vec<CompactBlock>) -> Result<(), Error> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let db = open(&store.db_name).await?;
+        let tx =
+            db.transaction_on_one_with_mode(store.store_name, IdbTransactionMode::Readwrite)?;
+        let object_store = tx.object_store(store.store_name)?;
+
+        for block in blocks {
+            // Keys have to be numbers rather than BigInts for range reads and deletes to match them
+            let key = JsValue::from(block.height as f64);
+            let value = Uint8Array::from(block.encode_to_vec().as_slice());
+            object_store.put_key_val(&key, &value)?;
+        }
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    /// Delete the blocks at heights in `[start, end)`, or everything from `start` upwards if `end` is `None`
+    pub(super) async fn delete(store: Store, start: u32, end: Option<u32>) -> Result<(), Error> {
+        let db = open(&store.db_name).await?;
+        let tx =
+            db.transaction_on_one_with_mode(store.store_name, IdbTransactionMode::Readwrite)?;
+        let object_store = tx.object_store(store.store_name)?;
+
+        object_store.delete(&key_range(start, end)?)?;
+        tx.await.into_result()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Where the change of a transaction goes
+//!
+//! The backend's [`SingleOutputChangeStrategy`] pays all change into one note, in the pool the spent notes came from.
+//! An account left with one large note then has to wait for each transaction to be mined before it can spend the
+//! change. [`SplitChangeStrategy`] wraps it to send change to a chosen pool and split it into several notes, each of
+//! which can be spent independently.
+//!
+//! The backend works out the fee for a single change output. The strategy then adjusts it by the ZIP-317 fee
+//! difference between that layout and the one it picks, so the fee matches what the transaction builder charges.
+
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::fees::zip317::SingleOutputChangeStrategy;
+use zcash_client_backend::fees::{
+    orchard as orchard_fees, sapling as sapling_fees, transparent, ChangeError, ChangeStrategy,
+    ChangeValue, DustAction, DustOutputPolicy, EphemeralBalance, TransactionBalance,
+};
+use zcash_client_backend::{PoolType, ShieldedProtocol};
+use zcash_primitives::consensus::{self, BlockHeight};
+use zcash_primitives::transaction::components::amount::{BalanceError, NonNegativeAmount};
+use zcash_primitives::transaction::fees::zip317::{FeeError, FeeRule};
+use zcash_primitives::transaction::fees::FeeRule as _;
+
+use crate::error::Error;
+
+/// A shielded pool that can receive change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangePool {
+    Sapling,
+    Orchard,
+}
+
+impl From<ChangePool> for ShieldedProtocol {
+    fn from(pool: ChangePool) -> Self {
+        match pool {
+            ChangePool::Sapling => ShieldedProtocol::Sapling,
+            ChangePool::Orchard => ShieldedProtocol::Orchard,
+        }
+    }
+}
+
+/// What to do with change worth less than the dust threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DustHandling {
+    /// Fail to propose the transaction
+    Reject,
+    /// Create the change output anyway
+    AllowDustChange,
+    /// Leave the change out and pay it to the miner as part of the fee
+    AddDustToFee,
+}
+
+impl From<DustHandling> for DustAction {
+    fn from(handling: DustHandling) -> Self {
+        match handling {
+            DustHandling::Reject => DustAction::Reject,
+            DustHandling::AllowDustChange => DustAction::AllowDustChange,
+            DustHandling::AddDustToFee => DustAction::AddDustToFee,
+        }
+    }
+}
+
+/// How the change of a proposed transaction is paid back to the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChangeOptions {
+    /// Pool that receives the change. If not set, change stays in the pool the spent notes came from.
+    pub pool: Option<ChangePool>,
+    /// Pool that receives the change when `pool` can't be used in the transaction, or when the spent funds don't
+    /// determine one
+    pub fallback_pool: ChangePool,
+    /// Number of notes to split the change into. Fewer are made if the change is too small to give each at least the
+    /// dust threshold.
+    pub split_count: u32,
+    /// Change outputs below this many Zatoshis are dust. Defaults to the ZIP-317 marginal fee.
+    pub dust_threshold: Option<u64>,
+    pub dust_handling: DustHandling,
+}
+
+impl Default for ChangeOptions {
+    fn default() -> Self {
+        ChangeOptions {
+            pool: None,
+            fallback_pool: ChangePool::Orchard,
+            split_count: 1,
+            dust_threshold: None,
+            dust_handling: DustHandling::Reject,
+        }
+    }
+}
+
+impl ChangeOptions {
+    /// The change strategy proposals made with these options use
+    pub fn change_strategy(&self) -> SplitChangeStrategy {
+        SplitChangeStrategy {
+            inner: SingleOutputChangeStrategy::new(
+                FeeRule::standard(),
+                None,
+                self.fallback_pool.into(),
+            ),
+            pool: self.pool.map(Into::into),
+            fallback_pool: self.fallback_pool.into(),
+            split_count: self.split_count.max(1) as usize,
+        }
+    }
+
+    /// The dust policy proposals made with these options use
+    pub fn dust_output_policy(&self) -> Result<DustOutputPolicy, Error> {
+        let dust_threshold = self
+            .dust_threshold
+            .map(NonNegativeAmount::from_u64)
+            .transpose()?;
+        Ok(DustOutputPolicy::new(
+            self.dust_handling.into(),
+            dust_threshold,
+        ))
+    }
+}
+
+/// Pays change to a chosen pool, split over up to `split_count` outputs
+#[derive(Clone)]
+pub struct SplitChangeStrategy {
+    inner: SingleOutputChangeStrategy,
+    pool: Option<ShieldedProtocol>,
+    fallback_pool: ShieldedProtocol,
+    split_count: usize,
+}
+
+impl ChangeStrategy for SplitChangeStrategy {
+    type FeeRule = FeeRule;
+    type Error = FeeError;
+
+    fn fee_rule(&self) -> &Self::FeeRule {
+        self.inner.fee_rule()
+    }
+
+    fn compute_balance<P: consensus::Parameters, NoteRefT: Clone>(
+        &self,
+        params: &P,
+        target_height: BlockHeight,
+        transparent_inputs: &[impl transparent::InputView],
+        transparent_outputs: &[impl transparent::OutputView],
+        sapling: &impl sapling_fees::BundleView<NoteRefT>,
+        orchard: &impl orchard_fees::BundleView<NoteRefT>,
+        dust_output_policy: &DustOutputPolicy,
+        ephemeral_balance: Option<&EphemeralBalance>,
+    ) -> Result<TransactionBalance, ChangeError<Self::Error, NoteRefT>> {
+        let balance = self.inner.compute_balance(
+            params,
+            target_height,
+            transparent_inputs,
+            transparent_outputs,
+            sapling,
+            orchard,
+            dust_output_policy,
+            ephemeral_balance,
+        )?;
+
+        // Only a transaction with a single shielded change output is rearranged. Multi-step proposals through ephemeral
+        // addresses and transactions without change are left as the backend made them.
+        if ephemeral_balance.is_some() || (self.pool.is_none() && self.split_count == 1) {
+            return Ok(balance);
+        }
+        let [change] = balance.proposed_change() else {
+            return Ok(balance);
+        };
+        let PoolType::Shielded(change_pool) = change.output_pool() else {
+            return Ok(balance);
+        };
+        if change.is_ephemeral() {
+            return Ok(balance);
+        }
+
+        let fee_with_change = |pool: ShieldedProtocol, count: usize| {
+            let (sapling_change, orchard_change) = match pool {
+                ShieldedProtocol::Sapling => (count, 0),
+                ShieldedProtocol::Orchard => (0, count),
+            };
+            // A pool that can't take the outputs, e.g. Orchard before NU5, rules the layout out
+            let (Ok(sapling_inputs), Ok(sapling_outputs), Ok(orchard_actions)) = (
+                sapling.bundle_type().num_spends(sapling.inputs().len()),
+                sapling.bundle_type().num_outputs(
+                    sapling.inputs().len(),
+                    sapling.outputs().len() + sapling_change,
+                ),
+                orchard.bundle_type().num_actions(
+                    orchard.inputs().len(),
+                    orchard.outputs().len() + orchard_change,
+                ),
+            ) else {
+                return Ok(None);
+            };
+            self.fee_rule()
+                .fee_required(
+                    params,
+                    target_height,
+                    transparent_inputs
+                        .iter()
+                        .map(|input| input.serialized_size()),
+                    transparent_outputs
+                        .iter()
+                        .map(|output| output.serialized_size()),
+                    sapling_inputs,
+                    sapling_outputs,
+                    orchard_actions,
+                )
+                .map(Some)
+                .map_err(ChangeError::StrategyError)
+        };
+
+        let Some(base_fee) = fee_with_change(change_pool, 1)? else {
+            return Ok(balance);
+        };
+        let available = u64::from(change.value()) + u64::from(balance.fee_required());
+        let dust_threshold = u64::from(
+            dust_output_policy
+                .dust_threshold()
+                .unwrap_or(self.fee_rule().marginal_fee()),
+        );
+
+        let pools = [self.pool.unwrap_or(change_pool), self.fallback_pool];
+        for (i, pool) in pools.into_iter().enumerate() {
+            if i > 0 && pool == pools[0] {
+                break;
+            }
+            for count in (1..=self.split_count).rev() {
+                if pool == change_pool && count == 1 {
+                    // The layout the backend already chose
+                    return Ok(balance);
+                }
+                let Some(fee) = fee_with_change(pool, count)? else {
+                    break;
+                };
+                // The backend's fee is exact for its own layout so only the difference is added
+                let Some(fee) = (u64::from(balance.fee_required()) + u64::from(fee))
+                    .checked_sub(u64::from(base_fee))
+                else {
+                    continue;
+                };
+                let Some(value) = available.checked_sub(fee) else {
+                    continue;
+                };
+                let share = value / count as u64;
+                if share < dust_threshold {
+                    continue;
+                }
+
+                let outputs = (0..count)
+                    .map(|i| {
+                        // The first output takes what doesn't divide evenly
+                        let extra = if i == 0 { value % count as u64 } else { 0 };
+                        Ok(ChangeValue::shielded(
+                            pool,
+                            NonNegativeAmount::from_u64(share + extra)?,
+                            change.memo().cloned(),
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, BalanceError>>()
+                    .map_err(|e| ChangeError::StrategyError(FeeError::Balance(e)))?;
+                let fee = NonNegativeAmount::from_u64(fee)
+                    .map_err(|e| ChangeError::StrategyError(FeeError::Balance(e)))?;
+                return TransactionBalance::new(outputs, fee).map_err(|_| {
+                    ChangeError::StrategyError(FeeError::Balance(BalanceError::Overflow))
+                });
+            }
+        }
+
+        Ok(balance)
+    }
+}
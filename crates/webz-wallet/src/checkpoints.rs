@@ -0,0 +1,129 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Tree states at regular heights used to set account birthdays
+//!
+//! Importing an account needs the note commitment tree state as of the block before its birthday. Asking lightwalletd
+//! for it with `GetTreeState(birthday - 1)` tells the server when the account was created. Instead the wallet starts
+//! from the nearest known checkpoint at or below that height, which only costs scanning a few more blocks.
+//!
+//! Checkpoints for mainnet and testnet are bundled from `checkpoints/*.json`, which are generated with
+//! `scripts/update_checkpoints.sh`. More can be loaded at runtime with [`Checkpoints::from_json`]. If there is no
+//! checkpoint below a birthday the wallet asks lightwalletd for the tree state, unless that was turned off with
+//! `Wallet::allow_birthday_requests(false)`, in which case importing the account fails.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use webz_common::Network;
+use zcash_client_backend::proto::service::TreeState;
+
+use crate::error::Error;
+
+const MAINNET_CHECKPOINTS: &str = include_str!("../checkpoints/mainnet.json");
+const TESTNET_CHECKPOINTS: &str = include_str!("../checkpoints/testnet.json");
+
+/// The note commitment tree state at the end of a block.
+///
+/// This has the same fields as lightwalletd's `TreeState` apart from the network, using the JSON names returned by
+/// lightwalletd so its responses can be used as they are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub height: u32,
+    /// Block hash, hex encoded in the byte order used by block explorers
+    pub hash: String,
+    /// Unix timestamp of the block
+    pub time: u32,
+    /// Hex encoded Sapling commitment tree frontier
+    #[serde(default)]
+    pub sapling_tree: String,
+    /// Hex encoded Orchard commitment tree frontier
+    #[serde(default)]
+    pub orchard_tree: String,
+}
+
+impl Checkpoint {
+    pub fn to_tree_state(&self, network: &Network) -> TreeState {
+        TreeState {
+            network: match network {
+                Network::MainNetwork => "main",
+                Network::TestNetwork => "test",
+            }
+            .to_string(),
+            height: self.height.into(),
+            hash: self.hash.clone(),
+            time: self.time,
+            sapling_tree: self.sapling_tree.clone(),
+            orchard_tree: self.orchard_tree.clone(),
+        }
+    }
+}
+
+/// A set of [`Checkpoint`]s for one network, ordered by height
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoints {
+    checkpoints: BTreeMap<u32, Checkpoint>,
+}
+
+impl Checkpoints {
+    /// The checkpoints that ship with the library
+    pub fn bundled(network: Network) -> Self {
+        let json = match network {
+            Network::MainNetwork => MAINNET_CHECKPOINTS,
+            Network::TestNetwork => TESTNET_CHECKPOINTS,
+        };
+        Self::from_json(json).expect("bundled checkpoints are valid JSON")
+    }
+
+    /// Parse a JSON array of checkpoints
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let checkpoints: Vec<Checkpoint> = serde_json::from_str(json)?;
+        Ok(checkpoints.into_iter().collect())
+    }
+
+    /// Add a checkpoint, replacing any existing one at the same height
+    pub fn insert(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.insert(checkpoint.height, checkpoint);
+    }
+
+    /// The highest checkpoint at or below `height`
+    pub fn at_or_below(&self, height: u32) -> Option<&Checkpoint> {
+        self.checkpoints
+            .range(..=height)
+            .next_back()
+            .map(|(_, checkpoint)| checkpoint)
+    }
+
+    /// The highest checkpoint whose block was mined at or before `unix_time`
+    pub fn at_or_before_time(&self, unix_time: u64) -> Option<&Checkpoint> {
+        self.checkpoints
+            .values()
+            .rev()
+            .find(|checkpoint| u64::from(checkpoint.time) <= unix_time)
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+}
+
+impl FromIterator<Checkpoint> for Checkpoints {
+    fn from_iter<I: IntoIterator<Item = Checkpoint>>(iter: I) -> Self {
+        let mut checkpoints = Self::default();
+        checkpoints.extend(iter);
+        checkpoints
+    }
+}
+
+impl Extend<Checkpoint> for Checkpoints {
+    fn extend<I: IntoIterator<Item = Checkpoint>>(&mut self, iter: I) {
+        for checkpoint in iter {
+            self.insert(checkpoint);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Proposing transactions that spend exactly the inputs the caller chose
+//!
+//! The chosen inputs are identified the same way [`Wallet::list_unspent`] lists them. Each must be unspent and
+//! spendable under the wallet's `min_confirmations`. The proposal spends all of them and nothing else, returning any
+//! excess as change. Shielded notes are spent by transfers and transparent outputs by shielding, since transfers can't
+//! spend transparent funds.
+
+use std::collections::HashSet;
+
+use zcash_client_backend::data_api::wallet::{propose_shielding, propose_transfer};
+use zcash_client_backend::data_api::{InputSource, WalletCommitmentTrees, WalletRead};
+use zcash_client_backend::proposal::Proposal;
+use zcash_client_backend::wallet::NoteId;
+use zcash_client_backend::zip321::TransactionRequest;
+use zcash_client_backend::ShieldedProtocol;
+use zcash_primitives::transaction::components::amount::{NonNegativeAmount, MAX_MONEY};
+use zcash_primitives::transaction::components::OutPoint;
+use zcash_primitives::transaction::fees::zip317::FeeRule;
+use zcash_primitives::transaction::TxId;
+
+use crate::change::ChangeOptions;
+use crate::diagnostics::ProposalFailure;
+use crate::error::Error;
+use crate::input_selection::SelectionStrategy;
+use crate::shielding::with_note_ref;
+use crate::unspent::{note_id, notes_of};
+use crate::wallet::{proposal_error, LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    ///
+    /// Create a proposal that makes every payment in `request` by spending all of `notes` and no other notes of
+    /// `account_id`
+    ///
+    /// Returns [`Error::InputNotSpendable`] if one of the notes is not an unspent, confirmed note of the account and
+    /// [`Error::InsufficientInputs`] if the notes don't cover the payments and the fee.
+    ///
+    pub async fn propose_request_from_notes(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+        notes: Vec<NoteId>,
+        change: &ChangeOptions,
+    ) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+        if notes.is_empty() {
+            return Err(Error::Generic("No notes were chosen to spend".to_string()));
+        }
+        self.check_recipients(&request)?;
+
+        let mut db = self.db.write().await;
+        let sources = [ShieldedProtocol::Sapling, ShieldedProtocol::Orchard];
+        let all_value = NonNegativeAmount::const_from_u64(MAX_MONEY);
+        let selected_at = |height| -> Result<HashSet<NoteId>, Error> {
+            let notes = db.select_spendable_notes(account_id, all_value, &sources, height, &[])?;
+            Ok(notes_of(&notes).iter().map(note_id).collect())
+        };
+        let spendable = match db.get_target_and_anchor_heights(self.min_confirmations)? {
+            Some((_, anchor_height)) => selected_at(anchor_height)?,
+            None => HashSet::new(),
+        };
+        // Everything unspent however recently it was received, to tell why a note can't be spent
+        let unspent = match db.chain_height()? {
+            Some(chain_tip) => selected_at(chain_tip)?,
+            None => HashSet::new(),
+        };
+        check_chosen_notes(&notes, &spendable, &unspent)?;
+
+        let input_selector = self
+            .input_selector(change, SelectionStrategy::default())?
+            .spending_notes(notes.into_iter().collect());
+        let proposal = propose_transfer::<_, _, _, <W as WalletCommitmentTrees>::Error>(
+            &mut *db,
+            &self.network,
+            account_id,
+            &input_selector,
+            request,
+            self.min_confirmations,
+        )
+        .map_err(|e| insufficient_inputs(proposal_error(e)))?;
+        tracing::debug!("Proposal: {:#?}", proposal);
+        Ok(proposal)
+    }
+
+    ///
+    /// Create a proposal to shield exactly the transparent outputs `utxos` of `account_id` into its shielded pool
+    ///
+    /// Returns [`Error::InputNotSpendable`] if one of the outputs is not an unspent, confirmed output received by the
+    /// account and [`Error::InsufficientInputs`] if the outputs don't cover the fee.
+    ///
+    pub async fn propose_shielding_from_utxos(
+        &self,
+        account_id: AccountId,
+        utxos: Vec<OutPoint>,
+        change: &ChangeOptions,
+    ) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+        if utxos.is_empty() {
+            return Err(Error::Generic(
+                "No transparent outputs were chosen to shield".to_string(),
+            ));
+        }
+
+        let mut db = self.db.write().await;
+        let receivers: HashSet<_> = db
+            .get_transparent_receivers(account_id)?
+            .into_keys()
+            .collect();
+        let target_height = db
+            .get_target_and_anchor_heights(self.min_confirmations)?
+            .map(|(target_height, _)| target_height);
+
+        let mut addresses = vec![];
+        for outpoint in &utxos {
+            let not_spendable = |reason| Error::InputNotSpendable {
+                output: describe_utxo(outpoint),
+                reason,
+            };
+            let output = db
+                .get_unspent_transparent_output(outpoint)?
+                .filter(|output| receivers.contains(output.recipient_address()))
+                .ok_or_else(|| {
+                    not_spendable("it is not an unspent transparent output of this account")
+                })?;
+            let address = *output.recipient_address();
+            let confirmed = match target_height {
+                Some(target_height) => db
+                    .get_spendable_transparent_outputs(
+                        &address,
+                        target_height,
+                        self.min_confirmations.into(),
+                    )?
+                    .iter()
+                    .any(|output| output.outpoint() == outpoint),
+                None => false,
+            };
+            if !confirmed {
+                return Err(not_spendable("it doesn't have enough confirmations yet"));
+            }
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+
+        let input_selector = self
+            .input_selector(change, SelectionStrategy::default())?
+            .spending_utxos(utxos.into_iter().collect());
+        let proposal = propose_shielding::<_, _, _, <W as WalletCommitmentTrees>::Error>(
+            &mut *db,
+            &self.network,
+            &input_selector,
+            NonNegativeAmount::ZERO,
+            &addresses,
+            self.min_confirmations.into(),
+        )
+        .map_err(|e| insufficient_inputs(proposal_error(e)))?;
+        tracing::debug!("Shielding proposal: {:#?}", proposal);
+
+        with_note_ref(proposal)
+    }
+}
+
+/// Check that each of the chosen `notes` is `spendable`. A note that isn't is reported as lacking confirmations if it
+/// is `unspent`, and as not belonging to the account otherwise.
+pub fn check_chosen_notes(
+    notes: &[NoteId],
+    spendable: &HashSet<NoteId>,
+    unspent: &HashSet<NoteId>,
+) -> Result<(), Error> {
+    match notes.iter().find(|note| !spendable.contains(note)) {
+        Some(note) => Err(Error::InputNotSpendable {
+            output: describe_note(note),
+            reason: if unspent.contains(note) {
+                "it doesn't have enough confirmations yet"
+            } else {
+                "it is not an unspent note of this account"
+            },
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Insufficient funds means the chosen inputs fell short, not the account
+pub fn insufficient_inputs(e: Error) -> Error {
+    match e {
+        Error::Proposal(ProposalFailure::InsufficientFunds {
+            available,
+            required,
+            ..
+        }) => Error::InsufficientInputs {
+            available,
+            required,
+        },
+        e => e,
+    }
+}
+
+fn describe_note(note: &NoteId) -> String {
+    format!(
+        "{:?} output {} of transaction {}",
+        note.protocol(),
+        note.output_index(),
+        note.txid()
+    )
+}
+
+fn describe_utxo(outpoint: &OutPoint) -> String {
+    format!(
+        "transparent output {} of transaction {}",
+        outpoint.n(),
+        TxId::from_bytes(*outpoint.hash())
+    )
+}
@@ -0,0 +1,248 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Explaining why a transaction could not be proposed
+//!
+//! The backend only reports the total available and required when an account can't cover a transaction. After a
+//! failed proposal the wallet looks at the account's balance to tell whether the funds are missing, still waiting for
+//! confirmations, or in blocks the wallet hasn't scanned yet. Recipients are checked before proposing so an address for
+//! the wrong network or a memo to a transparent address is reported as such.
+
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::data_api::{Balance, InputSource, WalletRead};
+use zcash_client_backend::zip321::TransactionRequest;
+use zcash_keys::address::Address;
+
+use crate::error::Error;
+use crate::history::Pool;
+use crate::wallet::{LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// Why a transaction could not be proposed
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ProposalFailure {
+    /// The account doesn't hold enough, even counting funds that aren't spendable yet
+    #[error("Insufficient funds: {available} Zatoshis available but {required} required")]
+    InsufficientFunds {
+        available: u64,
+        required: u64,
+        pools: Vec<PoolFunds>,
+    },
+    /// The account holds enough but not all of it has enough confirmations yet
+    #[error(
+        "Insufficient spendable funds: {available} Zatoshis spendable but {required} required. {}",
+        spendable_when(.spendable_at)
+    )]
+    NotYetSpendable {
+        available: u64,
+        required: u64,
+        /// Chain height at which enough of the pending notes will be spendable, if they are all mined
+        spendable_at: Option<u32>,
+        pools: Vec<PoolFunds>,
+    },
+    /// The wallet hasn't scanned far enough to spend at the anchor height its `min_confirmations` implies
+    #[error("The wallet must finish syncing before it can make this transaction")]
+    NotSynced {
+        fully_scanned: Option<u32>,
+        chain_tip: Option<u32>,
+    },
+    #[error("Cannot pay {address}: {reason}")]
+    InvalidRecipient { address: String, reason: String },
+    #[error("Cannot send a memo to the transparent address {address}")]
+    MemoToTransparent { address: String },
+}
+
+/// The funds of an account in one shielded pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolFunds {
+    pub pool: Pool,
+    /// Value that can be spent now
+    pub spendable: u64,
+    /// Value waiting for confirmations or for the wallet to finish scanning
+    pub pending: u64,
+}
+
+impl PoolFunds {
+    fn new(pool: Pool, balance: &Balance) -> Self {
+        PoolFunds {
+            pool,
+            spendable: balance.spendable_value().into(),
+            pending: u64::from(balance.change_pending_confirmation())
+                + u64::from(balance.value_pending_spendability()),
+        }
+    }
+}
+
+fn spendable_when(spendable_at: &Option<u32>) -> String {
+    match spendable_at {
+        Some(height) => format!("Enough will be spendable at height {}", height),
+        None => "More will be spendable once pending transactions are mined".to_string(),
+    }
+}
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    /// Check that every payment in `request` is to an address on the wallet's network that can receive its memo
+    pub(crate) fn check_recipients(&self, request: &TransactionRequest) -> Result<(), Error> {
+        for payment in request.payments().values() {
+            let address = payment.recipient_address();
+            if let Err(e) = Address::try_from_zcash_address(&self.network, address.clone()) {
+                return Err(ProposalFailure::InvalidRecipient {
+                    address: address.encode(),
+                    reason: e.to_string(),
+                }
+                .into());
+            }
+            if payment.memo().is_some() && !address.can_receive_memo() {
+                return Err(ProposalFailure::MemoToTransparent {
+                    address: address.encode(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in the details of a failed proposal of `account_id` from the wallet's current state
+    pub(crate) async fn diagnose(&self, account_id: AccountId, error: Error) -> Error {
+        let Error::Proposal(failure) = error else {
+            return error;
+        };
+        match self.explain(account_id, &failure).await {
+            Ok(failure) => failure.into(),
+            Err(e) => {
+                tracing::warn!("Could not diagnose the failed proposal: {}", e);
+                failure.into()
+            }
+        }
+    }
+
+    async fn explain(
+        &self,
+        account_id: AccountId,
+        failure: &ProposalFailure,
+    ) -> Result<ProposalFailure, Error> {
+        let summary = self.get_wallet_summary().await?;
+        let not_synced = ProposalFailure::NotSynced {
+            fully_scanned: summary
+                .as_ref()
+                .map(|summary| summary.fully_scanned_height().into()),
+            chain_tip: summary
+                .as_ref()
+                .map(|summary| summary.chain_tip_height().into()),
+        };
+
+        let (available, required) = match failure {
+            ProposalFailure::InsufficientFunds {
+                available,
+                required,
+                ..
+            } => (*available, *required),
+            ProposalFailure::NotSynced { .. } => return Ok(not_synced),
+            failure => return Ok(failure.clone()),
+        };
+        let Some(summary) = summary else {
+            return Ok(not_synced);
+        };
+        let pools = match summary.account_balances().get(&account_id) {
+            Some(balance) => vec![
+                PoolFunds::new(Pool::Sapling, balance.sapling_balance()),
+                PoolFunds::new(Pool::Orchard, balance.orchard_balance()),
+            ],
+            None => vec![],
+        };
+
+        let mut failure = classify_shortfall(
+            available,
+            required,
+            pools,
+            summary.fully_scanned_height().into(),
+            summary.chain_tip_height().into(),
+            self.min_confirmations.into(),
+        );
+        if let ProposalFailure::NotYetSpendable { spendable_at, .. } = &mut failure {
+            *spendable_at = self
+                .spendable_at(account_id, required.saturating_sub(available))
+                .await?;
+        }
+        Ok(failure)
+    }
+
+    /// The earliest chain height at which notes of `account_id` that aren't spendable yet add up to `shortfall`, or
+    /// `None` if that needs notes that haven't been mined
+    async fn spendable_at(
+        &self,
+        account_id: AccountId,
+        shortfall: u64,
+    ) -> Result<Option<u32>, Error> {
+        let pending = self
+            .list_unspent(account_id)
+            .await?
+            .into_iter()
+            .filter(|output| output.pool != Pool::Transparent && !output.spendable)
+            .filter_map(|output| Some((output.mined_height?, output.value)))
+            .collect();
+        Ok(spendable_height(
+            pending,
+            shortfall,
+            self.min_confirmations.into(),
+        ))
+    }
+}
+
+/// Why an account with `available` Zatoshis spendable can't cover `required`, given its `pools` and how far the
+/// wallet has scanned. The height a [`ProposalFailure::NotYetSpendable`] shortfall becomes spendable is left unknown.
+pub fn classify_shortfall(
+    available: u64,
+    required: u64,
+    pools: Vec<PoolFunds>,
+    fully_scanned: u32,
+    chain_tip: u32,
+    min_confirmations: u32,
+) -> ProposalFailure {
+    let pending = pools.iter().map(|funds| funds.pending).sum::<u64>();
+    if pending > 0 && available + pending >= required {
+        return ProposalFailure::NotYetSpendable {
+            available,
+            required,
+            spendable_at: None,
+            pools,
+        };
+    }
+    // Notes in blocks that haven't been scanned aren't counted at all
+    let anchor_height = (chain_tip + 1).saturating_sub(min_confirmations);
+    if fully_scanned < anchor_height {
+        return ProposalFailure::NotSynced {
+            fully_scanned: Some(fully_scanned),
+            chain_tip: Some(chain_tip),
+        };
+    }
+    ProposalFailure::InsufficientFunds {
+        available,
+        required,
+        pools,
+    }
+}
+
+/// The earliest chain height at which enough of the `pending` notes, given as mined height and value, have
+/// `min_confirmations` to cover `shortfall`. `None` if they don't add up to it.
+pub fn spendable_height(
+    mut pending: Vec<(u32, u64)>,
+    shortfall: u64,
+    min_confirmations: u32,
+) -> Option<u32> {
+    pending.sort();
+    let mut total = 0;
+    for (mined_height, value) in pending {
+        total += value;
+        if total >= shortfall {
+            return Some(mined_height + min_confirmations - 1);
+        }
+    }
+    None
+}
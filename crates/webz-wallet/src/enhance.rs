@@ -0,0 +1,242 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Completing what compact block scanning leaves out
+//!
+//! Scanning compact blocks finds the notes an account received and spent but not their memos, the recipients of
+//! outgoing notes or the transparent parts of a transaction. The wallet backend keeps a queue of transactions it
+//! wants the full data or mined status of, which is worked through here after each sync by fetching them from
+//! lightwalletd with `GetTransaction`. It can also ask for every transaction spending from one of its transparent
+//! addresses, which are found with `GetTaddressTxids`.
+
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+use tonic::Code;
+use zcash_client_backend::data_api::wallet::decrypt_and_store_transaction;
+use zcash_client_backend::data_api::{
+    TransactionDataRequest, TransactionStatus, WalletRead, WalletWrite,
+};
+use zcash_client_backend::proto::service::{
+    compact_tx_streamer_client::CompactTxStreamerClient, TxFilter,
+};
+use zcash_primitives::consensus::{BlockHeight, BranchId, Parameters};
+use zcash_primitives::transaction::{Transaction, TxId};
+
+use crate::error::Error;
+use crate::history::{Owners, TransactionIndex};
+use crate::sync::{SyncControl, SyncEvent};
+use crate::transparent;
+use crate::wallet::LightwalletdChannel;
+
+/// How many times a request is attempted before it is left for the next sync
+pub(crate) const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a failed request. Each further retry waits this much longer.
+pub(crate) const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Work through the wallet's pending transaction data requests.
+///
+/// A request that keeps failing after [`MAX_ATTEMPTS`] is skipped. The wallet still has it queued so it is tried
+/// again after the next sync. A [`SyncEvent::Enhancement`] is emitted after each request. Every transaction fetched is
+/// recorded in `index`.
+pub(crate) async fn run<P, ChT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    index: &TransactionIndex<DbT::AccountId>,
+    control: &SyncControl,
+    on_event: &mut F,
+) -> Result<(), Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error>,
+    F: FnMut(SyncEvent),
+{
+    let requests = db.read().await.transaction_data_requests()?;
+    if requests.is_empty() {
+        return Ok(());
+    }
+    tracing::info!("Fetching {} transactions to enhance", requests.len());
+    let owners = Owners::read(&*db.read().await)?;
+
+    let total = requests.len();
+    let mut failed = 0;
+    for (i, request) in requests.iter().enumerate() {
+        if control.should_pause()? {
+            control.wait_until_resumed().await?;
+        }
+
+        let mut attempt = 1;
+        let result = loop {
+            match handle_request(client, params, db, index, &owners, request).await {
+                Err(Error::Grpc(status)) if attempt < MAX_ATTEMPTS => {
+                    tracing::debug!("Retrying {:?} after error: {}", request, status);
+                    control.sleep(RETRY_DELAY * attempt).await;
+                    control.should_pause()?;
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to handle {:?}: {}", request, e);
+            failed += 1;
+        }
+
+        on_event(SyncEvent::Enhancement {
+            completed: i + 1,
+            total,
+            failed,
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_request<P, ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    index: &TransactionIndex<DbT::AccountId>,
+    owners: &Owners<DbT::AccountId>,
+    request: &TransactionDataRequest,
+) -> Result<(), Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error>,
+{
+    match request {
+        TransactionDataRequest::GetStatus(txid) => {
+            let status = match fetch_raw_transaction(client, *txid).await? {
+                Some((_, status)) => status,
+                None => TransactionStatus::TxidNotRecognized,
+            };
+            db.write().await.set_transaction_status(*txid, status)?;
+        }
+        TransactionDataRequest::Enhancement(txid) => {
+            let next_height = next_block_height(&*db.read().await)?;
+            match fetch_transaction(client, params, *txid, next_height).await? {
+                Some((tx, status)) => {
+                    let mined_height = match status {
+                        TransactionStatus::Mined(height) => Some(height),
+                        _ => None,
+                    };
+                    decrypt_and_store_transaction(
+                        params,
+                        &mut *db.write().await,
+                        &tx,
+                        mined_height,
+                    )?;
+                    index.record_transaction(
+                        params,
+                        &*db.read().await,
+                        owners,
+                        &tx,
+                        mined_height,
+                    )?;
+                }
+                None => db
+                    .write()
+                    .await
+                    .set_transaction_status(*txid, TransactionStatus::TxidNotRecognized)?,
+            }
+        }
+        TransactionDataRequest::SpendsFromAddress {
+            address,
+            block_range_start,
+            block_range_end,
+        } => {
+            // The requested range excludes its end but lightwalletd's includes it
+            let to_height = match block_range_end {
+                Some(end) => *end - 1,
+                None => match db.read().await.chain_height()? {
+                    Some(tip) => tip,
+                    None => return Ok(()),
+                },
+            };
+            transparent::store_address_transactions(
+                client,
+                params,
+                db,
+                index,
+                owners,
+                address,
+                *block_range_start,
+                to_height,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch a full transaction from lightwalletd along with its status in the main chain.
+///
+/// Unmined transactions are parsed with the consensus rules in effect at `next_height`. Returns `None` if
+/// lightwalletd doesn't know the transaction.
+pub(crate) async fn fetch_transaction<P, ChT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    txid: TxId,
+    next_height: BlockHeight,
+) -> Result<Option<(Transaction, TransactionStatus)>, Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+{
+    let Some((data, status)) = fetch_raw_transaction(client, txid).await? else {
+        return Ok(None);
+    };
+    let branch_height = match status {
+        TransactionStatus::Mined(height) => height,
+        _ => next_height,
+    };
+    let tx = Transaction::read(&data[..], BranchId::for_height(params, branch_height))?;
+    if tx.txid() != txid {
+        return Err(Error::Sync(format!(
+            "lightwalletd returned {} when asked for transaction {}",
+            tx.txid(),
+            txid
+        )));
+    }
+    Ok(Some((tx, status)))
+}
+
+/// Height of the block after the wallet's chain tip
+pub(crate) fn next_block_height<DbT: WalletRead>(db: &DbT) -> Result<BlockHeight, DbT::Error> {
+    Ok(db
+        .chain_height()?
+        .map_or(BlockHeight::from_u32(0), |tip| tip + 1))
+}
+
+async fn fetch_raw_transaction<ChT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    txid: TxId,
+) -> Result<Option<(Vec<u8>, TransactionStatus)>, Error>
+where
+    ChT: LightwalletdChannel,
+{
+    let response = client
+        .get_transaction(TxFilter {
+            hash: txid.as_ref().to_vec(),
+            ..Default::default()
+        })
+        .await;
+    let raw_tx = match response {
+        Ok(response) => response.into_inner(),
+        Err(status) if status.code() == Code::NotFound => return Ok(None),
+        Err(status) => return Err(status.into()),
+    };
+
+    // lightwalletd reports a height of 0 for transactions in the mempool and -1 for ones only in a stale chain
+    let status = match u32::try_from(raw_tx.height) {
+        Ok(0) | Err(_) => TransactionStatus::NotInMainChain,
+        Ok(height) => TransactionStatus::Mined(BlockHeight::from_u32(height)),
+    };
+    Ok(Some((raw_tx.data, status)))
+}
@@ -35,6 +35,12 @@ pub enum Error {
     Grpc(#[from] tonic::Status),
     #[error("Error handling wallet birthday")]
     Birthday,
+    #[error("Invalid birthday. Expected a block height, a Date or undefined")]
+    InvalidBirthday,
+    #[error("No checkpoint at or below the birthday {0}. Add checkpoints or allow the tree state to be requested from lightwalletd, which reveals the birthday to it")]
+    NoBirthdayCheckpoint(u32),
+    #[error("Error parsing checkpoints: {0}")]
+    Checkpoints(#[from] serde_json::Error),
     #[error("Memory client error: {0}")]
     MemoryClient(#[from] zcash_client_memory::Error),
     #[error("Error scanning: {0}")]
@@ -47,6 +53,23 @@ pub enum Error {
     InvalidMinConformations(u32),
     #[error("Error parsing zatoshi amount: {0}")]
     InvalidAmount(#[from] zcash_primitives::transaction::components::amount::BalanceError),
+    #[error(transparent)]
+    Proposal(#[from] crate::diagnostics::ProposalFailure),
+    #[error("Cannot spend {output}: {reason}")]
+    InputNotSpendable {
+        output: String,
+        reason: &'static str,
+    },
+    #[error("The chosen inputs hold {available} Zatoshis but {required} are needed to cover the payments and fee")]
+    InsufficientInputs { available: u64, required: u64 },
+    #[error("Error encoding or decoding proposal: {0}")]
+    ProposalEncoding(String),
+    #[error("Proposal expired at height {expiry_height} and the chain is at {chain_tip}")]
+    ProposalExpired { expiry_height: u32, chain_tip: u32 },
+    #[error("The spending key does not belong to the account the proposal spends from")]
+    SpendingKeyMismatch,
+    #[error("The wallet cannot tell which of its accounts the proposal spends from")]
+    UnknownProposalAccount,
     #[error("Failed to send transaction")]
     SendFailed { code: i32, reason: String },
     #[error("Failed to parse key: {0}")]
@@ -57,6 +80,14 @@ pub enum Error {
     // See: zcash_client_backend::sync::Error
     #[error("Syncing Error: {0}")]
     Sync(String),
+    #[error("Sync was cancelled")]
+    SyncCancelled,
+    #[error("A sync of this wallet is already running")]
+    SyncAlreadyRunning,
+    #[error("Background task failed: {0}")]
+    BackgroundTask(String),
+    #[error("Block cache error: {0}")]
+    BlockCache(#[from] crate::block_cache::BlockCacheError),
 
     #[error("Attempted to create a transaction with a memo to an unsupported recipient. Only shielded addresses are supported.")]
     UnsupportedMemoRecipient,
@@ -68,14 +99,18 @@ pub enum Error {
     Sqlite(#[from] zcash_client_sqlite::error::SqliteClientError),
     #[error("Invalid seed phrase")]
     InvalidSeedPhrase,
-    #[error("Failed when creating transaction")]
-    FailedToCreateTransaction,
+    #[error("Failed when creating transaction: {0}")]
+    FailedToCreateTransaction(String),
     #[error("Failed to serialize db using postcard: {0}")]
     FailedSerialization(#[from] postcard::Error),
     #[error("Account with given id not found: {0}")]
     AccountNotFound(u32),
     #[error("Transaction with given txid not found: {0}")]
     TransactionNotFound(zcash_primitives::transaction::TxId),
+    #[error("Invalid transaction id: {0}")]
+    InvalidTxId(String),
+    #[error("Not a transparent address of this account: {0}")]
+    UnknownTransparentAddress(String),
     #[error("Error constructing ZIP321 transaction request: {0}")]
     Zip321(#[from] zip321::Zip321Error),
     #[error("serde wasm-bindgen error")]
@@ -87,7 +122,14 @@ pub enum Error {
 
 impl From<Error> for JsValue {
     fn from(e: Error) -> Self {
-        js_sys::Error::new(&e.to_string()).into()
+        let error = js_sys::Error::new(&e.to_string());
+        // Lets callers act on why a proposal failed without parsing the message
+        if let Error::Proposal(failure) = &e {
+            if let Ok(details) = serde_wasm_bindgen::to_value(failure) {
+                let _ = js_sys::Reflect::set(&error, &"details".into(), &details);
+            }
+        }
+        error.into()
     }
 }
 
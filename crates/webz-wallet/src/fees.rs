@@ -0,0 +1,227 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Estimating fees and the most an account can send
+//!
+//! Both work by making proposals with the same input selector as [`Wallet::propose_request`], so they agree with what
+//! proposing would do. No proofs are built. The ZIP-317 fee depends on how many notes are spent, which depends on the
+//! amount, so the maximum is found by proposing the account's whole spendable balance and lowering the amount by the
+//! shortfall until the proposal succeeds.
+
+use std::future::Future;
+
+use zcash_address::ZcashAddress;
+use zcash_client_backend::data_api::wallet::propose_transfer;
+use zcash_client_backend::data_api::{self, InputSource, WalletCommitmentTrees, WalletRead};
+use zcash_client_backend::zip321::{Payment, TransactionRequest};
+use zcash_primitives::memo::MemoBytes;
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+
+use crate::change::ChangeOptions;
+use crate::diagnostics::ProposalFailure;
+use crate::error::Error;
+use crate::input_selection::SelectionStrategy;
+use crate::wallet::{proposal_error, LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// Proposals tried before giving up on finding the maximum. Each retry lowers the amount by what the previous proposal
+/// was short, which is normally enough the first time.
+pub const MAX_ATTEMPTS: usize = 8;
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    /// The ZIP-317 fee in Zatoshis of making every payment in `request` from `account_id`, summed over all the
+    /// transactions it would take
+    pub async fn estimate_fee(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+    ) -> Result<u64, Error> {
+        self.estimate_fee_with(
+            account_id,
+            request,
+            &self.change_options(),
+            SelectionStrategy::default(),
+        )
+        .await
+    }
+
+    /// Like [`Wallet::estimate_fee`] but for the proposal [`Wallet::propose_request_with`] would make with `change`
+    /// and `selection`
+    pub async fn estimate_fee_with(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+        change: &ChangeOptions,
+        selection: SelectionStrategy,
+    ) -> Result<u64, Error> {
+        let proposal = self
+            .propose_request_with(account_id, request, change, selection)
+            .await?;
+        Ok(proposal
+            .steps()
+            .iter()
+            .map(|step| u64::from(step.balance().fee_required()))
+            .sum())
+    }
+
+    /// The largest amount in Zatoshis that `account_id` can send to `to_address` after paying the fee
+    pub async fn max_sendable_to_address(
+        &self,
+        account_id: AccountId,
+        to_address: ZcashAddress,
+        memo: Option<MemoBytes>,
+    ) -> Result<u64, Error> {
+        let address = to_address.encode();
+        let payment = Payment::new(
+            to_address,
+            NonNegativeAmount::ZERO,
+            memo,
+            None,
+            None,
+            vec![],
+        )
+        .ok_or(ProposalFailure::MemoToTransparent { address })?;
+        self.max_sendable(account_id, TransactionRequest::new(vec![payment])?, 0)
+            .await
+    }
+
+    ///
+    /// The largest amount in Zatoshis the payment at `payment_index` of `request` can have for `account_id` to still
+    /// make every payment and pay the fee
+    ///
+    /// The amount the request gives for that payment is ignored. Returns zero if the other payments and the fee
+    /// already use up the spendable balance.
+    ///
+    pub async fn max_sendable(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+        payment_index: usize,
+    ) -> Result<u64, Error> {
+        self.max_sendable_with(
+            account_id,
+            request,
+            payment_index,
+            &self.change_options(),
+            SelectionStrategy::default(),
+        )
+        .await
+    }
+
+    /// Like [`Wallet::max_sendable`] but for proposals made by [`Wallet::propose_request_with`] with `change` and
+    /// `selection`
+    pub async fn max_sendable_with(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+        payment_index: usize,
+        change: &ChangeOptions,
+        selection: SelectionStrategy,
+    ) -> Result<u64, Error> {
+        self.check_recipients(&request)?;
+        let mut payments = request.payments().clone();
+        let Some(payment) = payments.remove(&payment_index) else {
+            return Err(Error::Generic(format!(
+                "Transaction request has no payment with index {}",
+                payment_index
+            )));
+        };
+        let others = payments
+            .values()
+            .map(|payment| u64::from(payment.amount()))
+            .sum::<u64>();
+
+        let spendable = match self
+            .db
+            .read()
+            .await
+            .get_wallet_summary(self.min_confirmations.into())?
+        {
+            Some(summary) => summary
+                .account_balances()
+                .get(&account_id)
+                .map_or(0, |balance| {
+                    u64::from(balance.sapling_balance().spendable_value())
+                        + u64::from(balance.orchard_balance().spendable_value())
+                }),
+            None => 0,
+        };
+
+        let input_selector = &self.input_selector(change, selection)?;
+        let payment = &payment;
+        let max = find_max_amount(spendable.saturating_sub(others), |amount| {
+            let mut payments = payments.clone();
+            async move {
+                payments.insert(
+                    payment_index,
+                    Payment::new(
+                        payment.recipient_address().clone(),
+                        NonNegativeAmount::from_u64(amount)?,
+                        payment.memo().cloned(),
+                        payment.label().cloned(),
+                        payment.message().cloned(),
+                        payment.other_params().to_vec(),
+                    )
+                    .ok_or(Error::UnsupportedMemoRecipient)?,
+                );
+                let request = TransactionRequest::from_indexed(payments)?;
+
+                // Proposing needs the database mutably. It is only locked for one proposal at a time so syncing and
+                // reads can go ahead between attempts.
+                let fit = match propose_transfer::<_, _, _, <W as WalletCommitmentTrees>::Error>(
+                    &mut *self.db.write().await,
+                    &self.network,
+                    account_id,
+                    input_selector,
+                    request,
+                    self.min_confirmations,
+                ) {
+                    Ok(_) => AmountFit::Fits,
+                    Err(data_api::error::Error::InsufficientFunds {
+                        available,
+                        required,
+                    }) => AmountFit::Short(u64::from(required).saturating_sub(available.into())),
+                    Err(e) => return Err(proposal_error(e)),
+                };
+                Ok::<_, Error>(fit)
+            }
+        })
+        .await?;
+
+        max.ok_or_else(|| Error::Generic("Could not find the maximum sendable amount".to_string()))
+    }
+}
+
+/// How a proposal of one amount went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountFit {
+    /// The proposal succeeded
+    Fits,
+    /// The proposal needed this many more Zatoshis than the account can spend
+    Short(u64),
+}
+
+/// Search down from `start` for an amount that `propose` reports as fitting, lowering the amount by each shortfall.
+/// Returns zero if the amount runs out and `None` if nothing fits within [`MAX_ATTEMPTS`] proposals.
+pub async fn find_max_amount<E, F, Fut>(start: u64, mut propose: F) -> Result<Option<u64>, E>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<AmountFit, E>>,
+{
+    let mut amount = start;
+    for _ in 0..MAX_ATTEMPTS {
+        if amount == 0 {
+            return Ok(Some(0));
+        }
+        match propose(amount).await? {
+            AmountFit::Fits => return Ok(Some(amount)),
+            AmountFit::Short(shortfall) => amount = amount.saturating_sub(shortfall.max(1)),
+        }
+    }
+    Ok(None)
+}
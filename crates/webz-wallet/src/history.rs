@@ -0,0 +1,1082 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Transaction history
+//!
+//! The wallet database can't list the transactions it stores outside of its test API, so the wallet keeps a
+//! [`TransactionIndex`] of its own. A transaction is recorded as soon as the wallet learns of it: from the compact
+//! blocks it is found in while scanning, and again from the full transaction once that is fetched from lightwalletd
+//! or created by the wallet. For each account the index keeps the net value change per pool, whether the account spent
+//! anything, what it paid to others and the fee, which is enough to filter, order and page through the history. Only
+//! the transactions on the requested page are then decrypted to recover their recipients and memos.
+//!
+//! The index is saved along with the wallet database by [`Wallet::db_to_bytes`]. A wallet saved before the index
+//! existed starts with an empty one and only lists the transactions it scans or fetches from then on.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use webz_common::Network;
+use zcash_client_backend::data_api::{NullifierQuery, ScannedBlock, WalletRead};
+use zcash_client_backend::decrypt::{decrypt_transaction, TransferType};
+use zcash_client_backend::wallet::NoteId;
+use zcash_client_backend::{PoolType, ShieldedProtocol};
+use zcash_keys::address::{Address, UnifiedAddress};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_primitives::consensus::{BlockHeight, Parameters};
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::memo::{Memo, MemoBytes};
+use zcash_primitives::transaction::components::{OutPoint, TxOut};
+use zcash_primitives::transaction::{Transaction, TxId};
+
+use crate::error::Error;
+use crate::memos::MemoDirection;
+use crate::transparent::COINBASE_MATURITY;
+use crate::wallet::{LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// Every transaction the wallet has seen and what it did to each of the wallet's accounts. See the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct TransactionIndex<AccountId>(RwLock<IndexData<AccountId>>);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexData<AccountId> {
+    /// Keyed by transaction ID
+    transactions: HashMap<[u8; 32], IndexedTransaction<AccountId>>,
+    /// Times of the blocks containing wallet transactions, keyed by height
+    block_times: BTreeMap<u32, u32>,
+    /// Values of the notes the wallet's accounts received, keyed by nullifier, so spends can be valued
+    note_values: HashMap<[u8; 32], u64>,
+    /// Heights from which the wallet's coinbase outputs can be spent, keyed by transaction ID and output index
+    coinbase_maturity: HashMap<([u8; 32], u32), u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedTransaction<AccountId> {
+    expiry_height: Option<u32>,
+    /// Height of the block the transaction was mined in, cleared again if the wallet is rewound below it
+    mined_height: Option<u32>,
+    /// Whether this was worked out from the full transaction. Compact blocks leave out outgoing outputs, fees and the
+    /// transparent parts of a transaction, so until the full transaction is seen this is only what scanning found.
+    complete: bool,
+    accounts: Vec<AccountEffect<AccountId>>,
+}
+
+/// What a transaction did to one account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AccountEffect<AccountId> {
+    pub(crate) account_id: AccountId,
+    pub(crate) pool_deltas: PoolDeltas,
+    /// Whether the account spent any of its funds, shielded or transparent
+    pub(crate) spent_funds: bool,
+    /// Value paid to addresses outside the wallet
+    pub(crate) sent: u64,
+    /// Fee of the transaction, if the account paid it and it is known
+    pub(crate) fee: Option<u64>,
+    /// Shielded outputs the account received or sent
+    pub(crate) outputs: Vec<IndexedOutput>,
+}
+
+impl<AccountId> AccountEffect<AccountId> {
+    fn new(account_id: AccountId) -> Self {
+        Self {
+            account_id,
+            pool_deltas: PoolDeltas::default(),
+            spent_funds: false,
+            sent: 0,
+            fee: None,
+            outputs: vec![],
+        }
+    }
+
+    /// Net change in the account's balance, fee included
+    pub(crate) fn value(&self) -> i64 {
+        self.pool_deltas.transparent + self.pool_deltas.sapling + self.pool_deltas.orchard
+    }
+
+    pub(crate) fn direction(&self) -> Direction {
+        if !self.spent_funds {
+            Direction::Received
+        } else if self.sent > 0 {
+            Direction::Sent
+        } else {
+            Direction::SelfTransfer
+        }
+    }
+}
+
+/// A shielded output an account received or sent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct IndexedOutput {
+    pub(crate) pool: Pool,
+    /// Index of the output within the pool's bundle: the Sapling output or Orchard action index
+    pub(crate) output_index: u32,
+    pub(crate) direction: MemoDirection,
+    pub(crate) value: u64,
+    /// Nullifier of a received note, known once the block it was mined in has been scanned
+    pub(crate) nullifier: Option<[u8; 32]>,
+}
+
+impl IndexedOutput {
+    /// ID of the note this output created in transaction `txid`
+    pub(crate) fn note_id(&self, txid: TxId) -> NoteId {
+        let protocol = match self.pool {
+            Pool::Orchard => ShieldedProtocol::Orchard,
+            _ => ShieldedProtocol::Sapling,
+        };
+        NoteId::new(txid, protocol, self.output_index as u16)
+    }
+}
+
+/// A transaction as recorded for one account
+#[derive(Debug, Clone)]
+pub(crate) struct AccountTransaction<AccountId> {
+    pub(crate) txid: TxId,
+    pub(crate) expiry_height: Option<BlockHeight>,
+    pub(crate) mined_height: Option<BlockHeight>,
+    /// Whether the effect was worked out from the full transaction rather than only from compact blocks
+    pub(crate) complete: bool,
+    pub(crate) effect: AccountEffect<AccountId>,
+}
+
+impl<AccountId> Default for TransactionIndex<AccountId> {
+    fn default() -> Self {
+        Self(RwLock::new(IndexData {
+            transactions: HashMap::new(),
+            block_times: BTreeMap::new(),
+            note_values: HashMap::new(),
+            coinbase_maturity: HashMap::new(),
+        }))
+    }
+}
+
+impl<AccountId: Copy + Eq + Hash> TransactionIndex<AccountId> {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, postcard::Error>
+    where
+        AccountId: DeserializeOwned,
+    {
+        Ok(Self(RwLock::new(postcard::from_bytes(bytes)?)))
+    }
+
+    /// Postcard encode the index, as [`Wallet::db_to_bytes`] does after the wallet database
+    pub fn to_bytes(&self) -> Result<Vec<u8>, postcard::Error>
+    where
+        AccountId: Serialize,
+    {
+        postcard::to_allocvec(&*self.read())
+    }
+
+    /// Record what scanning found once it has been committed to the wallet
+    pub(crate) fn record_scanned(&self, scanned: ScannedTransactions<AccountId>) {
+        let mut data = self.write();
+        for (height, time) in scanned.block_times {
+            data.block_times.insert(height.into(), time);
+        }
+
+        for tx in scanned.transactions {
+            for (_, output) in &tx.outputs {
+                if let Some(nf) = output.nullifier {
+                    data.note_values.insert(nf, output.value);
+                }
+            }
+
+            let key = *tx.txid.as_ref();
+            if let Some(indexed) = data
+                .transactions
+                .get_mut(&key)
+                .filter(|indexed| indexed.complete)
+            {
+                // Recorded in full before it was mined, e.g. by the wallet that created it. Only where it was mined
+                // and the nullifiers of the notes it created are new.
+                indexed.mined_height = Some(tx.height.into());
+                for (account_id, output) in &tx.outputs {
+                    if let Some(recorded) = find_output(&mut indexed.accounts, *account_id, output)
+                    {
+                        recorded.nullifier = output.nullifier;
+                    }
+                }
+                continue;
+            }
+
+            let mut effects = Effects::new();
+            for (account_id, pool, nf) in tx.spends {
+                let value = data.note_values.get(&nf).copied().unwrap_or_default();
+                let effect = effects.of(account_id);
+                effect.spent_funds = true;
+                effect.pool_deltas.add(pool, -(value as i64));
+            }
+            for (account_id, output) in tx.outputs {
+                let effect = effects.of(account_id);
+                effect.pool_deltas.add(output.pool, output.value as i64);
+                effect.outputs.push(output);
+            }
+            let expiry_height = data
+                .transactions
+                .get(&key)
+                .and_then(|indexed| indexed.expiry_height);
+            data.transactions.insert(
+                key,
+                IndexedTransaction {
+                    expiry_height,
+                    mined_height: Some(tx.height.into()),
+                    complete: false,
+                    accounts: effects.into_vec(),
+                },
+            );
+        }
+    }
+
+    /// Record a full transaction, replacing whatever scanning found out about it.
+    ///
+    /// Transparent inputs are valued from the transactions that created them, which the wallet stores for every
+    /// output sent to one of its addresses. Shielded spends are valued from the notes recorded while scanning.
+    pub fn record_transaction<P, DbT>(
+        &self,
+        params: &P,
+        db: &DbT,
+        owners: &Owners<AccountId>,
+        tx: &Transaction,
+        mined_height: Option<BlockHeight>,
+    ) -> Result<(), DbT::Error>
+    where
+        P: Parameters,
+        DbT: WalletRead<AccountId = AccountId>,
+    {
+        let mut effects = Effects::new();
+
+        // `None` once an input turns out not to be known, in which case the fee can't be worked out
+        let mut transparent_in = Some(0u64);
+        let mut transparent_out = 0u64;
+        let mut paid_out = 0u64;
+        if let Some(bundle) = tx.transparent_bundle() {
+            if bundle.is_coinbase() {
+                transparent_in = None;
+            } else {
+                for txin in &bundle.vin {
+                    let prevout = previous_output(db, &txin.prevout)?;
+                    transparent_in = transparent_in
+                        .zip(prevout.as_ref())
+                        .map(|(sum, prevout)| sum + u64::from(prevout.value));
+                    let owner = prevout
+                        .as_ref()
+                        .and_then(|prevout| owners.address_owner(prevout.recipient_address()?));
+                    if let (Some(account_id), Some(prevout)) = (owner, prevout) {
+                        let effect = effects.of(account_id);
+                        effect.spent_funds = true;
+                        effect
+                            .pool_deltas
+                            .add(Pool::Transparent, -(u64::from(prevout.value) as i64));
+                    }
+                }
+            }
+            for output in &bundle.vout {
+                let value = u64::from(output.value);
+                transparent_out += value;
+                match output
+                    .recipient_address()
+                    .and_then(|address| owners.address_owner(address))
+                {
+                    Some(account_id) => effects
+                        .of(account_id)
+                        .pool_deltas
+                        .add(Pool::Transparent, value as i64),
+                    None => paid_out += value,
+                }
+            }
+        }
+
+        let sapling_spends = tx.sapling_bundle().into_iter().flat_map(|bundle| {
+            bundle
+                .shielded_spends()
+                .iter()
+                .map(|spend| (Pool::Sapling, spend.nullifier().0))
+        });
+        let orchard_spends = tx.orchard_bundle().into_iter().flat_map(|bundle| {
+            bundle
+                .actions()
+                .iter()
+                .map(|action| (Pool::Orchard, action.nullifier().to_bytes()))
+        });
+        for (pool, nf) in sapling_spends.chain(orchard_spends) {
+            if let Some(account_id) = owners.nullifiers.get(&nf) {
+                let value = self
+                    .read()
+                    .note_values
+                    .get(&nf)
+                    .copied()
+                    .unwrap_or_default();
+                let effect = effects.of(*account_id);
+                effect.spent_funds = true;
+                effect.pool_deltas.add(pool, -(value as i64));
+            }
+        }
+
+        let decrypted =
+            decrypt_transaction(params, mined_height, owners.chain_tip, tx, &owners.ufvks);
+        let sapling_outputs = decrypted.sapling_outputs().iter().map(|output| {
+            (
+                *output.account(),
+                Pool::Sapling,
+                output.index(),
+                output.transfer_type(),
+                output.note().value().inner(),
+            )
+        });
+        let orchard_outputs = decrypted.orchard_outputs().iter().map(|output| {
+            (
+                *output.account(),
+                Pool::Orchard,
+                output.index(),
+                output.transfer_type(),
+                output.note().value().inner(),
+            )
+        });
+        for (account_id, pool, index, transfer_type, value) in
+            sapling_outputs.chain(orchard_outputs)
+        {
+            let effect = effects.of(account_id);
+            match transfer_type {
+                TransferType::Outgoing => effect.sent += value,
+                TransferType::Incoming | TransferType::WalletInternal => {
+                    effect.pool_deltas.add(pool, value as i64)
+                }
+            }
+            effect.outputs.push(IndexedOutput {
+                pool,
+                output_index: index as u32,
+                direction: transfer_type.into(),
+                value,
+                nullifier: None,
+            });
+        }
+
+        let key = *tx.txid().as_ref();
+        let mut accounts = effects.into_vec();
+        if accounts.is_empty() {
+            return Ok(());
+        }
+
+        // Transparent outputs to addresses outside the wallet are paid by whoever funded the transaction
+        let value_balance = tx
+            .sapling_bundle()
+            .map_or(0, |bundle| i64::from(*bundle.value_balance()))
+            + tx.orchard_bundle()
+                .map_or(0, |bundle| i64::from(*bundle.value_balance()));
+        let fee = transparent_in
+            .map(|transparent_in| transparent_in as i64 - transparent_out as i64 + value_balance)
+            .and_then(|fee| u64::try_from(fee).ok());
+        for effect in accounts.iter_mut().filter(|effect| effect.spent_funds) {
+            effect.sent += paid_out;
+            effect.fee = fee;
+        }
+
+        // Keep the nullifiers scanning already found for the notes this transaction created, and where it was mined
+        let mut data = self.write();
+        let mut mined_height = mined_height.map(u32::from);
+        if let Some(previous) = data.transactions.get(&key) {
+            mined_height = mined_height.or(previous.mined_height);
+            for effect in &previous.accounts {
+                for output in &effect.outputs {
+                    if let Some(recorded) = find_output(&mut accounts, effect.account_id, output) {
+                        recorded.nullifier = output.nullifier;
+                    }
+                }
+            }
+        }
+
+        data.transactions.insert(
+            key,
+            IndexedTransaction {
+                expiry_height: Some(u32::from(tx.expiry_height())).filter(|height| *height != 0),
+                mined_height,
+                complete: true,
+                accounts,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forget the block times and coinbase outputs above `height` after the wallet has been rewound to it. The
+    /// transactions mined above it are unmined again until scanning finds them in the new chain.
+    pub fn truncate(&self, height: BlockHeight) {
+        let mut data = self.write();
+        data.block_times.split_off(&(u32::from(height) + 1));
+        for indexed in data.transactions.values_mut() {
+            if indexed
+                .mined_height
+                .is_some_and(|mined_height| mined_height > u32::from(height))
+            {
+                indexed.mined_height = None;
+            }
+        }
+        let max_maturity = u32::from(height) + COINBASE_MATURITY;
+        data.coinbase_maturity
+            .retain(|_, maturity_height| *maturity_height <= max_maturity);
+    }
+
+    /// Remember that `outpoint` is a coinbase output which can be spent from `maturity_height`
+    pub(crate) fn insert_coinbase(&self, outpoint: &OutPoint, maturity_height: BlockHeight) {
+        self.write()
+            .coinbase_maturity
+            .insert((*outpoint.hash(), outpoint.n()), maturity_height.into());
+    }
+
+    /// Heights from which the wallet's coinbase outputs can be spent
+    pub(crate) fn coinbase_maturity(&self) -> HashMap<OutPoint, BlockHeight> {
+        self.read()
+            .coinbase_maturity
+            .iter()
+            .map(|((txid, n), height)| (OutPoint::new(*txid, *n), BlockHeight::from_u32(*height)))
+            .collect()
+    }
+
+    pub(crate) fn block_time(&self, height: BlockHeight) -> Option<u32> {
+        self.read().block_times.get(&u32::from(height)).copied()
+    }
+
+    /// Remember the time of the block at `height`
+    pub fn insert_block_time(&self, height: BlockHeight, time: u32) {
+        self.write().block_times.insert(height.into(), time);
+    }
+
+    /// The account that received the note `note_id`, if the index has its transaction
+    pub(crate) fn note_owner(&self, note_id: &NoteId) -> Option<AccountId> {
+        let data = self.read();
+        let indexed = data.transactions.get(note_id.txid().as_ref())?;
+        indexed
+            .accounts
+            .iter()
+            .find(|effect| {
+                effect.outputs.iter().any(|output| {
+                    output.direction != MemoDirection::Sent
+                        && output.note_id(*note_id.txid()) == *note_id
+                })
+            })
+            .map(|effect| effect.account_id)
+    }
+
+    /// The transactions that involve `account_id`, in no particular order
+    pub(crate) fn account_transactions(
+        &self,
+        account_id: AccountId,
+    ) -> Vec<AccountTransaction<AccountId>> {
+        self.read()
+            .transactions
+            .iter()
+            .filter_map(|(txid, indexed)| {
+                let effect = indexed
+                    .accounts
+                    .iter()
+                    .find(|effect| effect.account_id == account_id)?;
+                Some(AccountTransaction {
+                    txid: TxId::from_bytes(*txid),
+                    expiry_height: indexed.expiry_height.map(BlockHeight::from_u32),
+                    mined_height: indexed.mined_height.map(BlockHeight::from_u32),
+                    complete: indexed.complete,
+                    effect: effect.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, IndexData<AccountId>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, IndexData<AccountId>> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The effects of one transaction on each account it involves, in the order the accounts were first seen
+struct Effects<AccountId>(Vec<AccountEffect<AccountId>>);
+
+impl<AccountId: Copy + Eq> Effects<AccountId> {
+    fn new() -> Self {
+        Self(vec![])
+    }
+
+    fn of(&mut self, account_id: AccountId) -> &mut AccountEffect<AccountId> {
+        let i = match self
+            .0
+            .iter()
+            .position(|effect| effect.account_id == account_id)
+        {
+            Some(i) => i,
+            None => {
+                self.0.push(AccountEffect::new(account_id));
+                self.0.len() - 1
+            }
+        };
+        &mut self.0[i]
+    }
+
+    fn into_vec(self) -> Vec<AccountEffect<AccountId>> {
+        self.0
+    }
+}
+
+fn find_output<'a, AccountId: Eq>(
+    accounts: &'a mut [AccountEffect<AccountId>],
+    account_id: AccountId,
+    output: &IndexedOutput,
+) -> Option<&'a mut IndexedOutput> {
+    accounts
+        .iter_mut()
+        .filter(|effect| effect.account_id == account_id)
+        .flat_map(|effect| &mut effect.outputs)
+        .find(|recorded| {
+            recorded.pool == output.pool && recorded.output_index == output.output_index
+        })
+}
+
+/// The output spent by a transparent input, if the wallet has the transaction that created it
+fn previous_output<DbT: WalletRead>(
+    db: &DbT,
+    outpoint: &OutPoint,
+) -> Result<Option<TxOut>, DbT::Error> {
+    Ok(db
+        .get_transaction(TxId::from_bytes(*outpoint.hash()))?
+        .and_then(|tx| {
+            tx.transparent_bundle()?
+                .vout
+                .get(outpoint.n() as usize)
+                .cloned()
+        }))
+}
+
+/// Which account the wallet's viewing keys, nullifiers and transparent addresses belong to. Read once and used to
+/// record any number of transactions.
+pub struct Owners<AccountId> {
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    nullifiers: HashMap<[u8; 32], AccountId>,
+    addresses: HashMap<TransparentAddress, AccountId>,
+    chain_tip: Option<BlockHeight>,
+}
+
+impl<AccountId: Copy + Eq + Hash> Owners<AccountId> {
+    pub fn read<DbT>(db: &DbT) -> Result<Self, DbT::Error>
+    where
+        DbT: WalletRead<AccountId = AccountId>,
+    {
+        let ufvks = db.get_unified_full_viewing_keys()?;
+        let mut nullifiers = HashMap::new();
+        for (account_id, nf) in db.get_sapling_nullifiers(NullifierQuery::All)? {
+            nullifiers.insert(nf.0, account_id);
+        }
+        for (account_id, nf) in db.get_orchard_nullifiers(NullifierQuery::All)? {
+            nullifiers.insert(nf.to_bytes(), account_id);
+        }
+        let mut addresses = HashMap::new();
+        for account_id in ufvks.keys() {
+            for address in db.get_transparent_receivers(*account_id)?.into_keys() {
+                addresses.insert(address, *account_id);
+            }
+            for (address, _) in db.get_known_ephemeral_addresses(*account_id, None)? {
+                addresses.insert(address, *account_id);
+            }
+        }
+        Ok(Self {
+            ufvks,
+            nullifiers,
+            addresses,
+            chain_tip: db.chain_height()?,
+        })
+    }
+
+    fn address_owner(&self, address: TransparentAddress) -> Option<AccountId> {
+        self.addresses.get(&address).copied()
+    }
+}
+
+/// What scanning found in a batch, taken from the scanned blocks before they are handed to the wallet
+pub(crate) struct ScannedTransactions<AccountId> {
+    block_times: Vec<(BlockHeight, u32)>,
+    transactions: Vec<ScannedTransaction<AccountId>>,
+}
+
+struct ScannedTransaction<AccountId> {
+    txid: TxId,
+    height: BlockHeight,
+    /// Nullifiers of the notes the accounts spent
+    spends: Vec<(AccountId, Pool, [u8; 32])>,
+    outputs: Vec<(AccountId, IndexedOutput)>,
+}
+
+impl<AccountId: Copy> ScannedTransactions<AccountId> {
+    pub(crate) fn new(blocks: &[ScannedBlock<AccountId>]) -> Self {
+        let mut scanned = ScannedTransactions {
+            block_times: vec![],
+            transactions: vec![],
+        };
+        for block in blocks {
+            if !block.transactions().is_empty() {
+                scanned
+                    .block_times
+                    .push((block.height(), block.block_time()));
+            }
+            for tx in block.transactions() {
+                let sapling_spends = tx
+                    .sapling_spends()
+                    .iter()
+                    .map(|spend| (*spend.account_id(), Pool::Sapling, spend.nf().0));
+                let orchard_spends = tx
+                    .orchard_spends()
+                    .iter()
+                    .map(|spend| (*spend.account_id(), Pool::Orchard, spend.nf().to_bytes()));
+                let sapling_outputs = tx.sapling_outputs().iter().map(|output| {
+                    (
+                        *output.account_id(),
+                        IndexedOutput {
+                            pool: Pool::Sapling,
+                            output_index: output.index() as u32,
+                            direction: scanned_direction(output.is_change()),
+                            value: output.note().value().inner(),
+                            nullifier: output.nf().map(|nf| nf.0),
+                        },
+                    )
+                });
+                let orchard_outputs = tx.orchard_outputs().iter().map(|output| {
+                    (
+                        *output.account_id(),
+                        IndexedOutput {
+                            pool: Pool::Orchard,
+                            output_index: output.index() as u32,
+                            direction: scanned_direction(output.is_change()),
+                            value: output.note().value().inner(),
+                            nullifier: output.nf().map(|nf| nf.to_bytes()),
+                        },
+                    )
+                });
+                scanned.transactions.push(ScannedTransaction {
+                    txid: *tx.txid(),
+                    height: block.height(),
+                    spends: sapling_spends.chain(orchard_spends).collect(),
+                    outputs: sapling_outputs.chain(orchard_outputs).collect(),
+                });
+            }
+        }
+        scanned
+    }
+}
+
+fn scanned_direction(is_change: bool) -> MemoDirection {
+    if is_change {
+        MemoDirection::Internal
+    } else {
+        MemoDirection::Received
+    }
+}
+
+/// Which way value moved in a transaction, from the point of view of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// The account received funds from someone else
+    Received,
+    /// The account paid someone else
+    Sent,
+    /// Funds only moved between the account's own addresses, e.g. shielding
+    SelfTransfer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status")]
+pub enum TransactionStatus {
+    Mined {
+        height: u32,
+    },
+    /// Not yet mined but can still be
+    Pending,
+    /// Not mined before its expiry height
+    Expired,
+}
+
+/// Net change in an account's balance in each pool. These sum to the overall change, fee included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolDeltas {
+    pub transparent: i64,
+    pub sapling: i64,
+    pub orchard: i64,
+}
+
+impl PoolDeltas {
+    fn add(&mut self, pool: Pool, value: i64) {
+        match pool {
+            Pool::Transparent => self.transparent += value,
+            Pool::Sapling => self.sapling += value,
+            Pool::Orchard => self.orchard += value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pool {
+    Transparent,
+    Sapling,
+    Orchard,
+}
+
+impl From<PoolType> for Pool {
+    fn from(pool: PoolType) -> Self {
+        match pool {
+            PoolType::Transparent => Pool::Transparent,
+            PoolType::Shielded(ShieldedProtocol::Sapling) => Pool::Sapling,
+            PoolType::Shielded(ShieldedProtocol::Orchard) => Pool::Orchard,
+        }
+    }
+}
+
+/// An output of a transaction paying someone other than the account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipient {
+    pub address: String,
+    pub pool: Pool,
+    pub value: u64,
+    /// Text memo attached to the output, if any
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionHistoryEntry {
+    /// Transaction ID in the byte order used by block explorers
+    pub txid: String,
+    pub status: TransactionStatus,
+    /// Unix timestamp of the block the transaction was mined in
+    pub block_time: Option<u32>,
+    pub direction: Direction,
+    /// Whether the full transaction has been seen. Until then only what scanning found is known: no fee, no
+    /// recipients and no memos, and a transaction that paid someone else is listed as a
+    /// [`Direction::SelfTransfer`].
+    pub complete: bool,
+    /// Net change in the account's balance, fee included
+    pub value: i64,
+    pub pool_deltas: PoolDeltas,
+    /// Fee paid, if the account paid it and it is known
+    pub fee: Option<u64>,
+    pub recipients: Vec<Recipient>,
+    /// Text memos of the outputs the account can see, including its own
+    pub memos: Vec<String>,
+    pub expiry_height: Option<u32>,
+}
+
+/// Criteria for [`Wallet::get_transaction_history`]. Every field that is set has to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryFilter {
+    /// Lowest mined height to include. Unmined transactions are excluded if this is set.
+    pub from_height: Option<u32>,
+    /// Highest mined height to include
+    pub to_height: Option<u32>,
+    /// Earliest block time to include. Unmined transactions are excluded if this is set.
+    pub from_time: Option<u32>,
+    /// Latest block time to include
+    pub to_time: Option<u32>,
+    pub direction: Option<Direction>,
+    /// Smallest absolute net value change to include
+    pub min_value: Option<u64>,
+    /// Only include transactions with a text memo containing this, ignoring case. Memos are only known once a
+    /// transaction is decrypted, so this is checked against every transaction matching the other criteria.
+    pub memo_contains: Option<String>,
+}
+
+impl HistoryFilter {
+    /// Checks against what the index records, which need neither block times nor decrypting the transaction
+    fn matches_indexed<AccountId>(
+        &self,
+        mined_height: Option<BlockHeight>,
+        effect: &AccountEffect<AccountId>,
+    ) -> bool {
+        let height = mined_height.map(u32::from);
+
+        (self.from_height.is_none() && self.to_height.is_none()
+            || height.is_some_and(|h| {
+                self.from_height.map_or(true, |from| h >= from)
+                    && self.to_height.map_or(true, |to| h <= to)
+            }))
+            && self
+                .min_value
+                .map_or(true, |min| effect.value().unsigned_abs() >= min)
+            && self.direction.map_or(true, |d| effect.direction() == d)
+    }
+
+    fn filters_time(&self) -> bool {
+        self.from_time.is_some() || self.to_time.is_some()
+    }
+
+    fn matches_time(&self, block_time: Option<u32>) -> bool {
+        !self.filters_time()
+            || block_time.is_some_and(|t| {
+                self.from_time.map_or(true, |from| t >= from)
+                    && self.to_time.map_or(true, |to| t <= to)
+            })
+    }
+
+    fn matches_memos(&self, memos: &[String]) -> bool {
+        self.memo_contains.as_ref().map_or(true, |needle| {
+            let needle = needle.to_lowercase();
+            memos
+                .iter()
+                .any(|memo| memo.to_lowercase().contains(&needle))
+        })
+    }
+}
+
+/// One page of transaction history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub entries: Vec<TransactionHistoryEntry>,
+    /// Number of entries matching the filter across all pages
+    pub total: usize,
+}
+
+/// What building history entries needs to know about the account
+struct HistoryAccount<AccountId> {
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    /// The account's transparent and ephemeral addresses
+    addresses: HashSet<TransparentAddress>,
+    chain_tip: Option<BlockHeight>,
+}
+
+impl<W, T, AccountId> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    /// Transactions involving `account_id` that match `filter`, newest first with unmined transactions at the top.
+    ///
+    /// Returns up to `limit` entries after skipping the first `offset`, along with the total number of matches. Block
+    /// times the wallet didn't see while scanning are fetched from lightwalletd for the entries returned, or for
+    /// every candidate if `filter` has a time range.
+    pub async fn get_transaction_history(
+        &self,
+        account_id: AccountId,
+        filter: &HistoryFilter,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<HistoryPage, Error> {
+        let (account, mut candidates) = {
+            let db = self.db.read().await;
+            let Some(ufvk) = db.get_unified_full_viewing_keys()?.remove(&account_id) else {
+                return Ok(HistoryPage {
+                    entries: vec![],
+                    total: 0,
+                });
+            };
+
+            let mut candidates = vec![];
+            for tx in self.tx_index.account_transactions(account_id) {
+                let mined_height = tx.mined_height;
+                if filter.matches_indexed(mined_height, &tx.effect) {
+                    candidates.push((tx, mined_height));
+                }
+            }
+
+            let mut addresses: HashSet<_> = db
+                .get_transparent_receivers(account_id)?
+                .into_keys()
+                .collect();
+            addresses.extend(
+                db.get_known_ephemeral_addresses(account_id, None)?
+                    .into_iter()
+                    .map(|(address, _)| address),
+            );
+            let account = HistoryAccount {
+                ufvks: HashMap::from([(account_id, ufvk)]),
+                addresses,
+                chain_tip: db.chain_height()?,
+            };
+            (account, candidates)
+        };
+        // The transaction ID breaks ties so that pages never overlap
+        candidates.sort_by_key(|(tx, mined_height)| {
+            (
+                Reverse(mined_height.map_or(u32::MAX, u32::from)),
+                *tx.txid.as_ref(),
+            )
+        });
+
+        if filter.filters_time() {
+            let mut matching = vec![];
+            for (tx, mined_height) in candidates {
+                if filter.matches_time(self.mined_block_time(mined_height).await?) {
+                    matching.push((tx, mined_height));
+                }
+            }
+            candidates = matching;
+        }
+
+        if filter.memo_contains.is_none() {
+            let total = candidates.len();
+            let mut entries = vec![];
+            for (tx, mined_height) in candidates
+                .into_iter()
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX))
+            {
+                entries.push(self.history_entry(&account, &tx, mined_height).await?);
+            }
+            return Ok(HistoryPage { entries, total });
+        }
+
+        let mut entries = vec![];
+        for (tx, mined_height) in candidates {
+            let entry = self.history_entry(&account, &tx, mined_height).await?;
+            if filter.matches_memos(&entry.memos) {
+                entries.push(entry);
+            }
+        }
+        let total = entries.len();
+        Ok(HistoryPage {
+            entries: entries
+                .into_iter()
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX))
+                .collect(),
+            total,
+        })
+    }
+
+    /// Time of the block mined at `height`, fetched from lightwalletd and kept in the index if it isn't there yet
+    async fn mined_block_time(&self, height: Option<BlockHeight>) -> Result<Option<u32>, Error> {
+        let Some(height) = height else {
+            return Ok(None);
+        };
+        if let Some(time) = self.tx_index.block_time(height) {
+            return Ok(Some(time));
+        }
+        let time = u32::try_from(self.fetch_block_time(height.into()).await?)
+            .map_err(|_| Error::Sync("lightwalletd returned an invalid block time".to_string()))?;
+        self.tx_index.insert_block_time(height, time);
+        Ok(Some(time))
+    }
+
+    async fn history_entry(
+        &self,
+        account: &HistoryAccount<AccountId>,
+        tx: &AccountTransaction<AccountId>,
+        mined_height: Option<BlockHeight>,
+    ) -> Result<TransactionHistoryEntry, Error> {
+        let block_time = self.mined_block_time(mined_height).await?;
+        let full_tx = self.db.read().await.get_transaction(tx.txid)?;
+        let (recipients, memos) = match &full_tx {
+            Some(full_tx) => recipients_and_memos(
+                &self.network,
+                account,
+                full_tx,
+                mined_height,
+                tx.effect.spent_funds,
+            ),
+            // Only seen in a compact block so far
+            None => (vec![], vec![]),
+        };
+
+        let status = match mined_height {
+            Some(height) => TransactionStatus::Mined {
+                height: height.into(),
+            },
+            None if tx
+                .expiry_height
+                .zip(account.chain_tip)
+                .is_some_and(|(expiry, tip)| tip >= expiry) =>
+            {
+                TransactionStatus::Expired
+            }
+            None => TransactionStatus::Pending,
+        };
+
+        Ok(TransactionHistoryEntry {
+            txid: tx.txid.to_string(),
+            status,
+            block_time,
+            direction: tx.effect.direction(),
+            complete: tx.complete,
+            value: tx.effect.value(),
+            pool_deltas: tx.effect.pool_deltas,
+            fee: tx.effect.fee,
+            recipients,
+            memos,
+            expiry_height: tx.expiry_height.map(u32::from),
+        })
+    }
+}
+
+/// Decrypt `tx` with the account's viewing key to find who it paid and the text memos the account can read
+fn recipients_and_memos<AccountId: Copy + Eq + Hash>(
+    network: &Network,
+    account: &HistoryAccount<AccountId>,
+    tx: &Transaction,
+    mined_height: Option<BlockHeight>,
+    spent_funds: bool,
+) -> (Vec<Recipient>, Vec<String>) {
+    let mut recipients = vec![];
+    let mut memos = vec![];
+
+    let decrypted =
+        decrypt_transaction(network, mined_height, account.chain_tip, tx, &account.ufvks);
+    for output in decrypted.sapling_outputs() {
+        let memo = text_memo(output.memo());
+        if matches!(output.transfer_type(), TransferType::Outgoing) {
+            recipients.push(Recipient {
+                address: Address::Sapling(output.note().recipient()).encode(network),
+                pool: Pool::Sapling,
+                value: output.note().value().inner(),
+                memo: memo.clone(),
+            });
+        }
+        memos.extend(memo);
+    }
+    for output in decrypted.orchard_outputs() {
+        let memo = text_memo(output.memo());
+        if matches!(output.transfer_type(), TransferType::Outgoing) {
+            recipients.push(Recipient {
+                address: UnifiedAddress::from_receivers(
+                    Some(output.note().recipient()),
+                    None,
+                    None,
+                )
+                .map(|ua| ua.encode(network))
+                .unwrap_or_default(),
+                pool: Pool::Orchard,
+                value: output.note().value().inner(),
+                memo: memo.clone(),
+            });
+        }
+        memos.extend(memo);
+    }
+
+    // Transparent outputs to addresses outside the account are paid by the account if it funded the transaction
+    if spent_funds {
+        for output in tx
+            .transparent_bundle()
+            .into_iter()
+            .flat_map(|bundle| &bundle.vout)
+        {
+            if let Some(address) = output
+                .recipient_address()
+                .filter(|address| !account.addresses.contains(address))
+            {
+                recipients.push(Recipient {
+                    address: Address::Transparent(address).encode(network),
+                    pool: Pool::Transparent,
+                    value: output.value.into(),
+                    memo: None,
+                });
+            }
+        }
+    }
+
+    (recipients, memos)
+}
+
+fn text_memo(memo: &MemoBytes) -> Option<String> {
+    match Memo::try_from(memo.clone()) {
+        Ok(Memo::Text(text)) => Some(text.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Choosing which notes a transaction spends
+//!
+//! The backend's [`GreedyInputSelector`] works out the fee and change, asking the wallet database for notes worth at
+//! least some amount each time it finds it needs more. [`StrategyInputSelector`] runs it against a view of the
+//! database that answers with the notes a [`SelectionStrategy`] picks instead, so the strategies only have to decide
+//! which notes cover an amount. That choice is made by [`select_notes`], which only looks at a [`NoteCandidate`]
+//! summary of each note.
+//!
+//! The same view can instead answer with exactly the notes or transparent outputs a caller chose, for coin control.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::data_api::wallet::input_selection::{
+    GreedyInputSelector, GreedyInputSelectorError, InputSelector, InputSelectorError,
+    ShieldingSelector,
+};
+use zcash_client_backend::data_api::{InputSource, SpendableNotes, WalletRead};
+use zcash_client_backend::fees::{ChangeStrategy, DustOutputPolicy};
+use zcash_client_backend::proposal::Proposal;
+use zcash_client_backend::wallet::{Note, NoteId, ReceivedNote, WalletTransparentOutput};
+use zcash_client_backend::zip321::TransactionRequest;
+use zcash_client_backend::ShieldedProtocol;
+use zcash_primitives::consensus::{self, BlockHeight};
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::transaction::components::amount::{NonNegativeAmount, MAX_MONEY};
+use zcash_primitives::transaction::components::OutPoint;
+use zcash_primitives::transaction::TxId;
+
+use crate::history::Pool;
+
+/// How the notes a transaction spends are chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SelectionStrategy {
+    /// The wallet database's own order
+    #[default]
+    Greedy,
+    /// As few notes as possible, without spending a larger note than needed for the last one
+    MinimizeInputs,
+    /// Notes from only one pool, so that the transaction never moves value between Sapling and Orchard and reveals the
+    /// amount crossing the turnstile. Orchard is used if it holds enough.
+    SinglePool,
+    /// The notes mined longest ago first
+    OldestFirst,
+    /// Notes with at least `confirmations` confirmations first, then newer ones if those aren't enough
+    PreferConfirmed { confirmations: u32 },
+}
+
+/// What a strategy knows about a spendable note
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteCandidate {
+    pub pool: Pool,
+    pub value: u64,
+    pub mined_height: Option<u32>,
+    /// Number of blocks mined on top of the note's block, counting that block itself
+    pub confirmations: u32,
+}
+
+///
+/// Choose which of `notes` to spend to cover `target` Zatoshis, returning their indices in the order they were picked
+///
+/// If the notes can't cover `target`, every note the strategy is allowed to spend is returned so that the shortfall is
+/// reported against what the strategy had available.
+///
+pub fn select_notes(
+    strategy: &SelectionStrategy,
+    notes: &[NoteCandidate],
+    target: u64,
+) -> Vec<usize> {
+    let indices: Vec<usize> = (0..notes.len()).collect();
+    match strategy {
+        SelectionStrategy::Greedy => take_until(notes, indices, target),
+        SelectionStrategy::MinimizeInputs => minimize_inputs(notes, indices, target),
+        SelectionStrategy::SinglePool => {
+            let in_pool = |pool: Pool| -> Vec<usize> {
+                indices
+                    .iter()
+                    .copied()
+                    .filter(|&i| notes[i].pool == pool)
+                    .collect()
+            };
+            let orchard = in_pool(Pool::Orchard);
+            let sapling = in_pool(Pool::Sapling);
+            let orchard_total = total(notes, &orchard);
+            let sapling_total = total(notes, &sapling);
+            if orchard_total >= target || orchard_total >= sapling_total {
+                take_until(notes, orchard, target)
+            } else {
+                take_until(notes, sapling, target)
+            }
+        }
+        SelectionStrategy::OldestFirst => {
+            let mut indices = indices;
+            indices.sort_by_key(|&i| notes[i].mined_height.unwrap_or(u32::MAX));
+            take_until(notes, indices, target)
+        }
+        SelectionStrategy::PreferConfirmed { confirmations } => {
+            let (mut confirmed, newer): (Vec<_>, Vec<_>) = indices
+                .into_iter()
+                .partition(|&i| notes[i].confirmations >= *confirmations);
+            confirmed.extend(newer);
+            take_until(notes, confirmed, target)
+        }
+    }
+}
+
+/// The prefix of `order` worth at least `target`, or all of it
+fn take_until(notes: &[NoteCandidate], order: Vec<usize>, target: u64) -> Vec<usize> {
+    let mut selected = vec![];
+    let mut value = 0;
+    for i in order {
+        if value >= target {
+            break;
+        }
+        value += notes[i].value;
+        selected.push(i);
+    }
+    selected
+}
+
+fn total(notes: &[NoteCandidate], indices: &[usize]) -> u64 {
+    indices.iter().map(|&i| notes[i].value).sum()
+}
+
+/// The largest notes reach any amount with the fewest notes. The last of them is then swapped for the smallest note
+/// that still covers the amount.
+fn minimize_inputs(notes: &[NoteCandidate], mut indices: Vec<usize>, target: u64) -> Vec<usize> {
+    indices.sort_by(|&a, &b| notes[b].value.cmp(&notes[a].value));
+    let mut selected = take_until(notes, indices.clone(), target);
+    if total(notes, &selected) < target {
+        return selected;
+    }
+    let Some(last) = selected.pop() else {
+        return selected;
+    };
+    let rest = total(notes, &selected);
+    let smallest = indices[selected.len()..]
+        .iter()
+        .copied()
+        .filter(|&i| rest + notes[i].value >= target)
+        .min_by_key(|&i| notes[i].value)
+        .unwrap_or(last);
+    selected.push(smallest);
+    selected
+}
+
+/// An input selector that makes proposals like [`GreedyInputSelector`] but spends the notes a [`SelectionStrategy`]
+/// picks, or exactly the inputs the caller chose
+pub struct StrategyInputSelector<DbT, ChangeT> {
+    change_strategy: ChangeT,
+    dust_output_policy: DustOutputPolicy,
+    strategy: SelectionStrategy,
+    chosen_notes: Option<HashSet<NoteId>>,
+    chosen_utxos: Option<HashSet<OutPoint>>,
+    coinbase_maturity: HashMap<OutPoint, BlockHeight>,
+    _db: PhantomData<DbT>,
+}
+
+impl<DbT, ChangeT> StrategyInputSelector<DbT, ChangeT> {
+    pub fn new(
+        change_strategy: ChangeT,
+        dust_output_policy: DustOutputPolicy,
+        strategy: SelectionStrategy,
+    ) -> Self {
+        StrategyInputSelector {
+            change_strategy,
+            dust_output_policy,
+            strategy,
+            chosen_notes: None,
+            chosen_utxos: None,
+            coinbase_maturity: HashMap::new(),
+            _db: PhantomData,
+        }
+    }
+
+    /// Spend all of `notes` and no others, whatever the strategy would pick
+    pub fn spending_notes(mut self, notes: HashSet<NoteId>) -> Self {
+        self.chosen_notes = Some(notes);
+        self
+    }
+
+    /// Shield only `utxos` out of the transparent outputs of the addresses being shielded
+    pub fn spending_utxos(mut self, utxos: HashSet<OutPoint>) -> Self {
+        self.chosen_utxos = Some(utxos);
+        self
+    }
+
+    /// Leave out the coinbase outputs in `maturity` until the target height reaches the height each can be spent from
+    pub fn with_coinbase_maturity(mut self, maturity: HashMap<OutPoint, BlockHeight>) -> Self {
+        self.coinbase_maturity = maturity;
+        self
+    }
+
+    fn source<'a>(&'a self, db: &'a DbT) -> StrategySource<'a, DbT> {
+        StrategySource {
+            db,
+            strategy: self.strategy,
+            chosen_notes: self.chosen_notes.as_ref(),
+            chosen_utxos: self.chosen_utxos.as_ref(),
+            coinbase_maturity: &self.coinbase_maturity,
+        }
+    }
+}
+
+impl<DbT, ChangeT> InputSelector for StrategyInputSelector<DbT, ChangeT>
+where
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+    ChangeT: ChangeStrategy + Clone,
+{
+    type Error = GreedyInputSelectorError<ChangeT::Error, <DbT as InputSource>::NoteRef>;
+    type InputSource = DbT;
+    type FeeRule = ChangeT::FeeRule;
+
+    fn propose_transaction<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &Self::InputSource,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        account: <DbT as InputSource>::AccountId,
+        transaction_request: TransactionRequest,
+    ) -> Result<
+        Proposal<Self::FeeRule, <DbT as InputSource>::NoteRef>,
+        InputSelectorError<<DbT as InputSource>::Error, Self::Error>,
+    >
+    where
+        ParamsT: consensus::Parameters,
+    {
+        GreedyInputSelector::new(
+            self.change_strategy.clone(),
+            self.dust_output_policy.clone(),
+        )
+        .propose_transaction(
+            params,
+            &self.source(wallet_db),
+            target_height,
+            anchor_height,
+            account,
+            transaction_request,
+        )
+    }
+}
+
+impl<DbT, ChangeT> ShieldingSelector for StrategyInputSelector<DbT, ChangeT>
+where
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+    ChangeT: ChangeStrategy + Clone,
+{
+    type Error = GreedyInputSelectorError<ChangeT::Error, Infallible>;
+    type InputSource = DbT;
+    type FeeRule = ChangeT::FeeRule;
+
+    fn propose_shielding<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &Self::InputSource,
+        shielding_threshold: NonNegativeAmount,
+        source_addrs: &[TransparentAddress],
+        target_height: BlockHeight,
+        min_confirmations: u32,
+    ) -> Result<
+        Proposal<Self::FeeRule, Infallible>,
+        InputSelectorError<<DbT as InputSource>::Error, Self::Error>,
+    >
+    where
+        ParamsT: consensus::Parameters,
+    {
+        GreedyInputSelector::new(
+            self.change_strategy.clone(),
+            self.dust_output_policy.clone(),
+        )
+        .propose_shielding(
+            params,
+            &self.source(wallet_db),
+            shielding_threshold,
+            source_addrs,
+            target_height,
+            min_confirmations,
+        )
+    }
+}
+
+/// The wallet database as seen by the greedy selector, answering note selections with the strategy's choice
+struct StrategySource<'a, DbT> {
+    db: &'a DbT,
+    strategy: SelectionStrategy,
+    chosen_notes: Option<&'a HashSet<NoteId>>,
+    chosen_utxos: Option<&'a HashSet<OutPoint>>,
+    coinbase_maturity: &'a HashMap<OutPoint, BlockHeight>,
+}
+
+impl<DbT> InputSource for StrategySource<'_, DbT>
+where
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+{
+    type Error = <DbT as InputSource>::Error;
+    type AccountId = <DbT as InputSource>::AccountId;
+    type NoteRef = <DbT as InputSource>::NoteRef;
+
+    fn get_spendable_note(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        self.db.get_spendable_note(txid, protocol, index)
+    }
+
+    fn select_spendable_notes(
+        &self,
+        account: Self::AccountId,
+        target_value: NonNegativeAmount,
+        sources: &[ShieldedProtocol],
+        anchor_height: BlockHeight,
+        exclude: &[Self::NoteRef],
+    ) -> Result<SpendableNotes<Self::NoteRef>, Self::Error> {
+        if let Some(chosen) = self.chosen_notes {
+            let all = self.db.select_spendable_notes(
+                account,
+                NonNegativeAmount::const_from_u64(MAX_MONEY),
+                sources,
+                anchor_height,
+                exclude,
+            )?;
+            let sapling = all
+                .sapling()
+                .iter()
+                .filter(|note| {
+                    chosen.contains(&NoteId::new(
+                        *note.txid(),
+                        ShieldedProtocol::Sapling,
+                        note.output_index(),
+                    ))
+                })
+                .cloned()
+                .collect();
+            let orchard = all
+                .orchard()
+                .iter()
+                .filter(|note| {
+                    chosen.contains(&NoteId::new(
+                        *note.txid(),
+                        ShieldedProtocol::Orchard,
+                        note.output_index(),
+                    ))
+                })
+                .cloned()
+                .collect();
+            return Ok(SpendableNotes::new(sapling, orchard));
+        }
+        if self.strategy == SelectionStrategy::Greedy {
+            return self.db.select_spendable_notes(
+                account,
+                target_value,
+                sources,
+                anchor_height,
+                exclude,
+            );
+        }
+
+        let all = self.db.select_spendable_notes(
+            account,
+            NonNegativeAmount::const_from_u64(MAX_MONEY),
+            sources,
+            anchor_height,
+            exclude,
+        )?;
+        let chain_tip = self.db.chain_height()?;
+        let candidate = |pool: Pool, txid: &TxId, value: u64| {
+            let mined_height = self.db.get_tx_height(*txid)?;
+            let confirmations = match (mined_height, chain_tip) {
+                (Some(height), Some(tip)) if height <= tip => {
+                    u32::from(tip) - u32::from(height) + 1
+                }
+                _ => 0,
+            };
+            Ok(NoteCandidate {
+                pool,
+                value,
+                mined_height: mined_height.map(u32::from),
+                confirmations,
+            })
+        };
+        let mut candidates = vec![];
+        for note in all.sapling() {
+            candidates.push(candidate(
+                Pool::Sapling,
+                note.txid(),
+                note.note().value().inner(),
+            )?);
+        }
+        for note in all.orchard() {
+            candidates.push(candidate(
+                Pool::Orchard,
+                note.txid(),
+                note.note().value().inner(),
+            )?);
+        }
+
+        let selected: HashSet<usize> =
+            select_notes(&self.strategy, &candidates, target_value.into())
+                .into_iter()
+                .collect();
+        let sapling_count = all.sapling().len();
+        let sapling = all
+            .sapling()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| selected.contains(i))
+            .map(|(_, note)| note.clone())
+            .collect();
+        let orchard = all
+            .orchard()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| selected.contains(&(sapling_count + i)))
+            .map(|(_, note)| note.clone())
+            .collect();
+        Ok(SpendableNotes::new(sapling, orchard))
+    }
+
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        self.db.get_unspent_transparent_output(outpoint)
+    }
+
+    fn get_spendable_transparent_outputs(
+        &self,
+        address: &TransparentAddress,
+        target_height: BlockHeight,
+        min_confirmations: u32,
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        let mut outputs =
+            self.db
+                .get_spendable_transparent_outputs(address, target_height, min_confirmations)?;
+        if let Some(chosen) = self.chosen_utxos {
+            outputs.retain(|output| chosen.contains(output.outpoint()));
+        }
+        outputs.retain(|output| {
+            self.coinbase_maturity
+                .get(output.outpoint())
+                .map_or(true, |maturity_height| *maturity_height <= target_height)
+        });
+        Ok(outputs)
+    }
+}
@@ -6,9 +6,27 @@
 #[cfg(feature = "wasm")]
 mod bindgen;
 
+mod enhance;
 mod error;
 mod init;
-
+mod scan;
+mod transparent;
+
+pub mod birthday;
+pub mod block_cache;
+pub mod change;
+pub mod checkpoints;
+pub mod coin_control;
+pub mod diagnostics;
+pub mod fees;
+pub mod history;
+pub mod input_selection;
+pub mod memos;
+pub mod proposal_encoding;
+pub mod proposal_summary;
+pub mod shielding;
+pub mod sync;
+pub mod unspent;
 pub mod wallet;
 pub use wallet::Wallet;
 
@@ -0,0 +1,277 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Decrypted memos of the notes an account received or sent
+//!
+//! Compact blocks leave out memo ciphertexts so scanning alone never sees a memo. The outputs of each transaction are
+//! known from the wallet's [`TransactionIndex`](crate::history::TransactionIndex) and their memos are read from the
+//! wallet by note ID. Transactions whose memos the wallet doesn't have yet are first fetched from lightwalletd, several
+//! at a time, and stored in the wallet, which decrypts them with the account's viewing key.
+
+use std::cmp::Reverse;
+
+use futures_util::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::data_api::wallet::decrypt_and_store_transaction;
+use zcash_client_backend::data_api::WalletRead;
+use zcash_client_backend::decrypt::TransferType;
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::memo::{Memo, MemoBytes};
+use zcash_primitives::transaction::{Transaction, TxId};
+
+use crate::enhance;
+use crate::error::Error;
+use crate::history::{AccountTransaction, Owners, Pool};
+use crate::wallet::{LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// The contents of a memo, decoded as described in [ZIP 302](https://zips.z.cash/zip-0302)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum MemoContent {
+    Empty,
+    Text {
+        text: String,
+    },
+    /// Arbitrary data, hex encoded without the leading `0xFF` byte
+    Arbitrary {
+        data: String,
+    },
+    /// A memo format reserved for future use, hex encoded in full
+    Future {
+        data: String,
+    },
+}
+
+impl From<&MemoBytes> for MemoContent {
+    fn from(memo: &MemoBytes) -> Self {
+        match Memo::try_from(memo.clone()) {
+            Ok(Memo::Empty) => MemoContent::Empty,
+            Ok(Memo::Text(text)) => MemoContent::Text {
+                text: text.to_string(),
+            },
+            Ok(Memo::Arbitrary(data)) => MemoContent::Arbitrary {
+                data: hex::encode(&data[..]),
+            },
+            Ok(Memo::Future(bytes)) => MemoContent::Future {
+                data: hex::encode(bytes.as_slice()),
+            },
+            // Starts with a text lead byte but isn't valid UTF-8, which ZIP 302 leaves to future use
+            Err(_) => MemoContent::Future {
+                data: hex::encode(memo.as_slice()),
+            },
+        }
+    }
+}
+
+/// How an output relates to the account that decrypted it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoDirection {
+    /// Received from someone else
+    Received,
+    /// Sent to someone else
+    Sent,
+    /// Sent to the account itself, e.g. change
+    Internal,
+}
+
+impl From<TransferType> for MemoDirection {
+    fn from(transfer_type: TransferType) -> Self {
+        match transfer_type {
+            TransferType::Incoming => MemoDirection::Received,
+            TransferType::Outgoing => MemoDirection::Sent,
+            TransferType::WalletInternal => MemoDirection::Internal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputMemo {
+    pub pool: Pool,
+    /// Index of the output within the pool's bundle: the Sapling output or Orchard action index
+    pub output_index: usize,
+    pub direction: MemoDirection,
+    pub value: u64,
+    pub memo: MemoContent,
+}
+
+/// How many full transactions are fetched from lightwalletd at once when memos are missing
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// The memos of the outputs of one transaction that an account can decrypt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionMemos {
+    /// Transaction ID in the byte order used by block explorers
+    pub txid: String,
+    /// Ordered by pool and then output index
+    pub outputs: Vec<OutputMemo>,
+}
+
+impl<W, T, AccountId> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    /// Memos of every note `account_id` received or sent, newest transaction first. Empty if there is no such account.
+    ///
+    /// Transactions with no shielded outputs of the account are left out. Full transactions the wallet doesn't have
+    /// yet are fetched from lightwalletd, up to [`MAX_CONCURRENT_FETCHES`] at a time.
+    pub async fn get_account_memos(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<TransactionMemos>, Error> {
+        let mut transactions: Vec<_> = self
+            .tx_index
+            .account_transactions(account_id)
+            .into_iter()
+            .filter(|tx| !tx.effect.outputs.is_empty())
+            .map(|tx| {
+                let mined_height = tx.mined_height;
+                (tx, mined_height)
+            })
+            .collect();
+        transactions.sort_by_key(|(tx, mined_height)| {
+            (
+                Reverse(mined_height.map_or(u32::MAX, u32::from)),
+                *tx.txid.as_ref(),
+            )
+        });
+
+        self.fetch_missing_memos(&transactions).await?;
+
+        let db = self.db.read().await;
+        transactions
+            .iter()
+            .map(|(tx, _)| transaction_memos(&*db, tx))
+            .collect()
+    }
+
+    /// Memos of the outputs of `txid` that `account_id` received or sent, fetching the transaction from lightwalletd
+    /// if the wallet doesn't have it yet
+    pub async fn get_transaction_memos(
+        &self,
+        account_id: AccountId,
+        txid: TxId,
+    ) -> Result<TransactionMemos, Error> {
+        let mined_height = self.db.read().await.get_tx_height(txid)?;
+        let account_transaction = |wallet: &Self| {
+            wallet
+                .tx_index
+                .account_transactions(account_id)
+                .into_iter()
+                .find(|tx| tx.txid == txid)
+        };
+        let tx = match account_transaction(self) {
+            Some(tx) => {
+                self.fetch_missing_memos(&[(tx.clone(), mined_height)])
+                    .await?;
+                tx
+            }
+            None => {
+                // Not a transaction the wallet has seen. Fetching it records it in the index if it involves the account.
+                self.full_transaction(txid, mined_height).await?;
+                match account_transaction(self) {
+                    Some(tx) => tx,
+                    None => {
+                        return Ok(TransactionMemos {
+                            txid: txid.to_string(),
+                            outputs: vec![],
+                        })
+                    }
+                }
+            }
+        };
+        transaction_memos(&*self.db.read().await, &tx)
+    }
+
+    /// Fetch the full transactions of `transactions` whose memos the wallet doesn't have, several at a time
+    async fn fetch_missing_memos(
+        &self,
+        transactions: &[(AccountTransaction<AccountId>, Option<BlockHeight>)],
+    ) -> Result<(), Error> {
+        let mut missing = vec![];
+        {
+            let db = self.db.read().await;
+            for (tx, mined_height) in transactions {
+                for output in &tx.effect.outputs {
+                    if db.get_memo(output.note_id(tx.txid))?.is_none() {
+                        missing.push((tx.txid, *mined_height));
+                        break;
+                    }
+                }
+            }
+        }
+        if !missing.is_empty() {
+            tracing::info!("Fetching {} transactions for their memos", missing.len());
+        }
+
+        futures_util::stream::iter(missing)
+            .map(|(txid, mined_height)| self.full_transaction(txid, mined_height))
+            .buffer_unordered(MAX_CONCURRENT_FETCHES)
+            .try_for_each(|_| async { Ok(()) })
+            .await
+    }
+
+    /// The full transaction with ID `txid`, from the wallet if it has it or else from lightwalletd.
+    ///
+    /// A transaction fetched from lightwalletd is decrypted and stored in the wallet, which records its memos, and is
+    /// added to the transaction index.
+    pub(crate) async fn full_transaction(
+        &self,
+        txid: TxId,
+        mined_height: Option<BlockHeight>,
+    ) -> Result<Transaction, Error> {
+        if let Some(tx) = self.db.read().await.get_transaction(txid)? {
+            return Ok(tx);
+        }
+
+        tracing::info!("Fetching transaction {} from lightwalletd", txid);
+        let next_height = enhance::next_block_height(&*self.db.read().await)?;
+        let (tx, _) =
+            enhance::fetch_transaction(&mut self.client.clone(), &self.network, txid, next_height)
+                .await?
+                .ok_or(Error::TransactionNotFound(txid))?;
+
+        decrypt_and_store_transaction(
+            &self.network,
+            &mut *self.db.write().await,
+            &tx,
+            mined_height,
+        )?;
+        let db = self.db.read().await;
+        let owners = Owners::read(&*db)?;
+        self.tx_index
+            .record_transaction(&self.network, &*db, &owners, &tx, mined_height)?;
+        Ok(tx)
+    }
+}
+
+/// The memos the wallet has for the outputs of `tx`
+fn transaction_memos<DbT: WalletRead>(
+    db: &DbT,
+    tx: &AccountTransaction<DbT::AccountId>,
+) -> Result<TransactionMemos, Error>
+where
+    Error: From<DbT::Error>,
+{
+    let mut outputs = vec![];
+    for output in &tx.effect.outputs {
+        let memo = db
+            .get_memo(output.note_id(tx.txid))?
+            .map_or(MemoContent::Empty, |memo| (&memo.encode()).into());
+        outputs.push(OutputMemo {
+            pool: output.pool,
+            output_index: output.output_index as usize,
+            direction: output.direction,
+            value: output.value,
+            memo,
+        });
+    }
+    outputs.sort_by_key(|output| (output.pool as u8, output.output_index));
+
+    Ok(TransactionMemos {
+        txid: tx.txid.to_string(),
+        outputs,
+    })
+}
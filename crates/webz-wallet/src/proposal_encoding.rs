@@ -0,0 +1,105 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Moving proposals between sessions and devices
+//!
+//! Proposals are encoded in the backend's protobuf proposal format, which refers to the inputs it spends by
+//! transaction ID and index rather than including them. Decoding looks every input up in the wallet again, so a
+//! proposal can only be decoded by a wallet that holds its inputs and only while they are still unspent.
+
+use std::fmt::Display;
+
+use prost::Message;
+use zcash_client_backend::data_api::{InputSource, WalletRead};
+use zcash_client_backend::fees::StandardFeeRule;
+use zcash_client_backend::proposal::Proposal;
+use zcash_client_backend::proto::{self, ProposalDecodingError};
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::transaction::builder::DEFAULT_TX_EXPIRY_DELTA;
+use zcash_primitives::transaction::fees::zip317::FeeRule;
+
+use crate::error::Error;
+use crate::wallet::{LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// Encode `proposal` in the backend's protobuf proposal format
+pub fn encode_proposal<NoteRef: Clone>(
+    proposal: &Proposal<FeeRule, NoteRef>,
+) -> Result<Vec<u8>, Error> {
+    // Proposals are only ever made with the standard ZIP-317 rule, which is the one the format can name
+    let standard = FeeRule::standard();
+    let fee_rule = proposal.fee_rule();
+    if fee_rule.marginal_fee() != standard.marginal_fee()
+        || fee_rule.grace_actions() != standard.grace_actions()
+    {
+        return Err(Error::ProposalEncoding(
+            "only proposals with the standard ZIP-317 fee rule can be encoded".to_string(),
+        ));
+    }
+    let proposal = Proposal::multi_step(
+        StandardFeeRule::Zip317,
+        proposal.min_target_height(),
+        proposal.steps().clone(),
+    )
+    .map_err(|e| Error::ProposalEncoding(e.to_string()))?;
+    Ok(proto::proposal::Proposal::from_standard_proposal(&proposal).encode_to_vec())
+}
+
+/// Decode a proposal made by [`encode_proposal`], looking its inputs up in `db`, and check it hasn't expired by
+/// `chain_tip`
+pub fn decode_proposal<DbT>(
+    db: &DbT,
+    bytes: &[u8],
+    chain_tip: Option<BlockHeight>,
+) -> Result<Proposal<FeeRule, DbT::NoteRef>, Error>
+where
+    DbT: InputSource,
+    DbT::Error: Display,
+{
+    let encoded = proto::proposal::Proposal::decode(bytes)
+        .map_err(|e| Error::ProposalEncoding(e.to_string()))?;
+    let proposal = encoded
+        .try_into_standard_proposal(db)
+        .map_err(|e| match e {
+            ProposalDecodingError::InputNotFound(txid, pool, index) => Error::InputNotSpendable {
+                output: format!("{} output {} of transaction {}", pool, index, txid),
+                reason: "it has been spent or is not in this wallet",
+            },
+            e => Error::ProposalEncoding(e.to_string()),
+        })?;
+
+    let expiry_height = u32::from(proposal.min_target_height()) + DEFAULT_TX_EXPIRY_DELTA;
+    if let Some(chain_tip) = chain_tip {
+        if u32::from(chain_tip) >= expiry_height {
+            return Err(Error::ProposalExpired {
+                expiry_height,
+                chain_tip: chain_tip.into(),
+            });
+        }
+    }
+
+    Proposal::multi_step(
+        FeeRule::standard(),
+        proposal.min_target_height(),
+        proposal.steps().clone(),
+    )
+    .map_err(|e| Error::ProposalEncoding(e.to_string()))
+}
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    ///
+    /// Decode a proposal made by [`encode_proposal`] and check it can still be authorized by this wallet
+    ///
+    /// Returns [`Error::InputNotSpendable`] if an input of the proposal has been spent or isn't in this wallet, and
+    /// [`Error::ProposalExpired`] if the chain has passed the height at which its transactions would expire.
+    ///
+    pub async fn decode_proposal(&self, bytes: &[u8]) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+        let db = self.db.read().await;
+        decode_proposal(&*db, bytes, db.chain_height()?)
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! What a proposal will do, in a form to show the user before they approve it
+//!
+//! A [`Proposal`] holds the backend's own types, whose serialized shape changes between versions. The summary lists
+//! what matters for review instead: the inputs spent, who is paid, the change returned and the fee of each
+//! transaction. Nothing is looked up in the wallet so a summary can be made from a proposal alone.
+
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::proposal::{Proposal, Step, StepOutputIndex};
+use zcash_client_backend::wallet::Note;
+use zcash_primitives::transaction::builder::DEFAULT_TX_EXPIRY_DELTA;
+use zcash_primitives::transaction::fees::zip317::FeeRule;
+use zcash_primitives::transaction::TxId;
+
+use crate::history::Pool;
+use crate::memos::MemoContent;
+
+/// The transactions a proposal will create
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposalSummary {
+    /// One entry per transaction, in the order they are created and sent
+    pub steps: Vec<StepSummary>,
+    /// Sum of the fees of all the transactions in Zatoshis
+    pub total_fee: u64,
+}
+
+/// A single transaction of a proposal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepSummary {
+    /// Height of the block the transaction is built to be mined in
+    pub target_height: u32,
+    /// Height after which the transaction can no longer be mined
+    pub expiry_height: u32,
+    /// Height of the note commitment tree anchor the shielded inputs are spent against
+    pub anchor_height: Option<u32>,
+    /// Confirmations the shielded inputs were required to have. Not set for transactions without shielded inputs.
+    pub min_confirmations: Option<u32>,
+    /// Whether the transaction only moves transparent funds of the account into a shielded pool
+    pub is_shielding: bool,
+    pub inputs: Vec<InputSummary>,
+    pub payments: Vec<PaymentSummary>,
+    pub change: Vec<ChangeSummary>,
+    /// The ZIP-317 fee in Zatoshis
+    pub fee: u64,
+    /// The ZIP-317 logical actions the fee is charged for, never fewer than the two grace actions
+    pub logical_actions: u64,
+}
+
+/// A note or transparent output spent by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputSummary {
+    pub pool: Pool,
+    pub value: u64,
+    /// ID of the transaction that created the input, in the byte order used by block explorers. Not set for inputs
+    /// created by an earlier step.
+    pub txid: Option<String>,
+    /// Index of the input within the pool's part of that transaction
+    pub output_index: Option<u32>,
+    /// Index of the earlier step of the proposal whose output this is
+    pub prior_step: Option<usize>,
+}
+
+/// A payment made by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentSummary {
+    /// Address as the request gave it
+    pub address: String,
+    pub amount: u64,
+    /// Pool the payment is sent to, which for a unified address is one of its receivers
+    pub pool: Pool,
+    pub memo: Option<MemoContent>,
+}
+
+/// Change a transaction returns to the account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeSummary {
+    pub pool: Pool,
+    pub value: u64,
+    pub memo: Option<MemoContent>,
+    /// Whether this goes to an ephemeral transparent address for a later step to spend, rather than back to the
+    /// account
+    pub is_ephemeral: bool,
+}
+
+impl<NoteRef> From<&Proposal<FeeRule, NoteRef>> for ProposalSummary {
+    fn from(proposal: &Proposal<FeeRule, NoteRef>) -> Self {
+        let target_height = u32::from(proposal.min_target_height());
+        let steps: Vec<_> = proposal
+            .steps()
+            .iter()
+            .map(|step| summarize_step(proposal, step, target_height))
+            .collect();
+        ProposalSummary {
+            total_fee: steps.iter().map(|step| step.fee).sum(),
+            steps,
+        }
+    }
+}
+
+fn summarize_step<NoteRef>(
+    proposal: &Proposal<FeeRule, NoteRef>,
+    step: &Step<NoteRef>,
+    target_height: u32,
+) -> StepSummary {
+    let anchor_height = step
+        .shielded_inputs()
+        .map(|inputs| u32::from(inputs.anchor_height()));
+
+    let transparent = step.transparent_inputs().iter().map(|utxo| InputSummary {
+        pool: Pool::Transparent,
+        value: utxo.txout().value.into(),
+        txid: Some(TxId::from_bytes(*utxo.outpoint().hash()).to_string()),
+        output_index: Some(utxo.outpoint().n()),
+        prior_step: None,
+    });
+    let shielded = step
+        .shielded_inputs()
+        .into_iter()
+        .flat_map(|inputs| inputs.notes().iter())
+        .map(|note| InputSummary {
+            pool: match note.note() {
+                Note::Sapling(_) => Pool::Sapling,
+                Note::Orchard(_) => Pool::Orchard,
+            },
+            value: note.note().value().into(),
+            txid: Some(note.txid().to_string()),
+            output_index: Some(note.output_index().into()),
+            prior_step: None,
+        });
+    // Outputs of earlier steps are paid to ephemeral transparent addresses
+    let prior = step.prior_step_inputs().iter().map(|input| InputSummary {
+        pool: Pool::Transparent,
+        value: prior_output_value(proposal, input.step_index(), input.output_index()),
+        txid: None,
+        output_index: None,
+        prior_step: Some(input.step_index()),
+    });
+
+    let payments = step
+        .transaction_request()
+        .payments()
+        .iter()
+        .map(|(index, payment)| PaymentSummary {
+            address: payment.recipient_address().encode(),
+            amount: payment.amount().into(),
+            pool: step
+                .payment_pools()
+                .get(index)
+                .map_or(Pool::Transparent, |pool| (*pool).into()),
+            memo: payment.memo().map(Into::into),
+        })
+        .collect();
+    let change = step
+        .balance()
+        .proposed_change()
+        .iter()
+        .map(|change| ChangeSummary {
+            pool: change.output_pool().into(),
+            value: change.value().into(),
+            memo: change.memo().map(Into::into),
+            is_ephemeral: change.is_ephemeral(),
+        })
+        .collect();
+
+    let fee = u64::from(step.balance().fee_required());
+    StepSummary {
+        target_height,
+        expiry_height: target_height + DEFAULT_TX_EXPIRY_DELTA,
+        anchor_height,
+        min_confirmations: anchor_height.map(|anchor_height| target_height - anchor_height),
+        is_shielding: step.is_shielding(),
+        inputs: transparent.chain(shielded).chain(prior).collect(),
+        payments,
+        change,
+        fee,
+        // The ZIP-317 fee is the marginal fee times the logical actions, with a minimum of the grace actions
+        logical_actions: fee / u64::from(proposal.fee_rule().marginal_fee()).max(1),
+    }
+}
+
+fn prior_output_value<NoteRef>(
+    proposal: &Proposal<FeeRule, NoteRef>,
+    step_index: usize,
+    output_index: StepOutputIndex,
+) -> u64 {
+    let Some(step) = proposal.steps().get(step_index) else {
+        return 0;
+    };
+    match output_index {
+        StepOutputIndex::Payment(i) => step
+            .transaction_request()
+            .payments()
+            .get(&i)
+            .map_or(0, |payment| payment.amount().into()),
+        StepOutputIndex::Change(i) => step
+            .balance()
+            .proposed_change()
+            .get(i)
+            .map_or(0, |change| change.value().into()),
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Scanning cached compact blocks without holding the wallet for the whole scan
+//!
+//! `zcash_client_backend::data_api::chain::scan_cached_blocks` needs mutable access to the wallet for all of the time
+//! it spends trial-decrypting. Here the same work is split in three so the wallet only has to be locked briefly at
+//! either end: a [`ScanContext`] is read from the wallet, blocks are scanned against it without touching the wallet,
+//! and the resulting [`ScannedBatch`] is committed.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Range;
+
+#[cfg(feature = "multicore")]
+use rayon::prelude::*;
+use subtle::ConditionallySelectable;
+use zcash_client_backend::data_api::chain::{error::Error as ChainError, BlockSource, ChainState};
+use zcash_client_backend::data_api::{
+    BlockMetadata, NullifierQuery, ScannedBlock, WalletRead, WalletWrite,
+};
+use zcash_client_backend::proto::compact_formats::CompactBlock;
+use zcash_client_backend::scanning::{scan_block, Nullifiers, ScanError, ScanningKeys};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_primitives::consensus::{BlockHeight, Parameters};
+
+use crate::history::{ScannedTransactions, TransactionIndex};
+
+/// The wallet state needed to scan a range of blocks, read before scanning starts
+pub(crate) struct ScanContext<AccountId> {
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    sapling_nullifiers: Vec<(AccountId, sapling::Nullifier)>,
+    orchard_nullifiers: Vec<(AccountId, orchard::note::Nullifier)>,
+    prior_block_metadata: Option<BlockMetadata>,
+    chain_tip: Option<BlockHeight>,
+}
+
+impl<AccountId: Copy + Eq + Hash> ScanContext<AccountId> {
+    pub(crate) fn read<DbT>(db_data: &DbT, from_height: BlockHeight) -> Result<Self, DbT::Error>
+    where
+        DbT: WalletRead<AccountId = AccountId>,
+    {
+        Ok(Self {
+            ufvks: db_data.get_unified_full_viewing_keys()?,
+            sapling_nullifiers: db_data.get_sapling_nullifiers(NullifierQuery::Unspent)?,
+            orchard_nullifiers: db_data.get_orchard_nullifiers(NullifierQuery::Unspent)?,
+            prior_block_metadata: prior_block_metadata(db_data, from_height)?,
+            chain_tip: db_data.chain_height()?,
+        })
+    }
+}
+
+fn prior_block_metadata<DbT: WalletRead>(
+    db_data: &DbT,
+    from_height: BlockHeight,
+) -> Result<Option<BlockMetadata>, DbT::Error> {
+    if from_height > BlockHeight::from_u32(0) {
+        db_data.block_metadata(from_height - 1)
+    } else {
+        Ok(None)
+    }
+}
+
+/// What was found in a scanned batch
+#[derive(Debug, Clone)]
+pub(crate) struct BatchSummary {
+    pub(crate) scanned_range: Range<BlockHeight>,
+    pub(crate) received_sapling_notes: usize,
+    pub(crate) received_orchard_notes: usize,
+    pub(crate) spent_sapling_notes: usize,
+    pub(crate) spent_orchard_notes: usize,
+}
+
+impl BatchSummary {
+    fn new<AccountId>(from_height: BlockHeight, blocks: &[ScannedBlock<AccountId>]) -> Self {
+        let mut summary = BatchSummary {
+            scanned_range: from_height
+                ..blocks
+                    .last()
+                    .map_or(from_height, |block| block.height() + 1),
+            received_sapling_notes: 0,
+            received_orchard_notes: 0,
+            spent_sapling_notes: 0,
+            spent_orchard_notes: 0,
+        };
+        for block in blocks {
+            for tx in block.transactions() {
+                summary.spent_sapling_notes += tx.sapling_spends().len();
+                summary.spent_orchard_notes += tx.orchard_spends().len();
+                summary.received_sapling_notes += tx.sapling_outputs().len();
+                summary.received_orchard_notes += tx.orchard_outputs().len();
+            }
+        }
+        summary
+    }
+
+    pub(crate) fn has_notes(&self) -> bool {
+        self.received_sapling_notes > 0
+            || self.received_orchard_notes > 0
+            || self.spent_sapling_notes > 0
+            || self.spent_orchard_notes > 0
+    }
+}
+
+/// Blocks which have been scanned but not yet written to the wallet
+pub(crate) struct ScannedBatch<AccountId> {
+    /// The accounts the blocks were scanned for
+    account_ids: HashSet<AccountId>,
+    /// The wallet's block before the batch and its chain tip when scanning started
+    prior_block_metadata: Option<BlockMetadata>,
+    chain_tip: Option<BlockHeight>,
+    blocks: Vec<ScannedBlock<AccountId>>,
+    summary: BatchSummary,
+    /// What the transaction history needs to know about the blocks, recorded once they are committed
+    transactions: ScannedTransactions<AccountId>,
+}
+
+impl<AccountId: Copy + Eq + Hash> ScannedBatch<AccountId> {
+    /// Write the scanned blocks to the wallet.
+    ///
+    /// Returns `None` without writing anything if the wallet changed in a way the scan didn't account for since the
+    /// [`ScanContext`] was read: an account was added or removed, or the wallet was rewound or truncated in the
+    /// meantime, which shows as a lower chain tip or a different block before the batch. The batch has to be scanned
+    /// again in that case.
+    pub(crate) fn commit<DbT>(
+        self,
+        db_data: &mut DbT,
+        from_state: &ChainState,
+        index: &TransactionIndex<AccountId>,
+    ) -> Result<Option<BatchSummary>, <DbT as WalletRead>::Error>
+    where
+        DbT: WalletWrite<AccountId = AccountId>,
+    {
+        let current: HashSet<_> = db_data
+            .get_unified_full_viewing_keys()?
+            .into_keys()
+            .collect();
+        if current != self.account_ids {
+            tracing::debug!("Accounts changed while scanning");
+            return Ok(None);
+        }
+
+        let from_height = self.summary.scanned_range.start;
+        let prior_block = prior_block_metadata(db_data, from_height)?;
+        let chain_tip = db_data.chain_height()?;
+        if prior_block.map(|block| block.block_hash())
+            != self.prior_block_metadata.map(|block| block.block_hash())
+            || chain_tip < self.chain_tip
+        {
+            tracing::debug!("The wallet was rewound while scanning");
+            return Ok(None);
+        }
+
+        if !self.blocks.is_empty() {
+            db_data.put_blocks(from_state, self.blocks)?;
+        }
+        index.record_scanned(self.transactions);
+        Ok(Some(self.summary))
+    }
+}
+
+/// Scan up to `limit` blocks from `block_source`, starting at `from_height`, against `context`
+///
+/// Trial decryption is what scanning spends its time on, and blocks don't depend on each other for it. The blocks are
+/// split into chunks of `chunk_size` which are scanned at the same time on the rayon thread pool, each starting from
+/// the tree sizes lightwalletd reports for the block before it. The one thing a chunk can't see is a note received
+/// in an earlier chunk being spent. Chunks that spend a nullifier of such a note are scanned again, in order, once
+/// the notes before them are known. With a `chunk_size` of at least `limit` the whole batch is scanned in order on
+/// a single thread.
+pub(crate) fn scan_blocks<P, BsT, AccountId, WalletErrT>(
+    params: &P,
+    block_source: &BsT,
+    context: ScanContext<AccountId>,
+    from_height: BlockHeight,
+    limit: usize,
+    chunk_size: usize,
+) -> Result<ScannedBatch<AccountId>, ChainError<WalletErrT, BsT::Error>>
+where
+    P: Parameters + Clone + Send + 'static,
+    BsT: BlockSource,
+    AccountId: Copy + Default + Eq + Hash + ConditionallySelectable + Send + 'static,
+{
+    let ScanContext {
+        ufvks,
+        sapling_nullifiers,
+        orchard_nullifiers,
+        prior_block_metadata,
+        chain_tip,
+    } = context;
+    let account_ids = ufvks.keys().copied().collect();
+
+    let mut blocks = vec![];
+    block_source.with_blocks::<_, WalletErrT>(Some(from_height), Some(limit), |block| {
+        blocks.push(block);
+        Ok(())
+    })?;
+    let chunks = split_into_chunks(blocks, prior_block_metadata, chunk_size);
+
+    let mut nullifiers = TrackedNullifiers {
+        sapling: sapling_nullifiers,
+        orchard: orchard_nullifiers,
+    };
+    let first_pass = scan_chunks(params, &ufvks, &nullifiers, &chunks);
+
+    // Nullifiers of the notes received in this batch, which the chunks after them were scanned without
+    let mut received = HashSet::new();
+    let mut scanned_blocks = vec![];
+    for (chunk, scanned) in chunks.iter().zip(first_pass) {
+        let scanned = if chunk.spends_any(&received) {
+            tracing::debug!(
+                "Scanning blocks from {} again for spends of notes received before them",
+                chunk.start_height()
+            );
+            scan_chunk(params, ufvks.clone(), &nullifiers, chunk)
+        } else {
+            scanned
+        }
+        .map_err(ChainError::Scan)?;
+
+        for block in &scanned {
+            nullifiers.update(block);
+            for tx in block.transactions() {
+                received.extend(
+                    tx.sapling_outputs()
+                        .iter()
+                        .filter_map(|output| output.nf().map(|nf| nf.0)),
+                );
+                received.extend(
+                    tx.orchard_outputs()
+                        .iter()
+                        .filter_map(|output| output.nf().map(|nf| nf.to_bytes())),
+                );
+            }
+        }
+        scanned_blocks.extend(scanned);
+    }
+
+    Ok(ScannedBatch {
+        account_ids,
+        prior_block_metadata,
+        chain_tip,
+        summary: BatchSummary::new(from_height, &scanned_blocks),
+        transactions: ScannedTransactions::new(&scanned_blocks),
+        blocks: scanned_blocks,
+    })
+}
+
+/// Consecutive blocks scanned by a single task
+struct Chunk {
+    blocks: Vec<CompactBlock>,
+    /// Metadata of the block before the first one
+    prior_block_metadata: Option<BlockMetadata>,
+}
+
+impl Chunk {
+    fn start_height(&self) -> BlockHeight {
+        self.blocks[0].height()
+    }
+
+    /// Whether a transaction in the chunk reveals one of `nullifiers`
+    fn spends_any(&self, nullifiers: &HashSet<[u8; 32]>) -> bool {
+        !nullifiers.is_empty()
+            && self.blocks.iter().flat_map(|block| &block.vtx).any(|tx| {
+                tx.spends
+                    .iter()
+                    .map(|spend| &spend.nf)
+                    .chain(tx.actions.iter().map(|action| &action.nullifier))
+                    .any(|nf| {
+                        <[u8; 32]>::try_from(nf.as_slice()).is_ok_and(|nf| nullifiers.contains(&nf))
+                    })
+            })
+    }
+}
+
+/// Split `blocks` into chunks of about `chunk_size`.
+///
+/// A chunk can only start after a block whose commitment tree sizes lightwalletd reported, since scanning needs to
+/// know the position of the first note in the chunk.
+fn split_into_chunks(
+    blocks: Vec<CompactBlock>,
+    prior_block_metadata: Option<BlockMetadata>,
+    chunk_size: usize,
+) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut current = Chunk {
+        blocks: vec![],
+        prior_block_metadata,
+    };
+    for block in blocks {
+        let metadata = (current.blocks.len() + 1 >= chunk_size)
+            .then(|| block_metadata(&block))
+            .flatten();
+        current.blocks.push(block);
+        if let Some(metadata) = metadata {
+            let next = Chunk {
+                blocks: vec![],
+                prior_block_metadata: Some(metadata),
+            };
+            chunks.push(std::mem::replace(&mut current, next));
+        }
+    }
+    if !current.blocks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// The metadata scanning the block after `block` needs, if lightwalletd included the commitment tree sizes
+fn block_metadata(block: &CompactBlock) -> Option<BlockMetadata> {
+    let chain_metadata = block.chain_metadata.as_ref()?;
+    Some(BlockMetadata::from_parts(
+        block.height(),
+        block.hash(),
+        Some(chain_metadata.sapling_commitment_tree_size),
+        Some(chain_metadata.orchard_commitment_tree_size),
+    ))
+}
+
+/// Scan every chunk against the nullifiers as of the start of the batch, on the rayon thread pool if there is one.
+/// Results are in the order of `chunks`.
+fn scan_chunks<P, AccountId>(
+    params: &P,
+    ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
+    nullifiers: &TrackedNullifiers<AccountId>,
+    chunks: &[Chunk],
+) -> Vec<Result<Vec<ScannedBlock<AccountId>>, ScanError>>
+where
+    P: Parameters + Clone + Send + 'static,
+    AccountId: Copy + Default + Eq + Hash + ConditionallySelectable + Send + 'static,
+{
+    // Each task gets its own copy of the keys and nullifiers, which are small next to the blocks
+    let tasks: Vec<_> = chunks
+        .iter()
+        .map(|chunk| (params.clone(), ufvks.clone(), nullifiers.clone(), chunk))
+        .collect();
+
+    #[cfg(feature = "multicore")]
+    let tasks = tasks.into_par_iter();
+    #[cfg(not(feature = "multicore"))]
+    let tasks = tasks.into_iter();
+
+    tasks
+        .map(|(params, ufvks, nullifiers, chunk)| scan_chunk(&params, ufvks, &nullifiers, chunk))
+        .collect()
+}
+
+/// Scan the blocks of `chunk` in order, starting from `nullifiers`
+fn scan_chunk<P, AccountId>(
+    params: &P,
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    nullifiers: &TrackedNullifiers<AccountId>,
+    chunk: &Chunk,
+) -> Result<Vec<ScannedBlock<AccountId>>, ScanError>
+where
+    P: Parameters + Send + 'static,
+    AccountId: Copy + Default + Eq + Hash + ConditionallySelectable + Send + 'static,
+{
+    let scanning_keys = ScanningKeys::from_account_ufvks(ufvks);
+    let mut tracked = nullifiers.clone();
+    let mut nullifiers = tracked.to_nullifiers();
+    let mut prior_block_metadata = chunk.prior_block_metadata;
+
+    let mut blocks = Vec::with_capacity(chunk.blocks.len());
+    for block in &chunk.blocks {
+        let scanned_block = scan_block(
+            params,
+            block.clone(),
+            &scanning_keys,
+            &nullifiers,
+            prior_block_metadata.as_ref(),
+        )?;
+
+        // A note received in this block can be spent later in the chunk so the nullifiers have to be kept up to
+        // date as the scan goes
+        if tracked.update(&scanned_block) {
+            nullifiers = tracked.to_nullifiers();
+        }
+        prior_block_metadata = Some(scanned_block.to_block_metadata());
+        blocks.push(scanned_block);
+    }
+    Ok(blocks)
+}
+
+/// The nullifiers of the wallet's unspent notes
+#[derive(Clone)]
+struct TrackedNullifiers<AccountId> {
+    sapling: Vec<(AccountId, sapling::Nullifier)>,
+    orchard: Vec<(AccountId, orchard::note::Nullifier)>,
+}
+
+impl<AccountId: Copy> TrackedNullifiers<AccountId> {
+    fn to_nullifiers(&self) -> Nullifiers<AccountId> {
+        Nullifiers::new(self.sapling.clone(), self.orchard.clone())
+    }
+
+    /// Remove the notes spent in `block` and add the ones received. Returns `true` if anything changed.
+    fn update(&mut self, block: &ScannedBlock<AccountId>) -> bool {
+        let mut changed = false;
+        for tx in block.transactions() {
+            for spend in tx.sapling_spends() {
+                self.sapling.retain(|(_, nf)| nf != spend.nf());
+                changed = true;
+            }
+            for spend in tx.orchard_spends() {
+                self.orchard.retain(|(_, nf)| nf != spend.nf());
+                changed = true;
+            }
+            for output in tx.sapling_outputs() {
+                if let Some(nf) = output.nf() {
+                    self.sapling.push((*output.account_id(), *nf));
+                    changed = true;
+                }
+            }
+            for output in tx.orchard_outputs() {
+                if let Some(nf) = output.nf() {
+                    self.orchard.push((*output.account_id(), *nf));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Moving transparent funds into the shielded pool
+//!
+//! Shielding proposals are made by the backend's shielding input selection and then go through the same
+//! authorization and send path as transfers.
+
+use std::collections::HashSet;
+use std::convert::Infallible;
+
+use nonempty::NonEmpty;
+use zcash_client_backend::data_api::wallet::propose_shielding;
+use zcash_client_backend::data_api::{InputSource, WalletCommitmentTrees, WalletRead};
+use zcash_client_backend::proposal::{Proposal, Step};
+use zcash_keys::encoding::AddressCodec;
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::fees::zip317::FeeRule;
+
+use crate::error::Error;
+use crate::wallet::{proposal_error, LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    ///
+    /// Create a proposal to shield the confirmed transparent funds of `account_id` into its Orchard pool
+    ///
+    /// Only the outputs received by `from_addresses` are shielded, or by all of the account's transparent receivers if
+    /// that isn't given. Returns `None` if those hold less than `threshold` Zatoshis with the wallet's
+    /// `min_confirmations`.
+    ///
+    pub async fn propose_shielding(
+        &self,
+        account_id: AccountId,
+        threshold: u64,
+        from_addresses: Option<Vec<TransparentAddress>>,
+    ) -> Result<Option<Proposal<FeeRule, NoteRef>>, Error> {
+        let mut db = self.db.write().await;
+
+        let receivers: HashSet<_> = db
+            .get_transparent_receivers(account_id)?
+            .into_keys()
+            .collect();
+        let addresses = match from_addresses {
+            Some(addresses) => {
+                if let Some(unknown) = addresses.iter().find(|a| !receivers.contains(a)) {
+                    return Err(Error::UnknownTransparentAddress(
+                        unknown.encode(&self.network),
+                    ));
+                }
+                addresses
+            }
+            None => receivers.into_iter().collect(),
+        };
+
+        // The backend fails when there is too little to shield. Check first so that isn't mistaken for a real error.
+        let Some((_, max_height)) = db.get_target_and_anchor_heights(self.min_confirmations)?
+        else {
+            return Ok(None);
+        };
+        let balances = db.get_transparent_balances(account_id, max_height)?;
+        let available = addresses
+            .iter()
+            .filter_map(|address| balances.get(address))
+            .map(|value| u64::from(*value))
+            .sum::<u64>();
+        if available == 0 || available < threshold {
+            return Ok(None);
+        }
+
+        let proposal = propose_shielding::<_, _, _, <W as WalletCommitmentTrees>::Error>(
+            &mut *db,
+            &self.network,
+            &self.shielding_selector(&self.change_options())?,
+            NonNegativeAmount::from_u64(threshold)?,
+            &addresses,
+            self.min_confirmations.into(),
+        )
+        .map_err(proposal_error)?;
+        tracing::debug!("Shielding proposal: {:#?}", proposal);
+
+        Ok(Some(with_note_ref(proposal)?))
+    }
+
+    /// Shielding proposals for every account with at least `threshold` Zatoshis of confirmed transparent funds
+    pub async fn propose_auto_shielding(
+        &self,
+        threshold: u64,
+    ) -> Result<Vec<(AccountId, Proposal<FeeRule, NoteRef>)>, Error> {
+        let account_ids = self.db.read().await.get_account_ids()?;
+        let mut proposals = vec![];
+        for account_id in account_ids {
+            if let Some(proposal) = self.propose_shielding(account_id, threshold, None).await? {
+                proposals.push((account_id, proposal));
+            }
+        }
+        Ok(proposals)
+    }
+}
+
+/// Shielding proposals never spend notes so their note reference type is uninhabited. This rebuilds one with the
+/// wallet's note reference type so it can be authorized like any other proposal.
+pub(crate) fn with_note_ref<NoteRef>(
+    proposal: Proposal<FeeRule, Infallible>,
+) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+    let mut steps: Vec<Step<NoteRef>> = vec![];
+    for step in proposal.steps() {
+        let step = Step::from_parts(
+            &steps,
+            step.transaction_request().clone(),
+            step.payment_pools().clone(),
+            step.transparent_inputs().to_vec(),
+            None,
+            step.prior_step_inputs().to_vec(),
+            step.balance().clone(),
+            step.is_shielding(),
+        )
+        .map_err(|e| Error::Generic(format!("Invalid shielding proposal: {}", e)))?;
+        steps.push(step);
+    }
+    let steps = NonEmpty::from_vec(steps)
+        .ok_or_else(|| Error::Generic("Invalid shielding proposal: it has no steps".to_string()))?;
+
+    Proposal::multi_step(
+        proposal.fee_rule().clone(),
+        proposal.min_target_height(),
+        steps,
+    )
+    .map_err(|e| Error::Generic(format!("Invalid shielding proposal: {}", e)))
+}
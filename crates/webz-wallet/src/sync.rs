@@ -0,0 +1,1179 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! The wallet sync loop
+//!
+//! This follows the same strategy as `zcash_client_backend::sync::run` but is implemented here so that the
+//! wallet can observe and steer each step. The caller receives [`SyncEvent`]s as blocks are downloaded and scanned,
+//! and a [`SyncControl`] can pause or cancel the sync between batches.
+//!
+//! Unlike the upstream loop, blocks are downloaded several batches ahead of the scanner and trial decryption runs on
+//! the rayon thread pool, so network and CPU work overlap. The wallet database is never locked while waiting on the
+//! network or scanning. Each batch is scanned against a snapshot of the state it needs (see the `scan` module) and the
+//! write lock is only taken to commit the result, so balances can be read and transactions proposed mid-sync.
+//!
+//! Once scanning catches up, the wallet's transparent addresses are checked for new and spent outputs (see the
+//! `transparent` module) and the full transactions the wallet asked for are fetched (see the `enhance` module) to fill
+//! in the memos, recipients and transparent details that compact blocks leave out.
+//!
+//! Besides a one-off sync to the chain tip, [`watch`] keeps following the chain as new blocks are mined.
+
+use std::ops::Range;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+
+use zcash_client_backend::data_api::chain::{
+    error::Error as ChainError, BlockCache, ChainState, CommitmentTreeRoot,
+};
+use zcash_client_backend::data_api::scanning::{ScanPriority, ScanRange};
+use zcash_client_backend::data_api::{
+    InputSource, Ratio, WalletCommitmentTrees, WalletRead, WalletWrite,
+};
+use zcash_client_backend::proto::service::{
+    self, compact_tx_streamer_client::CompactTxStreamerClient,
+};
+use zcash_primitives::consensus::{BlockHeight, Parameters};
+use zcash_primitives::merkle_tree::HashSer;
+
+use crate::enhance;
+use crate::error::Error;
+use crate::history::TransactionIndex;
+use crate::scan::{self, BatchSummary, ScanContext, ScannedBatch};
+use crate::transparent;
+use crate::wallet::{LightwalletdChannel, WalletDb};
+
+/// The maximum number of blocks to download and scan in a single batch
+pub const BATCH_SIZE: u32 = 2500;
+
+/// The number of blocks of a batch scanned together by one thread. Chunks of a batch are scanned in parallel.
+pub const DEFAULT_SCAN_CHUNK_SIZE: usize = 100;
+
+/// The maximum number of batches being downloaded at the same time.
+///
+/// Together with [`BATCH_SIZE`] this bounds the number of compact blocks held in memory during a sync
+pub const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// How often lightwalletd is polled for new blocks while watching the chain
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Notifications emitted while syncing or watching the chain
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum SyncEvent {
+    /// A range of blocks has been downloaded or scanned
+    Progress(SyncProgress),
+    /// lightwalletd reported a new chain tip. If several blocks were mined since the last poll only the latest is reported
+    NewBlock { height: u32 },
+    /// The wallet's view of the chain was found to be inconsistent with lightwalletd at `detected_at`.
+    /// Wallet data above `rewound_to` was discarded and will be rescanned.
+    Reorg { detected_at: u32, rewound_to: u32 },
+    /// Scanning a range of blocks found notes received by, or spent from, the wallet's accounts
+    NotesDetected {
+        scan_range: (u32, u32),
+        received_sapling_notes: usize,
+        received_orchard_notes: usize,
+        spent_sapling_notes: usize,
+        spent_orchard_notes: usize,
+    },
+    /// Checking the wallet's transparent addresses after scanning found new unspent outputs or found that known ones
+    /// were spent
+    TransparentUtxosDetected {
+        new_utxos: usize,
+        spent_utxos: usize,
+    },
+    /// The wallet has caught up with the chain tip at `height`, including its transparent outputs and the full
+    /// transactions it requested. Emitted at the end of every pass over the chain.
+    Synced { height: u32 },
+    /// Full transactions requested by the wallet have been fetched after scanning. `failed` requests are retried
+    /// after the next sync.
+    Enhancement {
+        completed: usize,
+        total: usize,
+        failed: usize,
+    },
+}
+
+/// A snapshot of the state of a running sync
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProgress {
+    /// The block range currently being processed (start inclusive, end exclusive)
+    pub scan_range: (u32, u32),
+    /// The priority the wallet assigned to the range currently being processed
+    pub scan_priority: RangePriority,
+    /// Number of blocks downloaded from lightwalletd since the sync started
+    pub blocks_downloaded: u64,
+    /// Number of blocks scanned since the sync started
+    pub blocks_scanned: u64,
+    /// Fraction of the wallet's blocks of interest that have been scanned
+    pub scan_progress: Option<ProgressRatio>,
+    /// Fraction of the blocks between the wallet birthday and the recovery height that have been scanned
+    pub recovery_progress: Option<ProgressRatio>,
+    /// Estimated time until the sync completes, extrapolated from the scan rate of this sync
+    pub estimated_seconds_remaining: Option<f64>,
+    /// Chain tip height as last reported by lightwalletd
+    pub chain_tip_height: u32,
+}
+
+/// A progress fraction as reported by the wallet backend
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProgressRatio {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl ProgressRatio {
+    /// The ratio as a value between 0 and 1
+    pub fn fraction(&self) -> f64 {
+        if self.denominator == 0 {
+            1.0
+        } else {
+            self.numerator as f64 / self.denominator as f64
+        }
+    }
+}
+
+impl From<&Ratio<u64>> for ProgressRatio {
+    fn from(ratio: &Ratio<u64>) -> Self {
+        ProgressRatio {
+            numerator: *ratio.numerator(),
+            denominator: *ratio.denominator(),
+        }
+    }
+}
+
+/// Mirror of [`ScanPriority`] which can be serialized for reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RangePriority {
+    Ignored,
+    Scanned,
+    Historic,
+    OpenAdjacent,
+    FoundNote,
+    ChainTip,
+    Verify,
+}
+
+impl From<ScanPriority> for RangePriority {
+    fn from(priority: ScanPriority) -> Self {
+        match priority {
+            ScanPriority::Ignored => RangePriority::Ignored,
+            ScanPriority::Scanned => RangePriority::Scanned,
+            ScanPriority::Historic => RangePriority::Historic,
+            ScanPriority::OpenAdjacent => RangePriority::OpenAdjacent,
+            ScanPriority::FoundNote => RangePriority::FoundNote,
+            ScanPriority::ChainTip => RangePriority::ChainTip,
+            ScanPriority::Verify => RangePriority::Verify,
+        }
+    }
+}
+
+/// Keeps the running counters for a sync and delivers [`SyncEvent`]s to the caller
+struct ProgressTracker<'a, AccountId, F> {
+    on_event: &'a mut F,
+    min_confirmations: u32,
+    /// Where the transactions found in a batch are recorded as it is committed
+    index: &'a TransactionIndex<AccountId>,
+    started_at: f64,
+    blocks_downloaded: u64,
+    blocks_scanned: u64,
+    chain_tip_height: BlockHeight,
+    /// Progress as of the last committed batch. Reading it from the wallet means building a wallet summary and the
+    /// suggested scan ranges, so it is only done once per batch and reused by every report until the next commit.
+    scan_progress: Option<ProgressRatio>,
+    recovery_progress: Option<ProgressRatio>,
+    remaining_blocks: Option<u64>,
+}
+
+impl<'a, AccountId, F: FnMut(SyncEvent)> ProgressTracker<'a, AccountId, F> {
+    fn new(
+        on_event: &'a mut F,
+        min_confirmations: u32,
+        index: &'a TransactionIndex<AccountId>,
+    ) -> Self {
+        Self {
+            on_event,
+            min_confirmations,
+            index,
+            started_at: now_seconds(),
+            blocks_downloaded: 0,
+            blocks_scanned: 0,
+            chain_tip_height: BlockHeight::from_u32(0),
+            scan_progress: None,
+            recovery_progress: None,
+            remaining_blocks: None,
+        }
+    }
+
+    fn emit(&mut self, event: SyncEvent) {
+        (self.on_event)(event)
+    }
+
+    /// Read the wallet's scan and recovery progress. Called once per batch, after it has been committed.
+    fn refresh<DbT>(&mut self, db_data: &DbT) -> Result<(), Error>
+    where
+        DbT: WalletRead,
+        Error: From<DbT::Error>,
+    {
+        let summary = db_data.get_wallet_summary(self.min_confirmations)?;
+        self.scan_progress = summary
+            .as_ref()
+            .and_then(|s| s.scan_progress())
+            .map(|r| (&r).into());
+        self.recovery_progress = summary
+            .as_ref()
+            .and_then(|s| s.recovery_progress())
+            .map(|r| (&r).into());
+        self.remaining_blocks = Some(
+            db_data
+                .suggest_scan_ranges()?
+                .iter()
+                .filter(|range| range.priority() > ScanPriority::Scanned)
+                .map(|range| range.len() as u64)
+                .sum(),
+        );
+        Ok(())
+    }
+
+    /// Emit a [`SyncEvent::Progress`] for `scan_range` from the counters and the progress last read by
+    /// [`Self::refresh`]
+    fn report(&mut self, scan_range: &ScanRange) {
+        let elapsed = now_seconds() - self.started_at;
+        let estimated_seconds_remaining = self
+            .remaining_blocks
+            .filter(|_| self.blocks_scanned > 0 && elapsed > 0.0)
+            .map(|remaining| remaining as f64 / (self.blocks_scanned as f64 / elapsed));
+
+        self.emit(SyncEvent::Progress(SyncProgress {
+            scan_range: range_bounds(scan_range.block_range()),
+            scan_priority: scan_range.priority().into(),
+            blocks_downloaded: self.blocks_downloaded,
+            blocks_scanned: self.blocks_scanned,
+            scan_progress: self.scan_progress,
+            recovery_progress: self.recovery_progress,
+            estimated_seconds_remaining,
+            chain_tip_height: self.chain_tip_height.into(),
+        }));
+    }
+}
+
+/// Requested state of a sync, set through a [`SyncControl`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncState {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+/// A handle for pausing, resuming and cancelling a sync from outside of the task running it.
+///
+/// Requests are only acted on at batch boundaries, after the previous batch has been committed to the wallet
+/// database, so a paused or cancelled sync always leaves the database in a consistent state.
+/// While paused the sync does not hold any lock on the wallet database.
+///
+/// The control also makes sure only one sync or watch of a wallet runs at a time, see [`SyncControl::start`].
+#[derive(Debug, Clone)]
+pub struct SyncControl {
+    state: Arc<watch::Sender<SyncState>>,
+    active: Arc<AtomicBool>,
+}
+
+impl Default for SyncControl {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(watch::channel(SyncState::Running).0),
+            active: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Marks a sync as running until it is dropped. Returned by [`SyncControl::start`].
+///
+/// Dropping it also clears any pause or cancel request, so one made for a sync never carries over to the next.
+#[derive(Debug)]
+pub struct ActiveSync {
+    state: Arc<watch::Sender<SyncState>>,
+    active: Arc<AtomicBool>,
+}
+
+impl Drop for ActiveSync {
+    fn drop(&mut self) {
+        // Cleared before releasing the claim so the request of a sync started right after isn't cleared instead
+        self.state.send_replace(SyncState::Running);
+        self.active.store(false, Ordering::Release);
+    }
+}
+
+impl SyncControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the running sync to pause at the next batch boundary. Has no effect on a cancelled sync.
+    pub fn pause(&self) {
+        self.transition(SyncState::Running, SyncState::Paused);
+    }
+
+    /// Resume a paused sync
+    pub fn resume(&self) {
+        self.transition(SyncState::Paused, SyncState::Running);
+    }
+
+    /// Ask the running sync to stop at the next batch boundary
+    pub fn cancel(&self) {
+        self.state.send_replace(SyncState::Cancelled);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.state.borrow() == SyncState::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.state.borrow() == SyncState::Cancelled
+    }
+
+    /// Claim the wallet for a new sync. Returns [`Error::SyncAlreadyRunning`] if another sync or watch holds it.
+    ///
+    /// The claim is released, and any pause or cancel request cleared, when the returned [`ActiveSync`] is dropped.
+    pub fn start(&self) -> Result<ActiveSync, Error> {
+        self.active
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| Error::SyncAlreadyRunning)?;
+        Ok(ActiveSync {
+            state: self.state.clone(),
+            active: self.active.clone(),
+        })
+    }
+
+    /// Whether a sync or watch currently holds the claim made by [`Self::start`]
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Clear any previous pause or cancel request so the control can be reused for a new sync
+    pub fn reset(&self) {
+        self.state.send_replace(SyncState::Running);
+    }
+
+    fn transition(&self, from: SyncState, to: SyncState) {
+        self.state.send_if_modified(|state| {
+            if *state == from {
+                *state = to;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// Called at a batch boundary. Returns `Ok(true)` if the sync should release the database and wait
+    /// in [`Self::wait_until_resumed`], or an error if it has been cancelled.
+    pub(crate) fn should_pause(&self) -> Result<bool, Error> {
+        match *self.state.borrow() {
+            SyncState::Running => Ok(false),
+            SyncState::Paused => Ok(true),
+            SyncState::Cancelled => Err(Error::SyncCancelled),
+        }
+    }
+
+    /// Sleep for `duration`, waking early if the sync is paused, resumed or cancelled in the meantime
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        let mut state = self.state.subscribe();
+        let sleep = pin!(tokio_with_wasm::alias::time::sleep(duration));
+        let changed = pin!(state.changed());
+        future::select(sleep, changed).await;
+    }
+
+    pub(crate) async fn wait_until_resumed(&self) -> Result<(), Error> {
+        tracing::info!("Sync paused");
+        let mut state = self.state.subscribe();
+        let resumed_state = *state
+            .wait_for(|state| *state != SyncState::Paused)
+            .await
+            .map_err(|_| Error::SyncCancelled)?;
+        match resumed_state {
+            SyncState::Cancelled => Err(Error::SyncCancelled),
+            _ => {
+                tracing::info!("Sync resumed");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Download and scan blocks until the wallet is synchronized with the chain tip reported by lightwalletd.
+///
+/// `on_event` receives a [`SyncEvent::Progress`] after each range has been downloaded and after each range has been
+/// scanned, along with any reorgs and detected notes.
+/// If `control` is used to cancel the sync this returns [`Error::SyncCancelled`] once the current batch is committed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run<P, ChT, CaT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db_cache: &Arc<CaT>,
+    db: &RwLock<DbT>,
+    batch_size: u32,
+    scan_chunk_size: usize,
+    min_confirmations: u32,
+    index: &TransactionIndex<<DbT as WalletRead>::AccountId>,
+    control: &SyncControl,
+    on_event: &mut F,
+) -> Result<(), Error>
+where
+    P: Parameters + Clone + Send + 'static,
+    ChT: LightwalletdChannel,
+    CaT: BlockCache + 'static,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletDb,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+    F: FnMut(SyncEvent),
+{
+    let mut progress = ProgressTracker::new(on_event, min_confirmations, index);
+
+    // Download note commitment tree data from lightwalletd and pass it to the wallet
+    update_subtree_roots(client, db).await?;
+
+    while running(
+        client,
+        params,
+        db_cache,
+        db,
+        batch_size,
+        scan_chunk_size,
+        control,
+        &mut progress,
+    )
+    .await?
+    {}
+
+    refresh_transparent(client, params, db, &mut progress).await?;
+    // Fetch the full transactions the wallet wants now that it has seen them in compact blocks
+    enhance::run(client, params, db, index, control, &mut |event| {
+        progress.emit(event)
+    })
+    .await?;
+    progress.emit(SyncEvent::Synced {
+        height: progress.chain_tip_height.into(),
+    });
+
+    Ok(())
+}
+
+/// Sync to the chain tip and then keep following the chain, scanning new blocks as soon as lightwalletd reports them.
+///
+/// lightwalletd is polled for a new chain tip every `poll_interval`. A [`SyncEvent::NewBlock`] is emitted for each
+/// new tip and reorgs are handled by rewinding and rescanning as in [`run`]. This only returns once `control` is used
+/// to cancel it, in which case it returns `Ok(())`, or if an error occurs.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn watch<P, ChT, CaT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db_cache: &Arc<CaT>,
+    db: &RwLock<DbT>,
+    batch_size: u32,
+    scan_chunk_size: usize,
+    min_confirmations: u32,
+    index: &TransactionIndex<<DbT as WalletRead>::AccountId>,
+    poll_interval: Duration,
+    control: &SyncControl,
+    on_event: &mut F,
+) -> Result<(), Error>
+where
+    P: Parameters + Clone + Send + 'static,
+    ChT: LightwalletdChannel,
+    CaT: BlockCache + 'static,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletDb,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+    F: FnMut(SyncEvent),
+{
+    let mut progress = ProgressTracker::new(on_event, min_confirmations, index);
+
+    match follow_chain(
+        client,
+        params,
+        db_cache,
+        db,
+        batch_size,
+        scan_chunk_size,
+        poll_interval,
+        control,
+        &mut progress,
+    )
+    .await
+    {
+        Err(Error::SyncCancelled) => Ok(()),
+        result => result,
+    }
+}
+
+/// The body of [`watch`]. Only returns on error, including when cancelled.
+#[allow(clippy::too_many_arguments)]
+async fn follow_chain<P, ChT, CaT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db_cache: &Arc<CaT>,
+    db: &RwLock<DbT>,
+    batch_size: u32,
+    scan_chunk_size: usize,
+    poll_interval: Duration,
+    control: &SyncControl,
+    progress: &mut ProgressTracker<'_, <DbT as WalletRead>::AccountId, F>,
+) -> Result<(), Error>
+where
+    P: Parameters + Clone + Send + 'static,
+    ChT: LightwalletdChannel,
+    CaT: BlockCache + 'static,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletDb,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+    F: FnMut(SyncEvent),
+{
+    update_subtree_roots(client, db).await?;
+
+    loop {
+        while running(
+            client,
+            params,
+            db_cache,
+            db,
+            batch_size,
+            scan_chunk_size,
+            control,
+            progress,
+        )
+        .await?
+        {}
+        refresh_transparent(client, params, db, progress).await?;
+        let index = progress.index;
+        enhance::run(client, params, db, index, control, &mut |event| {
+            progress.emit(event)
+        })
+        .await?;
+        progress.emit(SyncEvent::Synced {
+            height: progress.chain_tip_height.into(),
+        });
+
+        // Caught up. Wait for lightwalletd to report a new tip before running another pass
+        let synced_tip = progress.chain_tip_height;
+        loop {
+            control.sleep(poll_interval).await;
+            if control.should_pause()? {
+                control.wait_until_resumed().await?;
+            }
+            let tip = fetch_chain_tip(client).await?;
+            if tip != synced_tip {
+                // A lower tip than before means lightwalletd switched to a shorter chain. The next
+                // pass will detect any resulting discontinuity when it scans the new tip.
+                progress.emit(SyncEvent::NewBlock { height: tip.into() });
+                break;
+            }
+        }
+    }
+}
+
+/// A single pass over the suggested scan ranges.
+///
+/// Ranges that need verifying are handled one at a time. The remaining ranges are split into batches which are
+/// downloaded up to [`MAX_CONCURRENT_DOWNLOADS`] at a time while earlier batches are being scanned.
+///
+/// Returns `true` if the pass was interrupted, either because the suggested scan ranges changed or because
+/// the sync was paused, and the ranges should be re-requested
+#[allow(clippy::too_many_arguments)]
+async fn running<P, ChT, CaT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db_cache: &Arc<CaT>,
+    db: &RwLock<DbT>,
+    batch_size: u32,
+    scan_chunk_size: usize,
+    control: &SyncControl,
+    progress: &mut ProgressTracker<'_, <DbT as WalletRead>::AccountId, F>,
+) -> Result<bool, Error>
+where
+    P: Parameters + Clone + Send + 'static,
+    ChT: LightwalletdChannel,
+    CaT: BlockCache + 'static,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletDb,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+    F: FnMut(SyncEvent),
+{
+    progress.chain_tip_height = update_chain_tip(client, db).await?;
+    // Blocks above the tip were cached from a chain lightwalletd has since abandoned
+    db_cache.truncate(progress.chain_tip_height).await?;
+
+    // If there is a range of blocks that needs to be verified, it will always be returned as the first
+    // element of the vector of suggested ranges. Run this until the wallet's view of the chain tip as of
+    // the previous session is valid.
+    let mut scan_ranges = {
+        let db_data = db.read().await;
+        progress.refresh(&*db_data)?;
+        db_data.suggest_scan_ranges()?
+    };
+    while let Some(scan_range) = scan_ranges
+        .first()
+        .filter(|range| range.priority() == ScanPriority::Verify)
+        .cloned()
+    {
+        if control.should_pause()? {
+            control.wait_until_resumed().await?;
+            return Ok(true);
+        }
+
+        let batch = download_batch(client.clone(), &**db_cache, scan_range).await?;
+        progress.blocks_downloaded += batch.scan_range.len() as u64;
+        progress.report(&batch.scan_range);
+
+        if !scan_batch(params, db_cache, db, batch, scan_chunk_size, progress).await? {
+            break;
+        }
+        scan_ranges = db.read().await.suggest_scan_ranges()?;
+    }
+
+    // Split the remaining suggested scan ranges into batches
+    let scan_ranges = db.read().await.suggest_scan_ranges()?;
+    tracing::debug!("Suggested ranges: {:?}", scan_ranges);
+
+    // Everything below the lowest suggested range has already been scanned. A cache that outlives the sync,
+    // such as one persisted by a previous session, can still hold some of those blocks so evict them here.
+    let scanned_below = scan_ranges
+        .iter()
+        .map(|range| range.block_range().start)
+        .min()
+        .unwrap_or(progress.chain_tip_height + 1);
+    db_cache
+        .delete(ScanRange::from_parts(
+            BlockHeight::from_u32(0)..scanned_below,
+            ScanPriority::Ignored,
+        ))
+        .await?;
+
+    let batches = scan_ranges
+        .into_iter()
+        .flat_map(|range| split_into_batches(range, batch_size));
+
+    // Download batches in order, with several downloads in flight at once. Completed downloads are handed to
+    // the scanner through a channel with a single slot so at most `MAX_CONCURRENT_DOWNLOADS + 2` batches are
+    // held in the cache at any time.
+    let (batch_tx, mut batch_rx) = mpsc::channel(1);
+    let producer = {
+        let client = client.clone();
+        async move {
+            let mut downloads = stream::iter(batches)
+                .map(|scan_range| download_batch(client.clone(), &**db_cache, scan_range))
+                .buffered(MAX_CONCURRENT_DOWNLOADS);
+            while let Some(batch) = downloads.next().await {
+                if batch_tx.send(batch).await.is_err() {
+                    // The scanner stopped early
+                    break;
+                }
+            }
+        }
+    };
+
+    let consumer = async {
+        while let Some(batch) = batch_rx.recv().await {
+            let batch = batch?;
+            progress.blocks_downloaded += batch.scan_range.len() as u64;
+            progress.report(&batch.scan_range);
+
+            if control.should_pause()? {
+                control.wait_until_resumed().await?;
+                return Ok(true);
+            }
+
+            if scan_batch(params, db_cache, db, batch, scan_chunk_size, progress).await? {
+                return Ok(true);
+            }
+        }
+        Ok::<_, Error>(false)
+    };
+
+    // Batches which were downloaded but not scanned before the pass was interrupted stay in the cache and are
+    // picked up by the next pass
+    match future::select(pin!(producer), pin!(consumer)).await {
+        Either::Left(((), consumer)) => consumer.await,
+        Either::Right((result, _producer)) => result,
+    }
+}
+
+/// A batch of blocks which has been downloaded into the block cache and is ready to be scanned
+struct DownloadedBatch {
+    scan_range: ScanRange,
+    /// The note commitment tree state as of the block before the batch
+    chain_state: ChainState,
+}
+
+/// Download a batch of blocks into the block cache, along with the chain state needed to scan them
+async fn download_batch<ChT, CaT>(
+    mut client: CompactTxStreamerClient<ChT>,
+    db_cache: &CaT,
+    scan_range: ScanRange,
+) -> Result<DownloadedBatch, Error>
+where
+    ChT: LightwalletdChannel,
+    CaT: BlockCache,
+    Error: From<CaT::Error>,
+{
+    if scan_range.priority() == ScanPriority::Verify {
+        // Verifying checks the wallet's view of the chain against lightwalletd, which cached blocks from before a
+        // reorg would defeat. Blocks cached for the range are dropped so none are left over if the chain got shorter.
+        db_cache.delete(scan_range.clone()).await?;
+        download_blocks(&mut client, db_cache, &scan_range).await?;
+    } else if db_cache.read(&scan_range).await?.len() != scan_range.len() {
+        download_blocks(&mut client, db_cache, &scan_range).await?;
+    } else {
+        // Blocks left over from an interrupted sync don't need downloading again
+        tracing::info!("Using cached blocks for {}", scan_range);
+    }
+    let chain_state = download_chain_state(&mut client, scan_range.block_range().start - 1).await?;
+    Ok(DownloadedBatch {
+        scan_range,
+        chain_state,
+    })
+}
+
+/// Scan a downloaded batch, commit it to the wallet and then evict it from the cache.
+///
+/// The wallet is only locked to read what scanning needs and again to commit the results. In between, trial
+/// decryption runs on the rayon thread pool without holding any lock, so other wallet operations can go ahead and
+/// the calling task is free to keep driving downloads.
+///
+/// Returns `true` if the batch changed the suggested scan ranges or has to be scanned again.
+async fn scan_batch<P, CaT, DbT, F>(
+    params: &P,
+    db_cache: &Arc<CaT>,
+    db: &RwLock<DbT>,
+    batch: DownloadedBatch,
+    chunk_size: usize,
+    progress: &mut ProgressTracker<'_, <DbT as WalletRead>::AccountId, F>,
+) -> Result<bool, Error>
+where
+    P: Parameters + Clone + Send + 'static,
+    CaT: BlockCache + 'static,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletDb,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+    F: FnMut(SyncEvent),
+{
+    let DownloadedBatch {
+        scan_range,
+        chain_state,
+    } = batch;
+    let from_height = scan_range.block_range().start;
+
+    let context = ScanContext::read(&*db.read().await, from_height)?;
+
+    let scan_result = {
+        let params = params.clone();
+        let db_cache = db_cache.clone();
+        let scan_range = scan_range.clone();
+        spawn_blocking(move || {
+            tracing::info!("Scanning {}", scan_range);
+            let started_at = now_seconds();
+            let result = scan::scan_blocks(
+                &params,
+                &*db_cache,
+                context,
+                from_height,
+                scan_range.len(),
+                chunk_size,
+            );
+            tracing::info!(
+                "Scanned {} in {:.2}s with chunks of {} blocks",
+                scan_range,
+                now_seconds() - started_at,
+                chunk_size,
+            );
+            result
+        })
+        .await?
+    };
+
+    let outcome = {
+        let mut db_data = db.write().await;
+        let outcome = handle_scan_result(
+            &**db_cache,
+            &mut *db_data,
+            &scan_range,
+            &chain_state,
+            progress.index,
+            scan_result,
+        )
+        .await?;
+        // Read the new progress while the wallet is still locked for the commit
+        progress.refresh(&*db_data)?;
+        outcome
+    };
+
+    let ranges_updated = match outcome {
+        ScanOutcome::Scanned {
+            summary,
+            ranges_updated,
+        } => {
+            if summary.has_notes() {
+                progress.emit(SyncEvent::NotesDetected {
+                    scan_range: range_bounds(&summary.scanned_range),
+                    received_sapling_notes: summary.received_sapling_notes,
+                    received_orchard_notes: summary.received_orchard_notes,
+                    spent_sapling_notes: summary.spent_sapling_notes,
+                    spent_orchard_notes: summary.spent_orchard_notes,
+                });
+            }
+            progress.blocks_scanned += scan_range.len() as u64;
+
+            // Delete the now-scanned blocks
+            db_cache.delete(scan_range.clone()).await?;
+            ranges_updated
+        }
+        ScanOutcome::Reorg {
+            detected_at,
+            rewound_to,
+        } => {
+            progress.emit(SyncEvent::Reorg {
+                detected_at: detected_at.into(),
+                rewound_to: rewound_to.into(),
+            });
+            true
+        }
+        ScanOutcome::WalletChanged => {
+            tracing::info!(
+                "The wallet changed while scanning {}, scanning it again",
+                scan_range
+            );
+            true
+        }
+    };
+
+    progress.report(&scan_range);
+    Ok(ranges_updated)
+}
+
+/// Look for transparent funds now that the wallet has caught up with the chain tip
+async fn refresh_transparent<P, ChT, DbT, F>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    progress: &mut ProgressTracker<'_, <DbT as WalletRead>::AccountId, F>,
+) -> Result<(), Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite + InputSource<Error = <DbT as WalletRead>::Error>,
+    Error: From<<DbT as WalletRead>::Error>,
+    F: FnMut(SyncEvent),
+{
+    let summary = transparent::refresh(
+        client,
+        params,
+        db,
+        progress.index,
+        progress.chain_tip_height,
+    )
+    .await?;
+    if summary.new_utxos > 0 || summary.spent_utxos > 0 {
+        progress.emit(SyncEvent::TransparentUtxosDetected {
+            new_utxos: summary.new_utxos,
+            spent_utxos: summary.spent_utxos,
+        });
+    }
+    Ok(())
+}
+
+/// Run CPU heavy work on the rayon thread pool and wait for the result without blocking the calling task.
+///
+/// Without the `multicore` feature there is no thread pool and `f` runs inline.
+async fn spawn_blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    #[cfg(feature = "multicore")]
+    {
+        let (tx, rx) = oneshot::channel();
+        rayon::spawn(move || {
+            let _ = tx.send(f());
+        });
+        rx.await
+            .map_err(|_| Error::Sync("Scanning task panicked".to_string()))
+    }
+    #[cfg(not(feature = "multicore"))]
+    {
+        Ok(f())
+    }
+}
+
+/// Split a scan range into consecutive ranges of at most `batch_size` blocks
+pub(crate) fn split_into_batches(
+    range: ScanRange,
+    batch_size: u32,
+) -> impl Iterator<Item = ScanRange> {
+    (0..).scan(range, move |acc, _| {
+        if acc.is_empty() {
+            None
+        } else if let Some((cur, next)) = acc.split_at(acc.block_range().start + batch_size) {
+            *acc = next;
+            Some(cur)
+        } else {
+            let cur = acc.clone();
+            let end = acc.block_range().end;
+            *acc = ScanRange::from_parts(end..end, acc.priority());
+            Some(cur)
+        }
+    })
+}
+
+pub(crate) fn range_bounds(range: &Range<BlockHeight>) -> (u32, u32) {
+    (range.start.into(), range.end.into())
+}
+
+async fn update_subtree_roots<ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    db: &RwLock<DbT>,
+) -> Result<(), Error>
+where
+    ChT: LightwalletdChannel,
+    DbT: WalletCommitmentTrees,
+    <DbT as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut request = service::GetSubtreeRootsArg::default();
+    request.set_shielded_protocol(service::ShieldedProtocol::Sapling);
+    // Hack to work around a bug in the initial lightwalletd implementation.
+    request.max_entries = 65536;
+
+    let sapling_roots: Vec<CommitmentTreeRoot<sapling::Node>> = client
+        .get_subtree_roots(request)
+        .await?
+        .into_inner()
+        .map_err(Error::from)
+        .and_then(|root| async move {
+            let root_hash = sapling::Node::read(&root.root_hash[..])?;
+            Ok(CommitmentTreeRoot::from_parts(
+                BlockHeight::from_u32(root.completing_block_height as u32),
+                root_hash,
+            ))
+        })
+        .try_collect()
+        .await?;
+
+    tracing::info!("Sapling tree has {} subtrees", sapling_roots.len());
+
+    let mut request = service::GetSubtreeRootsArg::default();
+    request.set_shielded_protocol(service::ShieldedProtocol::Orchard);
+    request.max_entries = 65536;
+
+    let orchard_roots: Vec<CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>> = client
+        .get_subtree_roots(request)
+        .await?
+        .into_inner()
+        .map_err(Error::from)
+        .and_then(|root| async move {
+            let root_hash = orchard::tree::MerkleHashOrchard::read(&root.root_hash[..])?;
+            Ok(CommitmentTreeRoot::from_parts(
+                BlockHeight::from_u32(root.completing_block_height as u32),
+                root_hash,
+            ))
+        })
+        .try_collect()
+        .await?;
+
+    tracing::info!("Orchard tree has {} subtrees", orchard_roots.len());
+
+    // Only lock the wallet once everything has been downloaded
+    let mut db_data = db.write().await;
+    db_data
+        .put_sapling_subtree_roots(0, &sapling_roots)
+        .map_err(|e| Error::Sync(e.to_string()))?;
+    db_data
+        .put_orchard_subtree_roots(0, &orchard_roots)
+        .map_err(|e| Error::Sync(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Fetch the latest block height from lightwalletd and notify the wallet of the new chain tip
+async fn update_chain_tip<ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    db: &RwLock<DbT>,
+) -> Result<BlockHeight, Error>
+where
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error>,
+{
+    let tip_height = fetch_chain_tip(client).await?;
+    tracing::info!("Latest block height is {}", tip_height);
+    db.write().await.update_chain_tip(tip_height)?;
+
+    Ok(tip_height)
+}
+
+async fn fetch_chain_tip<ChT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+) -> Result<BlockHeight, Error>
+where
+    ChT: LightwalletdChannel,
+{
+    client
+        .get_latest_block(service::ChainSpec::default())
+        .await?
+        .get_ref()
+        .height
+        .try_into()
+        .map_err(|_| Error::Sync("lightwalletd returned an invalid chain tip height".to_string()))
+}
+
+async fn download_blocks<ChT, CaT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    db_cache: &CaT,
+    scan_range: &ScanRange,
+) -> Result<(), Error>
+where
+    ChT: LightwalletdChannel,
+    CaT: BlockCache,
+    Error: From<CaT::Error>,
+{
+    tracing::info!("Fetching {}", scan_range);
+    let range = service::BlockRange {
+        start: Some(service::BlockId {
+            height: scan_range.block_range().start.into(),
+            ..Default::default()
+        }),
+        end: Some(service::BlockId {
+            height: (scan_range.block_range().end - 1).into(),
+            ..Default::default()
+        }),
+    };
+    let compact_blocks = client
+        .get_block_range(range)
+        .await?
+        .into_inner()
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    db_cache.insert(compact_blocks).await?;
+
+    Ok(())
+}
+
+async fn download_chain_state<ChT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    block_height: BlockHeight,
+) -> Result<ChainState, Error>
+where
+    ChT: LightwalletdChannel,
+{
+    let tree_state = client
+        .get_tree_state(service::BlockId {
+            height: block_height.into(),
+            hash: vec![],
+        })
+        .await?;
+
+    tree_state
+        .into_inner()
+        .to_chain_state()
+        .map_err(|_| Error::Sync("lightwalletd returned an invalid tree state".to_string()))
+}
+
+/// What happened when committing a scanned batch
+enum ScanOutcome {
+    Scanned {
+        summary: BatchSummary,
+        /// Whether scanning added a suggested range with a higher priority than the batch's range
+        ranges_updated: bool,
+    },
+    /// The blocks didn't connect to the wallet's view of the chain and the wallet was rewound
+    Reorg {
+        detected_at: BlockHeight,
+        rewound_to: BlockHeight,
+    },
+    /// Accounts were added or removed, or the wallet was rewound, while the batch was being scanned so nothing was
+    /// committed
+    WalletChanged,
+}
+
+/// Commit the result of scanning a block range to the wallet. Errors that indicate the wallet's chain tip is out of
+/// sync with blockchain history rewind the wallet instead.
+async fn handle_scan_result<CaT, DbT>(
+    db_cache: &CaT,
+    db_data: &mut DbT,
+    scan_range: &ScanRange,
+    chain_state: &ChainState,
+    index: &TransactionIndex<DbT::AccountId>,
+    scan_result: Result<
+        ScannedBatch<DbT::AccountId>,
+        ChainError<<DbT as WalletRead>::Error, CaT::Error>,
+    >,
+) -> Result<ScanOutcome, Error>
+where
+    CaT: BlockCache,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error> + From<CaT::Error>,
+{
+    match scan_result {
+        Err(ChainError::Scan(err)) if err.is_continuity_error() => {
+            // Pick a height to rewind to, which must be at least one block before the
+            // height at which the error occurred.
+            let rewind_height = err.at_height().saturating_sub(10);
+            tracing::info!(
+                "Chain reorg detected at {}, rewinding to {}",
+                err.at_height(),
+                rewind_height,
+            );
+
+            db_data.truncate_to_height(rewind_height)?;
+            db_cache.truncate(rewind_height).await?;
+            index.truncate(rewind_height);
+
+            // The database was truncated, invalidating prior suggested ranges.
+            Ok(ScanOutcome::Reorg {
+                detected_at: err.at_height(),
+                rewound_to: rewind_height,
+            })
+        }
+        Ok(batch) => {
+            let Some(summary) = batch.commit(db_data, chain_state, index)? else {
+                return Ok(ScanOutcome::WalletChanged);
+            };
+
+            // If scanning these blocks caused a suggested range to be added that has a
+            // higher priority than the current range, invalidate the current ranges.
+            let latest_ranges = db_data.suggest_scan_ranges()?;
+
+            Ok(ScanOutcome::Scanned {
+                summary,
+                ranges_updated: if let Some(range) = latest_ranges.first() {
+                    range.priority() > scan_range.priority()
+                } else {
+                    false
+                },
+            })
+        }
+        Err(ChainError::Scan(err)) => Err(Error::Scan(err)),
+        Err(ChainError::Wallet(err)) => Err(err.into()),
+        Err(ChainError::BlockSource(err)) => Err(err.into()),
+    }
+}
+
+/// Current wall-clock time in seconds
+fn now_seconds() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now() / 1000.0
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default()
+    }
+}
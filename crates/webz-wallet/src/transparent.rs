@@ -0,0 +1,269 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Finding funds sent to the wallet's transparent addresses
+//!
+//! Transparent outputs aren't in compact blocks so scanning never sees them. After each sync lightwalletd is asked
+//! with `GetAddressUtxos` for the unspent outputs of every transparent receiver the wallet has handed out. New ones
+//! are stored along with the transactions that created them. A known output that lightwalletd no longer lists has been
+//! spent, and the spending transaction is found with `GetTaddressTxids` and stored so the wallet marks it spent.
+//!
+//! Coinbase outputs can't be spent until they have [`COINBASE_MATURITY`] confirmations. They are stored as soon as
+//! they are found and the height they mature at is recorded in the [`TransactionIndex`], which keeps them out of
+//! proposals and the spendable balance until then.
+
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::RwLock;
+use zcash_client_backend::data_api::wallet::decrypt_and_store_transaction;
+use zcash_client_backend::data_api::{InputSource, WalletRead, WalletWrite};
+use zcash_client_backend::proto::service::{
+    self, compact_tx_streamer_client::CompactTxStreamerClient, GetAddressUtxosArg,
+    TransparentAddressBlockFilter,
+};
+use zcash_client_backend::wallet::WalletTransparentOutput;
+use zcash_keys::encoding::AddressCodec;
+use zcash_primitives::consensus::{BlockHeight, BranchId, Parameters};
+use zcash_primitives::legacy::{Script, TransparentAddress};
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::{OutPoint, TxOut};
+use zcash_primitives::transaction::{Transaction, TxId};
+
+use crate::enhance;
+use crate::error::Error;
+use crate::history::{Owners, TransactionIndex};
+use crate::wallet::LightwalletdChannel;
+
+/// Number of confirmations a coinbase output needs before it can be spent
+pub(crate) const COINBASE_MATURITY: u32 = 100;
+
+/// What a refresh found
+#[derive(Debug, Default)]
+pub(crate) struct RefreshSummary {
+    pub(crate) new_utxos: usize,
+    pub(crate) spent_utxos: usize,
+}
+
+/// Bring the wallet's transparent outputs up to date with lightwalletd's view of the chain up to `chain_tip`. The
+/// transactions stored along the way, and the maturity of any coinbase outputs found, are recorded in `tx_index`.
+pub(crate) async fn refresh<P, ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    tx_index: &TransactionIndex<<DbT as WalletRead>::AccountId>,
+    chain_tip: BlockHeight,
+) -> Result<RefreshSummary, Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite + InputSource<Error = <DbT as WalletRead>::Error>,
+    Error: From<<DbT as WalletRead>::Error>,
+{
+    let mut summary = RefreshSummary::default();
+
+    // Every receiver handed out so far and the height to search from, which is the earliest birthday of the accounts
+    let (addresses, start_height, known_utxos) = {
+        let db = db.read().await;
+        let mut addresses = HashSet::new();
+        let mut start_height: Option<BlockHeight> = None;
+        for account_id in db.get_account_ids()? {
+            let birthday = db.get_account_birthday(account_id)?;
+            start_height = Some(start_height.map_or(birthday, |h| h.min(birthday)));
+            addresses.extend(db.get_transparent_receivers(account_id)?.into_keys());
+        }
+        let mut known_utxos = HashMap::new();
+        for address in &addresses {
+            for utxo in db.get_spendable_transparent_outputs(address, chain_tip + 1, 0)? {
+                known_utxos.insert(utxo.outpoint().clone(), (*address, utxo.mined_height()));
+            }
+        }
+        (addresses, start_height, known_utxos)
+    };
+    let Some(start_height) = start_height else {
+        return Ok(summary);
+    };
+    if addresses.is_empty() {
+        return Ok(summary);
+    }
+    let owners = Owners::read(&*db.read().await)?;
+
+    let replies = client
+        .get_address_utxos(GetAddressUtxosArg {
+            addresses: addresses
+                .iter()
+                .map(|address| address.encode(params))
+                .collect(),
+            start_height: start_height.into(),
+            max_entries: 0,
+        })
+        .await?
+        .into_inner()
+        .address_utxos;
+
+    let invalid = |what: &str| Error::Sync(format!("lightwalletd returned an invalid {}", what));
+    let mut unspent = HashSet::new();
+    for reply in replies {
+        let txid = TxId::from_bytes(
+            reply
+                .txid
+                .as_slice()
+                .try_into()
+                .map_err(|_| invalid("txid"))?,
+        );
+        let index = u32::try_from(reply.index).map_err(|_| invalid("output index"))?;
+        let height = u32::try_from(reply.height).map_err(|_| invalid("block height"))?;
+        let height = BlockHeight::from_u32(height);
+        let outpoint = OutPoint::new(*txid.as_ref(), index);
+        unspent.insert(outpoint.clone());
+        if known_utxos.contains_key(&outpoint) {
+            continue;
+        }
+
+        let coinbase =
+            store_transaction(client, params, db, tx_index, &owners, txid, Some(height)).await?;
+        let Some(output) = WalletTransparentOutput::from_parts(
+            outpoint.clone(),
+            TxOut {
+                value: NonNegativeAmount::from_nonnegative_i64(reply.value_zat)?,
+                script_pubkey: Script(reply.script),
+            },
+            Some(height),
+        ) else {
+            // The script isn't a P2PKH or P2SH script so the output can't be ours
+            continue;
+        };
+        if coinbase == Some(true) {
+            tx_index.insert_coinbase(&outpoint, height + COINBASE_MATURITY);
+        }
+        db.write().await.put_received_transparent_utxo(&output)?;
+        summary.new_utxos += 1;
+    }
+
+    // Outputs the wallet thinks are unspent but lightwalletd doesn't were spent, possibly by another wallet. The
+    // transactions of each of their addresses are fetched once, from the oldest of the address's missing outputs.
+    let mut spent_from: HashMap<TransparentAddress, (BlockHeight, Vec<OutPoint>)> = HashMap::new();
+    for (outpoint, (address, mined_height)) in known_utxos {
+        if unspent.contains(&outpoint) {
+            continue;
+        }
+        let from_height = mined_height.unwrap_or(start_height);
+        let (height, outpoints) = spent_from.entry(address).or_insert((from_height, vec![]));
+        *height = (*height).min(from_height);
+        outpoints.push(outpoint);
+    }
+    for (address, (from_height, outpoints)) in spent_from {
+        store_address_transactions(
+            client,
+            params,
+            db,
+            tx_index,
+            &owners,
+            &address,
+            from_height,
+            chain_tip,
+        )
+        .await?;
+
+        // Only the mined transactions up to `chain_tip` were fetched, so an output the wallet now sees as spent was
+        // spent in the main chain
+        let still_unspent: HashSet<_> = db
+            .read()
+            .await
+            .get_spendable_transparent_outputs(&address, chain_tip + 1, 0)?
+            .iter()
+            .map(|utxo| utxo.outpoint().clone())
+            .collect();
+        summary.spent_utxos += outpoints
+            .iter()
+            .filter(|outpoint| !still_unspent.contains(outpoint))
+            .count();
+    }
+
+    Ok(summary)
+}
+
+/// Store every transaction involving `address` mined from `from_height` up to and including `to_height`
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn store_address_transactions<P, ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    index: &TransactionIndex<DbT::AccountId>,
+    owners: &Owners<DbT::AccountId>,
+    address: &TransparentAddress,
+    from_height: BlockHeight,
+    to_height: BlockHeight,
+) -> Result<(), Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error>,
+{
+    let block_id = |height: BlockHeight| service::BlockId {
+        height: height.into(),
+        hash: vec![],
+    };
+    let mut stream = client
+        .get_taddress_txids(TransparentAddressBlockFilter {
+            address: address.encode(params),
+            range: Some(service::BlockRange {
+                start: Some(block_id(from_height)),
+                end: Some(block_id(to_height)),
+            }),
+        })
+        .await?
+        .into_inner();
+
+    while let Some(raw_tx) = stream.message().await? {
+        let mined_height = u32::try_from(raw_tx.height)
+            .ok()
+            .filter(|height| *height > 0)
+            .map(BlockHeight::from_u32);
+        let branch_height = match mined_height {
+            Some(height) => height,
+            None => enhance::next_block_height(&*db.read().await)?,
+        };
+        let tx = Transaction::read(
+            &raw_tx.data[..],
+            BranchId::for_height(params, branch_height),
+        )?;
+        decrypt_and_store_transaction(params, &mut *db.write().await, &tx, mined_height)?;
+        index.record_transaction(params, &*db.read().await, owners, &tx, mined_height)?;
+    }
+    Ok(())
+}
+
+/// Fetch and store a transaction unless the wallet already has it. Returns whether it is a coinbase transaction, or
+/// `None` if lightwalletd doesn't know it.
+async fn store_transaction<P, ChT, DbT>(
+    client: &mut CompactTxStreamerClient<ChT>,
+    params: &P,
+    db: &RwLock<DbT>,
+    index: &TransactionIndex<DbT::AccountId>,
+    owners: &Owners<DbT::AccountId>,
+    txid: TxId,
+    mined_height: Option<BlockHeight>,
+) -> Result<Option<bool>, Error>
+where
+    P: Parameters,
+    ChT: LightwalletdChannel,
+    DbT: WalletWrite,
+    Error: From<<DbT as WalletRead>::Error>,
+{
+    if let Some(tx) = db.read().await.get_transaction(txid)? {
+        return Ok(Some(is_coinbase(&tx)));
+    }
+    let next_height = enhance::next_block_height(&*db.read().await)?;
+    let Some((tx, _)) = enhance::fetch_transaction(client, params, txid, next_height).await? else {
+        return Ok(None);
+    };
+    decrypt_and_store_transaction(params, &mut *db.write().await, &tx, mined_height)?;
+    index.record_transaction(params, &*db.read().await, owners, &tx, mined_height)?;
+    Ok(Some(is_coinbase(&tx)))
+}
+
+fn is_coinbase(tx: &Transaction) -> bool {
+    tx.transparent_bundle()
+        .is_some_and(|bundle| bundle.is_coinbase())
+}
@@ -0,0 +1,197 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Listing the unspent notes that make up an account's balance
+//!
+//! A note is reported as spendable if it is one of the notes [`Wallet::propose_transfer`] would choose from, that is
+//! if the wallet selects it as spendable at the anchor height implied by the wallet's `min_confirmations`. Every other
+//! unspent note is still waiting for confirmations, including the notes of transactions that aren't mined yet. Notes of
+//! unmined transactions that have expired are left out. Transparent outputs can only be spent by shielding them, so they
+//! are spendable once they have `min_confirmations` confirmations, and coinbase outputs once they have matured.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use zcash_client_backend::data_api::{InputSource, NullifierQuery, SpendableNotes, WalletRead};
+use zcash_client_backend::wallet::{Note, NoteId, ReceivedNote};
+use zcash_client_backend::ShieldedProtocol;
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::transaction::components::amount::{NonNegativeAmount, MAX_MONEY};
+use zcash_primitives::transaction::TxId;
+
+use crate::error::Error;
+use crate::history::Pool;
+use crate::memos::{MemoContent, MemoDirection};
+use crate::wallet::{LightwalletdChannel, WalletDb};
+use crate::Wallet;
+
+/// An unspent note received by an account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnspentOutput {
+    /// ID of the transaction that created the note, in the byte order used by block explorers
+    pub txid: String,
+    pub pool: Pool,
+    /// Index of the output within the pool's bundle: the Sapling output or Orchard action index
+    pub output_index: u32,
+    pub value: u64,
+    /// Height of the block the note was mined in, `None` while its transaction is unmined
+    pub mined_height: Option<u32>,
+    /// Number of blocks mined on top of the note's block, counting that block itself. Zero for unmined notes.
+    pub confirmations: u32,
+    /// Whether the note can be spent now under the wallet's `min_confirmations`
+    pub spendable: bool,
+    /// Whether the note was sent to the account's internal address, which is where change goes
+    pub is_change: bool,
+    /// The memo of the note, if the wallet has the full transaction
+    pub memo: Option<MemoContent>,
+}
+
+impl<W, T, AccountId> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
+{
+    /// The unspent transparent outputs and Sapling and Orchard notes of `account_id`, ordered by pool and then by
+    /// mined height
+    pub async fn list_unspent(&self, account_id: AccountId) -> Result<Vec<UnspentOutput>, Error> {
+        let db = self.db.read().await;
+        let Some(chain_tip) = db.chain_height()? else {
+            return Ok(vec![]);
+        };
+        let sources = [ShieldedProtocol::Sapling, ShieldedProtocol::Orchard];
+        let all_value = NonNegativeAmount::const_from_u64(MAX_MONEY);
+
+        // The same selection `propose_transfer` makes from
+        let spendable: HashSet<NoteId> = match db
+            .get_target_and_anchor_heights(self.min_confirmations)?
+        {
+            Some((_, anchor_height)) => {
+                let notes =
+                    db.select_spendable_notes(account_id, all_value, &sources, anchor_height, &[])?;
+                notes_of(&notes).iter().map(note_id).collect()
+            }
+            None => HashSet::new(),
+        };
+
+        // Nullifiers of the received notes no transaction of the wallet spends
+        let mut unspent_nullifiers = HashSet::new();
+        for (id, nf) in db.get_sapling_nullifiers(NullifierQuery::Unspent)? {
+            if id == account_id {
+                unspent_nullifiers.insert(nf.0);
+            }
+        }
+        for (id, nf) in db.get_orchard_nullifiers(NullifierQuery::Unspent)? {
+            if id == account_id {
+                unspent_nullifiers.insert(nf.to_bytes());
+            }
+        }
+
+        // Every note the account received, including ones in transactions that aren't mined yet such as the change
+        // of a transaction it just sent. A note has no nullifier until the block it is mined in has been scanned, and
+        // nothing can have spent it before then.
+        let mut outputs = vec![];
+        for tx in self.tx_index.account_transactions(account_id) {
+            let mined_height = tx.mined_height;
+            let expired = mined_height.is_none()
+                && tx.expiry_height.is_some_and(|expiry| chain_tip >= expiry);
+            if expired {
+                continue;
+            }
+            for output in &tx.effect.outputs {
+                let received = output.direction != MemoDirection::Sent;
+                let unspent = output
+                    .nullifier
+                    .map_or(true, |nf| unspent_nullifiers.contains(&nf));
+                if !received || !unspent {
+                    continue;
+                }
+                let id = output.note_id(tx.txid);
+                outputs.push(UnspentOutput {
+                    txid: tx.txid.to_string(),
+                    pool: output.pool,
+                    output_index: output.output_index,
+                    value: output.value,
+                    mined_height: mined_height.map(u32::from),
+                    confirmations: confirmations(mined_height, chain_tip),
+                    spendable: spendable.contains(&id),
+                    is_change: output.direction == MemoDirection::Internal,
+                    memo: db.get_memo(id)?.map(|memo| (&memo.encode()).into()),
+                });
+            }
+        }
+
+        // Transparent outputs are spendable once confirmed, and coinbase outputs only once they have matured too
+        let target_height = chain_tip + 1;
+        let coinbase_maturity = self.tx_index.coinbase_maturity();
+        for address in db.get_transparent_receivers(account_id)?.into_keys() {
+            let spendable: HashSet<_> = db
+                .get_spendable_transparent_outputs(
+                    &address,
+                    target_height,
+                    self.min_confirmations.into(),
+                )?
+                .iter()
+                .map(|utxo| utxo.outpoint().clone())
+                .filter(|outpoint| {
+                    coinbase_maturity
+                        .get(outpoint)
+                        .map_or(true, |maturity_height| *maturity_height <= target_height)
+                })
+                .collect();
+            for utxo in db.get_spendable_transparent_outputs(&address, target_height, 0)? {
+                let mined_height = utxo.mined_height();
+                outputs.push(UnspentOutput {
+                    txid: TxId::from_bytes(*utxo.outpoint().hash()).to_string(),
+                    pool: Pool::Transparent,
+                    output_index: utxo.outpoint().n(),
+                    value: utxo.txout().value.into(),
+                    mined_height: mined_height.map(u32::from),
+                    confirmations: confirmations(mined_height, chain_tip),
+                    spendable: spendable.contains(utxo.outpoint()),
+                    is_change: false,
+                    memo: None,
+                });
+            }
+        }
+
+        outputs.sort_by_key(|output| {
+            (
+                output.pool as u8,
+                output.mined_height.unwrap_or(u32::MAX),
+                output.output_index,
+            )
+        });
+        Ok(outputs)
+    }
+}
+
+/// The notes of both pools in a selection
+pub(crate) fn notes_of<NoteRef: Clone>(
+    notes: &SpendableNotes<NoteRef>,
+) -> Vec<ReceivedNote<NoteRef, Note>> {
+    let sapling = notes
+        .sapling()
+        .iter()
+        .map(|note| note.clone().map_note(Note::Sapling));
+    let orchard = notes
+        .orchard()
+        .iter()
+        .map(|note| note.clone().map_note(Note::Orchard));
+    sapling.chain(orchard).collect()
+}
+
+pub(crate) fn note_id<NoteRef>(note: &ReceivedNote<NoteRef, Note>) -> NoteId {
+    let protocol = match note.note() {
+        Note::Sapling(_) => ShieldedProtocol::Sapling,
+        Note::Orchard(_) => ShieldedProtocol::Orchard,
+    };
+    NoteId::new(*note.txid(), protocol, note.output_index())
+}
+
+fn confirmations(mined_height: Option<BlockHeight>, chain_tip: BlockHeight) -> u32 {
+    match mined_height {
+        Some(height) if height <= chain_tip => u32::from(chain_tip) - u32::from(height) + 1,
+        _ => 0,
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroU32;
 
 use bip0039::{English, Mnemonic};
@@ -8,42 +9,50 @@ use tonic::{
     codegen::{Body, Bytes, StdError},
 };
 
+use crate::birthday::{self, Birthday};
+use crate::block_cache::CompactBlockCache;
+use crate::change::{ChangeOptions, SplitChangeStrategy};
+use crate::checkpoints::Checkpoints;
+use crate::diagnostics::ProposalFailure;
 use crate::error::Error;
+use crate::history::{Owners, TransactionIndex};
+use crate::input_selection::{SelectionStrategy, StrategyInputSelector};
+use crate::sync::{self, ActiveSync, SyncControl, SyncEvent, BATCH_SIZE, DEFAULT_SCAN_CHUNK_SIZE};
+use crate::unspent::{note_id, notes_of};
 use crate::BlockRange;
 use webz_common::Network;
 
+use serde::de::DeserializeOwned;
 use serde::{Serialize, Serializer};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use subtle::ConditionallySelectable;
 use tokio::sync::RwLock;
 use zcash_address::ZcashAddress;
-use zcash_client_backend::data_api::wallet::{
-    create_proposed_transactions, input_selection::GreedyInputSelector, propose_transfer,
-};
-use zcash_client_backend::data_api::WalletCommitmentTrees;
+use zcash_client_backend::data_api::wallet::{create_proposed_transactions, propose_transfer};
+use zcash_client_backend::data_api::{self, WalletCommitmentTrees};
 use zcash_client_backend::data_api::{
     Account, AccountBirthday, AccountPurpose, InputSource, WalletRead, WalletSummary, WalletWrite,
 };
-use zcash_client_backend::fees::zip317::SingleOutputChangeStrategy;
 use zcash_client_backend::proposal::Proposal;
 use zcash_client_backend::proto::service::{
     self, compact_tx_streamer_client::CompactTxStreamerClient,
 };
-use zcash_client_backend::wallet::OvkPolicy;
+use zcash_client_backend::wallet::{NoteId, OvkPolicy};
 use zcash_client_backend::zip321::{Payment, TransactionRequest};
 use zcash_client_backend::ShieldedProtocol;
-use zcash_client_memory::{MemBlockCache, MemoryWalletDb};
+use zcash_client_memory::MemoryWalletDb;
 use zcash_keys::keys::{UnifiedFullViewingKey, UnifiedSpendingKey};
-use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::transaction::components::amount::{NonNegativeAmount, MAX_MONEY};
+use zcash_primitives::transaction::components::OutPoint;
 use zcash_primitives::transaction::fees::zip317::FeeRule;
 use zcash_primitives::transaction::TxId;
 use zcash_proofs::prover::LocalTxProver;
 
-use zcash_client_backend::sync::run;
-const BATCH_SIZE: u32 = 10000;
-
 /// # A Zcash wallet
 ///
 /// A wallet is a set of accounts that can be synchronized together with the blockchain.
@@ -64,48 +73,117 @@ const BATCH_SIZE: u32 = 10000;
 /// TODO
 ///
 
-pub struct Wallet<W, T> {
+pub struct Wallet<W: WalletRead, T> {
     /// Internal database used to maintain wallet data (e.g. accounts, transactions, cached blocks)
     pub(crate) db: Arc<RwLock<W>>,
     // gRPC client used to connect to a lightwalletd instance for network data
     pub(crate) client: CompactTxStreamerClient<T>,
     pub(crate) network: Network,
     pub(crate) min_confirmations: NonZeroU32,
+    /// Compact blocks which have been downloaded but not yet scanned
+    pub(crate) block_cache: Arc<CompactBlockCache>,
+    /// Known tree states used to set account birthdays without asking lightwalletd
+    pub(crate) checkpoints: Arc<RwLock<Checkpoints>>,
+    /// Whether lightwalletd may be asked for the tree state at a birthday no checkpoint covers
+    pub(crate) birthday_requests: Arc<AtomicBool>,
+    /// Transactions the wallet has seen, from which the transaction history is built
+    pub(crate) tx_index: Arc<TransactionIndex<W::AccountId>>,
+    /// Shared handle used to pause, resume or cancel the sync of this wallet
+    pub(crate) sync_control: SyncControl,
+    /// How change is paid back when a proposal doesn't say otherwise
+    pub(crate) change_options: Arc<wasm_sync::RwLock<ChangeOptions>>,
+    /// Number of blocks of a batch scanned together by one thread
+    pub(crate) scan_chunk_size: usize,
 }
 
-impl<W, T: Clone> Clone for Wallet<W, T> {
+impl<W: WalletRead, T: Clone> Clone for Wallet<W, T> {
     fn clone(&self) -> Self {
         Self {
             db: self.db.clone(),
             client: self.client.clone(),
             network: self.network,
             min_confirmations: self.min_confirmations,
+            block_cache: self.block_cache.clone(),
+            checkpoints: self.checkpoints.clone(),
+            birthday_requests: self.birthday_requests.clone(),
+            tx_index: self.tx_index.clone(),
+            sync_control: self.sync_control.clone(),
+            change_options: self.change_options.clone(),
+            scan_chunk_size: self.scan_chunk_size,
         }
     }
 }
 
-impl<W, T, AccountId, NoteRef> Wallet<W, T>
+/// The wallet database a [`Wallet`] can work with
+///
+/// Implemented for every database that reads, writes and selects inputs with the same account and error types, so
+/// the `Wallet` impls can name this one bound instead of repeating all of them.
+pub trait WalletDb:
+    WalletRead<
+        AccountId: Copy + Debug + Eq + Hash + Default + Send + ConditionallySelectable + 'static,
+        Error: std::error::Error + Send + Sync + 'static,
+    > + WalletWrite
+    + InputSource<
+        AccountId = <Self as WalletRead>::AccountId,
+        Error = <Self as WalletRead>::Error,
+        NoteRef: Copy + Eq + Ord + Debug,
+    > + WalletCommitmentTrees<Error: std::error::Error + Send + Sync + 'static>
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<W> WalletDb for W
 where
-    W: WalletRead<AccountId = AccountId>
+    W: WalletRead
         + WalletWrite
-        + InputSource<
-            AccountId = <W as WalletRead>::AccountId,
-            Error = <W as WalletRead>::Error,
-            NoteRef = NoteRef,
-        > + WalletCommitmentTrees,
-
-    AccountId: Copy + Debug + Eq + Hash + Default + Send + ConditionallySelectable + 'static,
-    NoteRef: Copy + Eq + Ord + Debug,
-    Error: From<<W as WalletRead>::Error>,
-
+        + InputSource<AccountId = <W as WalletRead>::AccountId, Error = <W as WalletRead>::Error>
+        + WalletCommitmentTrees
+        + Send
+        + Sync
+        + 'static,
+    <W as WalletRead>::AccountId:
+        Copy + Debug + Eq + Hash + Default + Send + ConditionallySelectable + 'static,
     <W as WalletRead>::Error: std::error::Error + Send + Sync + 'static,
+    <W as InputSource>::NoteRef: Copy + Eq + Ord + Debug,
     <W as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+}
+
+/// The gRPC connection a [`Wallet`] uses to reach lightwalletd
+pub trait LightwalletdChannel:
+    GrpcService<
+        tonic::body::BoxBody,
+        Error: Into<StdError>,
+        ResponseBody: Body<Data = Bytes, Error: Into<StdError> + Send> + Send + 'static,
+    > + Clone
+{
+}
 
-    // GRPC connection Trait Bounds
+impl<T> LightwalletdChannel for T
+where
     T: GrpcService<tonic::body::BoxBody> + Clone,
     T::Error: Into<StdError>,
-    T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
-    <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+}
+
+/// An account's transparent value split by whether it can be spent now. See [`Wallet::transparent_maturity`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransparentMaturity {
+    /// Value with the wallet's `min_confirmations` that isn't an immature coinbase output, which can be shielded now
+    pub spendable: u64,
+    /// Value of coinbase outputs that can't be spent until they have matured
+    pub immature: u64,
+}
+
+impl<W, T, AccountId, NoteRef> Wallet<W, T>
+where
+    W: WalletDb + WalletRead<AccountId = AccountId> + InputSource<NoteRef = NoteRef>,
+    T: LightwalletdChannel,
+    Error: From<<W as WalletRead>::Error>,
 {
     /// Create a new instance of a Zcash wallet for a given network
     pub fn new(
@@ -119,6 +197,13 @@ where
             client: CompactTxStreamerClient::new(client),
             network,
             min_confirmations,
+            block_cache: Arc::new(CompactBlockCache::new()),
+            checkpoints: Arc::new(RwLock::new(Checkpoints::bundled(network))),
+            birthday_requests: Arc::new(AtomicBool::new(true)),
+            tx_index: Arc::new(TransactionIndex::default()),
+            sync_control: SyncControl::new(),
+            change_options: Default::default(),
+            scan_chunk_size: DEFAULT_SCAN_CHUNK_SIZE,
         })
     }
 
@@ -130,11 +215,36 @@ where
         self.db.read().await.serialize(serializer)
     }
 
+    /// The wallet database followed by the wallet's [`TransactionIndex`], postcard encoded. The result can be
+    /// restored with [`Wallet::from_bytes`].
     pub async fn db_to_bytes(&self) -> Result<Vec<u8>, Error>
     where
         W: Serialize,
+        AccountId: Serialize,
     {
-        Ok(postcard::to_allocvec(&*self.db.read().await)?)
+        let mut bytes = postcard::to_allocvec(&*self.db.read().await)?;
+        bytes.extend(self.tx_index.to_bytes()?);
+        Ok(bytes)
+    }
+
+    /// Restore a wallet from the output of [`Wallet::db_to_bytes`]. Bytes saved before the wallet kept a
+    /// [`TransactionIndex`] only hold the database, in which case the index starts out empty.
+    pub fn from_bytes(
+        bytes: &[u8],
+        client: T,
+        network: Network,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Self, Error>
+    where
+        W: DeserializeOwned,
+        AccountId: DeserializeOwned,
+    {
+        let (db, rest) = postcard::take_from_bytes(bytes)?;
+        let mut wallet = Self::new(db, client, network, min_confirmations)?;
+        if !rest.is_empty() {
+            wallet.tx_index = Arc::new(TransactionIndex::from_bytes(rest)?);
+        }
+        Ok(wallet)
     }
 
     /// Add a new account to the wallet
@@ -142,13 +252,13 @@ where
     /// # Arguments
     /// seed_phrase - mnemonic phrase to initialise the wallet
     /// account_id - The HD derivation index to use. Can be any integer
-    /// birthday_height - The block height at which the account was created, optionally None and the current height is used
+    /// birthday - The block height or date at which the account was created, or [`Birthday::ChainTip`] for a new account
     ///
     pub async fn create_account(
         &self,
         seed_phrase: &str,
         account_hd_index: u32,
-        birthday_height: Option<u32>,
+        birthday: Birthday,
     ) -> Result<AccountId, Error> {
         // decode the mnemonic and derive the first account
         let usk = usk_from_seed_str(seed_phrase, account_hd_index, &self.network)?;
@@ -156,16 +266,16 @@ where
 
         tracing::info!("Key successfully decoded. Importing into wallet");
 
-        self.import_account_ufvk(&ufvk, birthday_height, AccountPurpose::Spending)
+        self.import_account_ufvk(&ufvk, birthday, AccountPurpose::Spending)
             .await
     }
 
     pub async fn import_ufvk(
         &self,
         ufvk: &UnifiedFullViewingKey,
-        birthday_height: Option<u32>,
+        birthday: Birthday,
     ) -> Result<AccountId, Error> {
-        self.import_account_ufvk(ufvk, birthday_height, AccountPurpose::ViewOnly)
+        self.import_account_ufvk(ufvk, birthday, AccountPurpose::ViewOnly)
             .await
     }
 
@@ -173,35 +283,41 @@ where
     async fn import_account_ufvk(
         &self,
         ufvk: &UnifiedFullViewingKey,
-        birthday_height: Option<u32>,
+        birthday: Birthday,
         purpose: AccountPurpose,
     ) -> Result<AccountId, Error> {
         tracing::info!("Importing account with Ufvk: {:?}", ufvk);
-        let mut client = self.client.clone();
-        let birthday = match birthday_height {
-            Some(height) => height,
-            None => {
-                let chain_tip: u32 = client
-                    .get_latest_block(service::ChainSpec::default())
+        let tree_state = match birthday {
+            Birthday::Height(height) => self.birthday_tree_state(height).await?,
+            Birthday::Date(unix_time) => {
+                let height = self.estimate_birthday_height(unix_time).await?;
+                tracing::info!("Estimated birthday height {} for {}", height, unix_time);
+                self.birthday_tree_state(height).await?
+            }
+            Birthday::ChainTip => {
+                let chain_tip = self.fetch_chain_tip().await?;
+                // A birthday derived from the current tip reveals nothing about the account,
+                // so this can come straight from the server instead of an older checkpoint
+                self.fetch_tree_state(chain_tip.saturating_sub(100 + 1))
                     .await?
-                    .into_inner()
-                    .height
-                    .try_into()
-                    .expect("block heights must fit into u32");
-                chain_tip - 100
             }
         };
-        // Construct an `AccountBirthday` for the account's birthday.
-        let birthday = {
-            // Fetch the tree state corresponding to the last block prior to the wallet's
-            // birthday height. NOTE: THIS APPROACH LEAKS THE BIRTHDAY TO THE SERVER!
-            let request = service::BlockId {
-                height: (birthday - 1).into(),
-                ..Default::default()
-            };
-            let treestate = client.get_tree_state(request).await?.into_inner();
-            AccountBirthday::from_treestate(treestate, None).map_err(|_| Error::Birthday)?
-        };
+        self.import_account_from_tree_state(ufvk, tree_state, purpose)
+            .await
+    }
+
+    /// Import an account whose birthday is given by the tree state as of the block before it.
+    ///
+    /// This doesn't contact lightwalletd at all so can be used to import accounts offline, for example with a tree
+    /// state exported from another wallet or a full node.
+    pub async fn import_account_from_tree_state(
+        &self,
+        ufvk: &UnifiedFullViewingKey,
+        tree_state: service::TreeState,
+        purpose: AccountPurpose,
+    ) -> Result<AccountId, Error> {
+        let birthday =
+            AccountBirthday::from_treestate(tree_state, None).map_err(|_| Error::Birthday)?;
 
         Ok(self
             .db
@@ -211,6 +327,173 @@ where
             .id())
     }
 
+    /// Add a seed derived account whose birthday is given by the tree state as of the block before it.
+    /// See [`Wallet::import_account_from_tree_state`].
+    pub async fn create_account_from_tree_state(
+        &self,
+        seed_phrase: &str,
+        account_hd_index: u32,
+        tree_state: service::TreeState,
+    ) -> Result<AccountId, Error> {
+        let usk = usk_from_seed_str(seed_phrase, account_hd_index, &self.network)?;
+        let ufvk = usk.to_unified_full_viewing_key();
+
+        self.import_account_from_tree_state(&ufvk, tree_state, AccountPurpose::Spending)
+            .await
+    }
+
+    /// Estimate a conservative birthday height for an account created at `unix_time` (in seconds).
+    ///
+    /// The estimate is based on the nearest checkpoint before the date. If there is none within
+    /// [`birthday::REFERENCE_WINDOW_SECS`] of it, the times of a few blocks around the estimate are fetched from
+    /// lightwalletd instead. If that fails too the estimate falls back to the target block spacing with a wide margin.
+    pub async fn estimate_birthday_height(&self, unix_time: u64) -> Result<u32, Error> {
+        let reference = self
+            .checkpoints
+            .read()
+            .await
+            .at_or_before_time(unix_time)
+            .map(|checkpoint| (checkpoint.height, u64::from(checkpoint.time)))
+            .unwrap_or_else(|| birthday::genesis_reference(&self.network));
+
+        let (reference, margin, chain_tip) =
+            if unix_time.saturating_sub(reference.1) <= birthday::REFERENCE_WINDOW_SECS {
+                (reference, birthday::REFINED_MARGIN_SECS, None)
+            } else {
+                match self.refine_birthday_reference(reference, unix_time).await {
+                    Ok((reference, chain_tip)) => {
+                        (reference, birthday::REFINED_MARGIN_SECS, Some(chain_tip))
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to refine birthday estimate: {}", e);
+                        (reference, birthday::UNREFINED_MARGIN_SECS, None)
+                    }
+                }
+            };
+
+        let height = birthday::extrapolate_height(
+            &self.network,
+            reference,
+            unix_time.saturating_sub(margin),
+        )
+        .max(birthday::earliest_birthday(&self.network));
+
+        Ok(match chain_tip {
+            Some(chain_tip) => height.min(chain_tip),
+            None => height,
+        })
+    }
+
+    /// Move the reference block used to estimate a birthday closer to `unix_time` by fetching block times from
+    /// lightwalletd. Returns the new reference along with the chain tip.
+    async fn refine_birthday_reference(
+        &self,
+        mut reference: (u32, u64),
+        unix_time: u64,
+    ) -> Result<((u32, u64), u32), Error> {
+        let chain_tip = self.fetch_chain_tip().await?;
+        for _ in 0..birthday::MAX_REFINEMENTS {
+            let height =
+                birthday::extrapolate_height(&self.network, reference, unix_time).min(chain_tip);
+            if height == reference.0 {
+                break;
+            }
+            reference = (height, self.fetch_block_time(height).await?);
+            if reference.1.abs_diff(unix_time) <= birthday::REFERENCE_WINDOW_SECS {
+                break;
+            }
+        }
+        Ok((reference, chain_tip))
+    }
+
+    async fn fetch_chain_tip(&self) -> Result<u32, Error> {
+        self.client
+            .clone()
+            .get_latest_block(service::ChainSpec::default())
+            .await?
+            .into_inner()
+            .height
+            .try_into()
+            .map_err(|_| {
+                Error::Sync("lightwalletd returned an invalid chain tip height".to_string())
+            })
+    }
+
+    pub(crate) async fn fetch_block_time(&self, height: u32) -> Result<u64, Error> {
+        let request = service::BlockId {
+            height: height.into(),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .clone()
+            .get_block(request)
+            .await?
+            .into_inner()
+            .time
+            .into())
+    }
+
+    /// Add to the tree states used to set account birthdays. Checkpoints at existing heights are replaced.
+    pub async fn add_checkpoints(&self, checkpoints: Checkpoints) {
+        self.checkpoints.write().await.extend(checkpoints);
+    }
+
+    /// Whether lightwalletd may be asked for the tree state at a birthday when no checkpoint is at or below it.
+    ///
+    /// This is allowed by default since the bundled checkpoints don't cover every birthday yet. The request tells the
+    /// server when the account was created, so wallets that have the checkpoints they need can turn it off. Adding an
+    /// account with a birthday no checkpoint covers then fails with [`Error::NoBirthdayCheckpoint`].
+    pub fn allow_birthday_requests(&self, allow: bool) {
+        self.birthday_requests.store(allow, Ordering::Relaxed);
+    }
+
+    /// The tree state to start an account with a birthday of `birthday` from.
+    ///
+    /// This is the nearest checkpoint at or below `birthday - 1`. The account then effectively has a birthday just
+    /// after that checkpoint. If there is no such checkpoint, lightwalletd is asked for the exact tree state unless
+    /// that has been turned off with [`Wallet::allow_birthday_requests`].
+    async fn birthday_tree_state(&self, birthday: u32) -> Result<service::TreeState, Error> {
+        if let Some(checkpoint) = self
+            .checkpoints
+            .read()
+            .await
+            .at_or_below(birthday.saturating_sub(1))
+        {
+            tracing::info!(
+                "Using checkpoint at {} for birthday {}",
+                checkpoint.height,
+                birthday
+            );
+            return Ok(checkpoint.to_tree_state(&self.network));
+        }
+
+        if !self.birthday_requests.load(Ordering::Relaxed) {
+            return Err(Error::NoBirthdayCheckpoint(birthday));
+        }
+
+        // Fetch the tree state corresponding to the last block prior to the wallet's
+        // birthday height. NOTE: THIS APPROACH LEAKS THE BIRTHDAY TO THE SERVER!
+        tracing::warn!(
+            "No checkpoint at or below {}, requesting the tree state from lightwalletd",
+            birthday
+        );
+        self.fetch_tree_state(birthday.saturating_sub(1)).await
+    }
+
+    async fn fetch_tree_state(&self, height: u32) -> Result<service::TreeState, Error> {
+        let request = service::BlockId {
+            height: height.into(),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .clone()
+            .get_tree_state(request)
+            .await?
+            .into_inner())
+    }
+
     pub async fn suggest_scan_ranges(&self) -> Result<Vec<BlockRange>, Error> {
         Ok(self.db.read().await.suggest_scan_ranges().map(|ranges| {
             ranges
@@ -226,20 +509,113 @@ where
     }
 
     pub async fn sync(&self) -> Result<(), Error> {
+        let active = self.sync_control.start()?;
+        self.sync_control.reset();
+        self.run_sync(active, |_| {}).await
+    }
+
+    /// Synchronize the wallet with the chain tip, calling `on_event` each time a range of blocks
+    /// has been downloaded or scanned, or a reorg or relevant notes are found.
+    ///
+    /// The sync can be paused, resumed or cancelled through [`Wallet::sync_control`]. A cancelled sync returns
+    /// [`Error::SyncCancelled`]. Requests only apply to the sync running when they are made: the control is cleared
+    /// when a sync starts and again when it ends.
+    ///
+    /// Returns [`Error::SyncAlreadyRunning`] if the wallet is already being synced or watched.
+    pub async fn sync_with_events<F>(&self, on_event: F) -> Result<(), Error>
+    where
+        F: FnMut(SyncEvent),
+    {
+        let active = self.sync_control.start()?;
+        self.sync_control.reset();
+        self.run_sync(active, on_event).await
+    }
+
+    /// [`Wallet::sync_with_events`] for a caller that has already claimed the sync control
+    pub(crate) async fn run_sync<F>(
+        &self,
+        _active: ActiveSync,
+        mut on_event: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(SyncEvent),
+    {
         let mut client = self.client.clone();
-        // TODO: This should be held in the Wallet struct so we can download in parallel
-        let db_cache = MemBlockCache::new();
 
-        let mut db = self.db.write().await;
-        run(
+        sync::run(
             &mut client,
             &self.network.clone(),
-            &db_cache,
-            &mut *db,
+            &self.block_cache,
+            &self.db,
+            BATCH_SIZE,
+            self.scan_chunk_size,
+            self.min_confirmations.into(),
+            &self.tx_index,
+            &self.sync_control,
+            &mut on_event,
+        )
+        .await
+    }
+
+    /// Sync to the chain tip and then keep scanning new blocks as they are mined, polling lightwalletd
+    /// every `poll_interval`.
+    ///
+    /// This runs until it is cancelled through [`Wallet::sync_control`], at which point it returns `Ok(())`.
+    /// New chain tips, reorgs and detected notes are reported to `on_event` along with the usual progress.
+    /// Returns [`Error::SyncAlreadyRunning`] if the wallet is already being synced or watched.
+    pub async fn watch<F>(&self, poll_interval: Duration, on_event: F) -> Result<(), Error>
+    where
+        F: FnMut(SyncEvent),
+    {
+        let active = self.sync_control.start()?;
+        self.sync_control.reset();
+        self.run_watch(active, poll_interval, on_event).await
+    }
+
+    /// [`Wallet::watch`] for a caller that has already claimed the sync control
+    pub(crate) async fn run_watch<F>(
+        &self,
+        _active: ActiveSync,
+        poll_interval: Duration,
+        mut on_event: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(SyncEvent),
+    {
+        let mut client = self.client.clone();
+
+        sync::watch(
+            &mut client,
+            &self.network.clone(),
+            &self.block_cache,
+            &self.db,
             BATCH_SIZE,
+            self.scan_chunk_size,
+            self.min_confirmations.into(),
+            &self.tx_index,
+            poll_interval,
+            &self.sync_control,
+            &mut on_event,
         )
         .await
-        .map_err(Into::into)
+    }
+
+    /// Cache of downloaded compact blocks used by [`Wallet::sync`]. In the browser this can be made to persist across
+    /// page loads with [`CompactBlockCache::persist_to_indexed_db`].
+    pub fn block_cache(&self) -> &CompactBlockCache {
+        &self.block_cache
+    }
+
+    /// Set how many blocks of a batch one thread scans during a sync. Chunks of a batch are scanned in parallel on the
+    /// rayon thread pool, so smaller chunks spread a batch over more threads. `usize::MAX` scans each batch on a single
+    /// thread. Defaults to [`DEFAULT_SCAN_CHUNK_SIZE`].
+    pub fn set_scan_chunk_size(&mut self, blocks: usize) {
+        self.scan_chunk_size = blocks.max(1);
+    }
+
+    /// Handle which can be used to pause, resume or cancel a sync of this wallet from another task
+    pub fn sync_control(&self) -> &SyncControl {
+        &self.sync_control
     }
 
     pub async fn get_wallet_summary(&self) -> Result<Option<WalletSummary<AccountId>>, Error> {
@@ -250,6 +626,47 @@ where
             .get_wallet_summary(self.min_confirmations.into())?)
     }
 
+    /// How much of each account's transparent balance can be shielded now and how much is in coinbase outputs that
+    /// haven't matured. The balances of [`Self::get_wallet_summary`] can't tell these apart as the maturity of coinbase
+    /// outputs is only known to the wallet's [`TransactionIndex`].
+    pub async fn transparent_maturity(
+        &self,
+    ) -> Result<HashMap<AccountId, TransparentMaturity>, Error> {
+        let db = self.db.read().await;
+        let mut balances = HashMap::new();
+        let coinbase_maturity = self.tx_index.coinbase_maturity();
+        let Some(chain_tip) = db.chain_height()? else {
+            return Ok(balances);
+        };
+        let target_height = chain_tip + 1;
+        let is_immature = |outpoint: &OutPoint| {
+            coinbase_maturity
+                .get(outpoint)
+                .is_some_and(|maturity_height| *maturity_height > target_height)
+        };
+        for account_id in db.get_account_ids()? {
+            let mut maturity = TransparentMaturity::default();
+            for address in db.get_transparent_receivers(account_id)?.into_keys() {
+                for utxo in db.get_spendable_transparent_outputs(&address, target_height, 0)? {
+                    if is_immature(utxo.outpoint()) {
+                        maturity.immature += u64::from(utxo.txout().value);
+                    }
+                }
+                for utxo in db.get_spendable_transparent_outputs(
+                    &address,
+                    target_height,
+                    self.min_confirmations.into(),
+                )? {
+                    if !is_immature(utxo.outpoint()) {
+                        maturity.spendable += u64::from(utxo.txout().value);
+                    }
+                }
+            }
+            balances.insert(account_id, maturity);
+        }
+        Ok(balances)
+    }
+
     ///
     /// Create a transaction proposal to send funds from the wallet to a given address
     ///
@@ -259,49 +676,127 @@ where
         to_address: ZcashAddress,
         value: u64,
     ) -> Result<Proposal<FeeRule, NoteRef>, Error> {
-        let input_selector = GreedyInputSelector::new(
-            SingleOutputChangeStrategy::new(FeeRule::standard(), None, ShieldedProtocol::Orchard),
-            Default::default(),
-        );
-
         let request = TransactionRequest::new(vec![Payment::without_memo(
             to_address,
             NonNegativeAmount::from_u64(value)?,
         )])?;
+        self.propose_request(account_id, request).await
+    }
+
+    ///
+    /// Create a transaction proposal that makes every payment in a [ZIP-321](https://zips.z.cash/zip-0321) request,
+    /// each with its memo
+    ///
+    /// If the proposal fails, returns an [`Error::Proposal`] saying why, e.g. how much each pool holds if the account
+    /// can't cover the payments or when enough funds become spendable.
+    ///
+    pub async fn propose_request(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+    ) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+        self.propose_request_with(
+            account_id,
+            request,
+            &self.change_options(),
+            SelectionStrategy::default(),
+        )
+        .await
+    }
+
+    /// Like [`Wallet::propose_request`] but with `change` instead of the wallet's default change options, and spending
+    /// the notes `selection` picks
+    pub async fn propose_request_with(
+        &self,
+        account_id: AccountId,
+        request: TransactionRequest,
+        change: &ChangeOptions,
+        selection: SelectionStrategy,
+    ) -> Result<Proposal<FeeRule, NoteRef>, Error> {
+        // Requests parsed from a URI are already checked but ones built in code may not be
+        self.check_recipients(&request)?;
 
-        tracing::info!("Chain height: {:?}", self.db.read().await.chain_height()?);
-        tracing::info!(
+        tracing::debug!("Chain height: {:?}", self.db.read().await.chain_height()?);
+        tracing::debug!(
             "target and anchor heights: {:?}",
             self.db
                 .read()
                 .await
                 .get_target_and_anchor_heights(self.min_confirmations)?
         );
-        let mut db = self.db.write().await;
-        let proposal = propose_transfer::<_, _, _, <W as WalletCommitmentTrees>::Error>(
-            &mut *db,
+        let input_selector = self.input_selector(change, selection)?;
+        let result = propose_transfer::<_, _, _, <W as WalletCommitmentTrees>::Error>(
+            &mut *self.db.write().await,
             &self.network,
             account_id,
             &input_selector,
             request,
             self.min_confirmations,
+        );
+        match result {
+            Ok(proposal) => {
+                tracing::debug!("Proposal: {:#?}", proposal);
+                Ok(proposal)
+            }
+            Err(e) => Err(self.diagnose(account_id, proposal_error(e)).await),
+        }
+    }
+
+    /// The input selector proposals are made with: ZIP-317 fees, notes chosen by `selection` and change paid as `change`
+    /// says
+    pub(crate) fn input_selector(
+        &self,
+        change: &ChangeOptions,
+        selection: SelectionStrategy,
+    ) -> Result<StrategyInputSelector<W, SplitChangeStrategy>, Error> {
+        Ok(StrategyInputSelector::new(
+            change.change_strategy(),
+            change.dust_output_policy()?,
+            selection,
         )
-        .map_err(|_e| Error::Generic("something bad happened when calling propose transfer. Possibly insufficient balance..".to_string()))?;
-        tracing::info!("Proposal: {:#?}", proposal);
-        Ok(proposal)
+        .with_coinbase_maturity(self.tx_index.coinbase_maturity()))
+    }
+
+    /// The input selector shielding proposals are made with. Shielding spends every mature transparent output it is
+    /// given so there are no notes to choose between.
+    pub(crate) fn shielding_selector(
+        &self,
+        change: &ChangeOptions,
+    ) -> Result<StrategyInputSelector<W, SplitChangeStrategy>, Error> {
+        self.input_selector(change, SelectionStrategy::Greedy)
+    }
+
+    /// The change options used by proposals that don't give their own
+    pub fn change_options(&self) -> ChangeOptions {
+        *self
+            .change_options
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set the change options used by proposals that don't give their own
+    pub fn set_change_options(&self, options: ChangeOptions) {
+        *self
+            .change_options
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = options;
     }
 
     ///
     /// Do the proving and signing required to create one or more transaction from the proposal. Created transactions are stored in the wallet database.
     ///
-    /// Note: At the moment this requires a USK but ideally we want to be able to hand the signing off to a separate service
-    ///     e.g. browser plugin, hardware wallet, etc. Will need to look into refactoring librustzcash create_proposed_transactions to allow for this
+    /// Returns [`Error::SpendingKeyMismatch`] without proving anything if `usk` is not the spending key of the account
+    /// whose funds the proposal spends, and [`Error::FailedToCreateTransaction`] with the backend's reason if building
+    /// the transactions fails.
+    ///
+    /// Proving and signing both happen here, so the spending key has to be present wherever the proofs are made.
     ///
     pub async fn create_proposed_transactions(
         &self,
         proposal: Proposal<FeeRule, NoteRef>,
         usk: &UnifiedSpendingKey,
     ) -> Result<NonEmpty<TxId>, Error> {
+        self.check_spending_key(&proposal, usk).await?;
         let prover = LocalTxProver::bundled();
         let mut db = self.db.write().await;
         let transactions = create_proposed_transactions::<
@@ -319,11 +814,106 @@ where
             OvkPolicy::Sender,
             &proposal,
         )
-        .map_err(|_| Error::FailedToCreateTransaction)?;
+        .map_err(|e| Error::FailedToCreateTransaction(e.to_string()))?;
+
+        let owners = Owners::read(&*db)?;
+        for txid in transactions.iter() {
+            if let Some(tx) = db.get_transaction(*txid)? {
+                self.tx_index
+                    .record_transaction(&self.network, &*db, &owners, &tx, None)?;
+            }
+        }
 
         Ok(transactions)
     }
 
+    /// Check that `usk` belongs to the account that owns the inputs of `proposal`. Proving takes long enough that a
+    /// wrong key should be caught before it starts.
+    ///
+    /// The owner of a note is taken from the transaction index, or from the notes each account can spend if the index
+    /// doesn't have it, and the owner of a transparent output from its address. A proposal with an input the wallet
+    /// can't find the owner of is rejected.
+    async fn check_spending_key(
+        &self,
+        proposal: &Proposal<FeeRule, NoteRef>,
+        usk: &UnifiedSpendingKey,
+    ) -> Result<(), Error> {
+        let utxo_addresses: HashSet<TransparentAddress> = proposal
+            .steps()
+            .iter()
+            .flat_map(|step| step.transparent_inputs())
+            .map(|utxo| *utxo.recipient_address())
+            .collect();
+        let mut owners = HashSet::new();
+        let mut unindexed = HashSet::new();
+        for note in proposal
+            .steps()
+            .iter()
+            .filter_map(|step| step.shielded_inputs())
+            .flat_map(|inputs| inputs.notes().iter())
+        {
+            let id = note_id(note);
+            match self.tx_index.note_owner(&id) {
+                Some(account_id) => {
+                    owners.insert(account_id);
+                }
+                None => {
+                    unindexed.insert(id);
+                }
+            }
+        }
+
+        let db = self.db.read().await;
+        if !utxo_addresses.is_empty() {
+            for account_id in db.get_account_ids()? {
+                let owns_utxos = db
+                    .get_transparent_receivers(account_id)?
+                    .keys()
+                    .any(|address| utxo_addresses.contains(address));
+                if owns_utxos {
+                    owners.insert(account_id);
+                }
+            }
+        }
+        // Notes received before the index was kept, or by a wallet restored without it, are looked for among each
+        // account's unspent notes
+        if !unindexed.is_empty() {
+            let chain_tip = db.chain_height()?.ok_or(Error::UnknownProposalAccount)?;
+            let sources = [ShieldedProtocol::Sapling, ShieldedProtocol::Orchard];
+            let all_value = NonNegativeAmount::const_from_u64(MAX_MONEY);
+            for account_id in db.get_account_ids()? {
+                let spendable: HashSet<NoteId> = notes_of(&db.select_spendable_notes(
+                    account_id,
+                    all_value,
+                    &sources,
+                    chain_tip,
+                    &[],
+                )?)
+                .iter()
+                .map(note_id)
+                .collect();
+                if unindexed.iter().any(|id| spendable.contains(id)) {
+                    owners.insert(account_id);
+                    unindexed.retain(|id| !spendable.contains(id));
+                }
+            }
+        }
+        if owners.is_empty() || !unindexed.is_empty() {
+            return Err(Error::UnknownProposalAccount);
+        }
+
+        for account_id in owners {
+            let ufvk = db
+                .get_account(account_id)?
+                .and_then(|account| account.ufvk().cloned())
+                .ok_or(Error::UnknownProposalAccount)?;
+            if !spending_key_matches(&ufvk, usk) {
+                return Err(Error::SpendingKeyMismatch);
+            }
+        }
+        Ok(())
+    }
+
     pub async fn send_authorized_transactions(&self, txids: &NonEmpty<TxId>) -> Result<(), Error> {
         let mut client = self.client.clone();
         for txid in txids.iter() {
@@ -376,6 +966,56 @@ where
     }
 }
 
+/// Convert an error from proposing a transaction. Failures the caller can act on become an [`Error::Proposal`], with
+/// the details [`Wallet::diagnose`] fills in left empty.
+pub(crate) fn proposal_error<DE, CE, SE, FE>(e: data_api::error::Error<DE, CE, SE, FE>) -> Error
+where
+    data_api::error::Error<DE, CE, SE, FE>: Debug,
+{
+    match e {
+        data_api::error::Error::InsufficientFunds {
+            available,
+            required,
+        } => ProposalFailure::InsufficientFunds {
+            available: available.into(),
+            required: required.into(),
+            pools: vec![],
+        }
+        .into(),
+        data_api::error::Error::ScanRequired => ProposalFailure::NotSynced {
+            fully_scanned: None,
+            chain_tip: None,
+        }
+        .into(),
+        // The backend doesn't say which address, and requests are checked for this before proposing
+        data_api::error::Error::MemoForbidden => ProposalFailure::MemoToTransparent {
+            address: String::new(),
+        }
+        .into(),
+        e => Error::Generic(format!("Failed to propose transaction: {:?}", e)),
+    }
+}
+
+/// Whether `usk` is the spending key of an account with the viewing key `ufvk`. Only the components `ufvk` has are
+/// compared, since an account imported from a viewing key may leave some out.
+pub fn spending_key_matches(ufvk: &UnifiedFullViewingKey, usk: &UnifiedSpendingKey) -> bool {
+    let usk = usk.to_unified_full_viewing_key();
+    let sapling = ufvk
+        .sapling()
+        .map(|key| Some(key.to_bytes()) == usk.sapling().map(|key| key.to_bytes()));
+    let orchard = ufvk
+        .orchard()
+        .map(|key| Some(key.to_bytes()) == usk.orchard().map(|key| key.to_bytes()));
+    let transparent = ufvk
+        .transparent()
+        .map(|key| Some(key.serialize()) == usk.transparent().map(|key| key.serialize()));
+    let compared: Vec<bool> = [sapling, orchard, transparent]
+        .into_iter()
+        .flatten()
+        .collect();
+    !compared.is_empty() && compared.into_iter().all(|matches| matches)
+}
+
 pub(crate) fn usk_from_seed_str(
     seed: &str,
     account_id: u32,
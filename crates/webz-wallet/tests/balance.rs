@@ -0,0 +1,32 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{add_account, wallet_with_utxo, CHAIN_TIP, MINED_HEIGHT};
+use webz_wallet::wallet::TransparentMaturity;
+
+#[tokio::test]
+async fn confirmed_transparent_value_can_be_shielded() {
+    let (wallet, account) = wallet_with_utxo(1).await;
+    let other = add_account(&wallet, 1).await;
+
+    let maturity = wallet.transparent_maturity().await.unwrap();
+    assert_eq!(
+        maturity[&account],
+        TransparentMaturity {
+            spendable: 40_000,
+            immature: 0,
+        }
+    );
+    assert_eq!(maturity[&other], TransparentMaturity::default());
+}
+
+#[tokio::test]
+async fn unconfirmed_transparent_value_is_neither_mature_nor_immature() {
+    let (wallet, account) = wallet_with_utxo(CHAIN_TIP - MINED_HEIGHT + 2).await;
+    let maturity = wallet.transparent_maturity().await.unwrap();
+    assert_eq!(maturity[&account], TransparentMaturity::default());
+}
@@ -0,0 +1,144 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#[cfg(feature = "native")]
+mod common;
+
+use webz_common::Network;
+use webz_wallet::birthday::{earliest_birthday, extrapolate_height, genesis_reference};
+
+const MAINNET_BLOSSOM: u32 = 653_600;
+const DAY: u64 = 24 * 60 * 60;
+
+#[test]
+fn heights_before_blossom_are_150_seconds_apart() {
+    let genesis = genesis_reference(&Network::MainNetwork);
+    assert_eq!(genesis.0, 0);
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, genesis, genesis.1 + 150 * 1_000),
+        1_000
+    );
+    assert_eq!(
+        extrapolate_height(
+            &Network::MainNetwork,
+            genesis,
+            genesis.1 + 150 * 1_000 + 149
+        ),
+        1_000
+    );
+}
+
+#[test]
+fn heights_after_blossom_are_75_seconds_apart() {
+    let genesis = genesis_reference(&Network::MainNetwork);
+    let blossom_time = genesis.1 + 150 * MAINNET_BLOSSOM as u64;
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, genesis, blossom_time),
+        MAINNET_BLOSSOM
+    );
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, genesis, blossom_time + 75 * 100),
+        MAINNET_BLOSSOM + 100
+    );
+}
+
+#[test]
+fn extrapolating_backwards_across_blossom() {
+    let reference = (MAINNET_BLOSSOM + 100, 1_600_000_000);
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, reference, reference.1 - 75 * 100),
+        MAINNET_BLOSSOM
+    );
+    assert_eq!(
+        extrapolate_height(
+            &Network::MainNetwork,
+            reference,
+            reference.1 - 75 * 100 - 150 * 1_000
+        ),
+        MAINNET_BLOSSOM - 1_000
+    );
+}
+
+#[test]
+fn dates_before_genesis_give_height_zero() {
+    for network in [Network::MainNetwork, Network::TestNetwork] {
+        let genesis = genesis_reference(&network);
+        assert_eq!(extrapolate_height(&network, genesis, genesis.1 - DAY), 0);
+        assert_eq!(extrapolate_height(&network, genesis, 0), 0);
+    }
+}
+
+#[test]
+fn future_dates_keep_counting_up() {
+    let reference = (2_500_000, 1_700_000_000);
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, reference, reference.1 + 365 * DAY),
+        2_500_000 + (365 * DAY / 75) as u32
+    );
+    // 2100-01-01
+    assert_eq!(
+        extrapolate_height(&Network::MainNetwork, reference, 4_102_444_800),
+        2_500_000 + ((4_102_444_800 - reference.1) / 75) as u32
+    );
+}
+
+#[test]
+fn birthdays_start_at_sapling_activation() {
+    assert_eq!(earliest_birthday(&Network::MainNetwork), 419_200);
+    assert_eq!(earliest_birthday(&Network::TestNetwork), 280_000);
+}
+
+#[cfg(feature = "native")]
+mod estimate {
+    use super::*;
+    use common::{checkpoint, offline_wallet};
+    use webz_wallet::birthday::{
+        REFERENCE_WINDOW_SECS, REFINED_MARGIN_SECS, UNREFINED_MARGIN_SECS,
+    };
+
+    const CHECKPOINT: u32 = 2_500_000;
+    // The time of `common::checkpoint`
+    const CHECKPOINT_TIME: u64 = 1_700_000_000;
+
+    async fn estimate(unix_time: u64) -> u32 {
+        let wallet = offline_wallet();
+        wallet
+            .add_checkpoints([checkpoint(CHECKPOINT)].into_iter().collect())
+            .await;
+        wallet.estimate_birthday_height(unix_time).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_nearby_checkpoint_gets_the_refined_margin() {
+        let unix_time = CHECKPOINT_TIME + REFERENCE_WINDOW_SECS;
+        assert_eq!(
+            estimate(unix_time).await,
+            CHECKPOINT + ((REFERENCE_WINDOW_SECS - REFINED_MARGIN_SECS) / 75) as u32
+        );
+    }
+
+    #[tokio::test]
+    async fn a_distant_checkpoint_gets_the_unrefined_margin_when_lightwalletd_fails() {
+        let unix_time = CHECKPOINT_TIME + REFERENCE_WINDOW_SECS + 1;
+        // The margin is wider than the distance to the checkpoint
+        assert!(estimate(unix_time).await < CHECKPOINT);
+
+        let unix_time = CHECKPOINT_TIME + UNREFINED_MARGIN_SECS + 10 * DAY;
+        assert_eq!(
+            estimate(unix_time).await,
+            CHECKPOINT + (10 * DAY / 75) as u32
+        );
+    }
+
+    #[tokio::test]
+    async fn dates_before_sapling_give_the_earliest_birthday() {
+        let wallet = offline_wallet();
+        let genesis = genesis_reference(&Network::MainNetwork);
+        for unix_time in [0, genesis.1, genesis.1 + 100 * DAY] {
+            assert_eq!(
+                wallet.estimate_birthday_height(unix_time).await.unwrap(),
+                earliest_birthday(&Network::MainNetwork)
+            );
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+use std::ops::Range;
+
+use webz_wallet::block_cache::CompactBlockCache;
+use zcash_client_backend::data_api::chain::BlockCache;
+use zcash_client_backend::data_api::scanning::{ScanPriority, ScanRange};
+use zcash_client_backend::proto::compact_formats::CompactBlock;
+use zcash_primitives::consensus::BlockHeight;
+
+fn range(heights: Range<u32>) -> ScanRange {
+    ScanRange::from_parts(
+        BlockHeight::from_u32(heights.start)..BlockHeight::from_u32(heights.end),
+        ScanPriority::Historic,
+    )
+}
+
+fn blocks(heights: Range<u32>) -> Vec<CompactBlock> {
+    heights
+        .map(|height| CompactBlock {
+            height: height.into(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+async fn cached_heights(cache: &CompactBlockCache, heights: Range<u32>) -> Vec<u64> {
+    cache
+        .read(&range(heights))
+        .await
+        .unwrap()
+        .iter()
+        .map(|block| block.height)
+        .collect()
+}
+
+#[tokio::test]
+async fn blocks_are_read_back_by_range() {
+    let cache = CompactBlockCache::new();
+    cache.insert(blocks(100..110)).await.unwrap();
+
+    assert_eq!(cached_heights(&cache, 103..106).await, vec![103, 104, 105]);
+    assert_eq!(cached_heights(&cache, 108..120).await, vec![108, 109]);
+    assert_eq!(
+        cache.get_tip_height(None).unwrap(),
+        Some(BlockHeight::from_u32(109))
+    );
+    assert_eq!(
+        cache.get_tip_height(Some(&range(100..105))).unwrap(),
+        Some(BlockHeight::from_u32(104))
+    );
+}
+
+#[tokio::test]
+async fn scanned_batches_are_evicted() {
+    let cache = CompactBlockCache::new();
+    cache.insert(blocks(100..120)).await.unwrap();
+
+    cache.delete(range(100..110)).await.unwrap();
+    assert!(cached_heights(&cache, 100..110).await.is_empty());
+    assert_eq!(cached_heights(&cache, 100..120).await.len(), 10);
+}
+
+#[tokio::test]
+async fn blocks_above_the_chain_tip_are_evicted() {
+    let cache = CompactBlockCache::new();
+    cache.insert(blocks(100..120)).await.unwrap();
+
+    // lightwalletd now reports a shorter chain ending at 114
+    cache.truncate(BlockHeight::from_u32(114)).await.unwrap();
+    assert_eq!(
+        cache.get_tip_height(None).unwrap(),
+        Some(BlockHeight::from_u32(114))
+    );
+    assert!(cached_heights(&cache, 115..120).await.is_empty());
+}
+
+#[tokio::test]
+async fn downloading_a_range_again_replaces_its_blocks() {
+    let cache = CompactBlockCache::new();
+    cache.insert(blocks(100..105)).await.unwrap();
+
+    let mut replacement = blocks(102..103);
+    replacement[0].hash = vec![1; 32];
+    cache.insert(replacement).await.unwrap();
+
+    let read = cache.read(&range(100..105)).await.unwrap();
+    assert_eq!(read.len(), 5);
+    assert_eq!(read[2].hash, vec![1; 32]);
+}
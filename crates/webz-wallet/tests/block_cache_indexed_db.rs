@@ -0,0 +1,103 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! Run in a browser with `just test-web`
+
+#![cfg(feature = "wasm")]
+
+use std::ops::Range;
+
+use wasm_bindgen_test::*;
+use webz_common::Network;
+use webz_wallet::block_cache::CompactBlockCache;
+use zcash_client_backend::data_api::chain::BlockCache;
+use zcash_client_backend::data_api::scanning::{ScanPriority, ScanRange};
+use zcash_client_backend::proto::compact_formats::CompactBlock;
+use zcash_primitives::consensus::BlockHeight;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+fn range(heights: Range<u32>) -> ScanRange {
+    ScanRange::from_parts(
+        BlockHeight::from_u32(heights.start)..BlockHeight::from_u32(heights.end),
+        ScanPriority::Historic,
+    )
+}
+
+fn blocks(heights: Range<u32>) -> Vec<CompactBlock> {
+    heights
+        .map(|height| CompactBlock {
+            height: height.into(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// A cache mirrored to the IndexedDB database `name`, as a new page load would make
+async fn persisted(name: &str, network: Network) -> CompactBlockCache {
+    let cache = CompactBlockCache::new();
+    cache.persist_to_indexed_db(name, network).await.unwrap();
+    cache
+}
+
+async fn cached_heights(cache: &CompactBlockCache, heights: Range<u32>) -> Vec<u64> {
+    cache
+        .read(&range(heights))
+        .await
+        .unwrap()
+        .iter()
+        .map(|block| block.height)
+        .collect()
+}
+
+#[wasm_bindgen_test]
+async fn blocks_outlive_the_page() {
+    let name = "webz-test-outlive";
+    let cache = persisted(name, Network::MainNetwork).await;
+    cache.insert(blocks(100..110)).await.unwrap();
+
+    let reloaded = persisted(name, Network::MainNetwork).await;
+    assert_eq!(
+        cached_heights(&reloaded, 100..110).await,
+        (100..110).collect::<Vec<_>>()
+    );
+}
+
+#[wasm_bindgen_test]
+async fn blocks_cached_before_persisting_are_written_out() {
+    let name = "webz-test-before-persisting";
+    let cache = CompactBlockCache::new();
+    cache.insert(blocks(100..105)).await.unwrap();
+    cache
+        .persist_to_indexed_db(name, Network::MainNetwork)
+        .await
+        .unwrap();
+
+    let reloaded = persisted(name, Network::MainNetwork).await;
+    assert_eq!(cached_heights(&reloaded, 100..105).await.len(), 5);
+}
+
+#[wasm_bindgen_test]
+async fn evicted_blocks_are_removed_from_indexed_db() {
+    let name = "webz-test-evicted";
+    let cache = persisted(name, Network::MainNetwork).await;
+    cache.insert(blocks(100..120)).await.unwrap();
+    cache.delete(range(100..110)).await.unwrap();
+    cache.truncate(BlockHeight::from_u32(114)).await.unwrap();
+
+    let reloaded = persisted(name, Network::MainNetwork).await;
+    assert_eq!(
+        cached_heights(&reloaded, 100..120).await,
+        (110..115).collect::<Vec<_>>()
+    );
+}
+
+#[wasm_bindgen_test]
+async fn networks_are_kept_apart() {
+    let name = "webz-test-networks";
+    let mainnet = persisted(name, Network::MainNetwork).await;
+    mainnet.insert(blocks(100..105)).await.unwrap();
+
+    let testnet = persisted(name, Network::TestNetwork).await;
+    assert!(cached_heights(&testnet, 100..105).await.is_empty());
+}
@@ -0,0 +1,208 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use webz_wallet::change::{ChangeOptions, ChangePool};
+use zcash_client_backend::fees::{
+    orchard as orchard_fees, sapling as sapling_fees, ChangeStrategy, TransactionBalance,
+};
+use zcash_client_backend::wallet::WalletTransparentOutput;
+use zcash_client_backend::{PoolType, ShieldedProtocol};
+use zcash_primitives::consensus::{BlockHeight, MainNetwork};
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::TxOut;
+
+/// After NU5 so both pools can take outputs
+const TARGET_HEIGHT: u32 = 2_500_000;
+
+struct Input {
+    id: u32,
+    value: NonNegativeAmount,
+}
+
+impl sapling_fees::InputView<u32> for Input {
+    fn note_id(&self) -> &u32 {
+        &self.id
+    }
+
+    fn value(&self) -> NonNegativeAmount {
+        self.value
+    }
+}
+
+impl orchard_fees::InputView<u32> for Input {
+    fn note_id(&self) -> &u32 {
+        &self.id
+    }
+
+    fn value(&self) -> NonNegativeAmount {
+        self.value
+    }
+}
+
+struct Output(NonNegativeAmount);
+
+impl sapling_fees::OutputView for Output {
+    fn value(&self) -> NonNegativeAmount {
+        self.0
+    }
+}
+
+impl orchard_fees::OutputView for Output {
+    fn value(&self) -> NonNegativeAmount {
+        self.0
+    }
+}
+
+fn zat(value: u64) -> NonNegativeAmount {
+    NonNegativeAmount::const_from_u64(value)
+}
+
+/// Balance of a transaction spending one Sapling note worth `input` to pay `output` to a Sapling address
+fn sapling_balance(
+    options: ChangeOptions,
+    orchard_bundle: orchard::builder::BundleType,
+    input: u64,
+    output: u64,
+) -> TransactionBalance {
+    let inputs = [Input {
+        id: 0,
+        value: zat(input),
+    }];
+    let outputs = [Output(zat(output))];
+    let no_inputs: [Input; 0] = [];
+    let no_outputs: [Output; 0] = [];
+    options
+        .change_strategy()
+        .compute_balance(
+            &MainNetwork,
+            BlockHeight::from_u32(TARGET_HEIGHT),
+            &[] as &[WalletTransparentOutput],
+            &[] as &[TxOut],
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &inputs[..],
+                &outputs[..],
+            ),
+            &(orchard_bundle, &no_inputs[..], &no_outputs[..]),
+            &options.dust_output_policy().unwrap(),
+            None,
+        )
+        .unwrap()
+}
+
+fn change(balance: &TransactionBalance) -> Vec<(PoolType, u64)> {
+    balance
+        .proposed_change()
+        .iter()
+        .map(|change| (change.output_pool(), change.value().into()))
+        .collect()
+}
+
+const SAPLING: PoolType = PoolType::Shielded(ShieldedProtocol::Sapling);
+const ORCHARD: PoolType = PoolType::Shielded(ShieldedProtocol::Orchard);
+
+#[test]
+fn default_options_keep_the_backend_change() {
+    let balance = sapling_balance(
+        ChangeOptions::default(),
+        orchard::builder::BundleType::DEFAULT,
+        1_000_000,
+        100_000,
+    );
+    // One Sapling spend and two Sapling outputs: 2 logical actions
+    assert_eq!(u64::from(balance.fee_required()), 10_000);
+    assert_eq!(change(&balance), vec![(SAPLING, 890_000)]);
+}
+
+#[test]
+fn changing_pool_adds_the_fee_of_the_new_bundle() {
+    let options = ChangeOptions {
+        pool: Some(ChangePool::Orchard),
+        ..Default::default()
+    };
+    let balance = sapling_balance(
+        options,
+        orchard::builder::BundleType::DEFAULT,
+        1_000_000,
+        100_000,
+    );
+    // The padded Sapling bundle keeps 2 logical actions and the Orchard bundle adds 2 more
+    assert_eq!(u64::from(balance.fee_required()), 20_000);
+    assert_eq!(change(&balance), vec![(ORCHARD, 880_000)]);
+}
+
+#[test]
+fn split_change_pays_for_each_output() {
+    let options = ChangeOptions {
+        split_count: 3,
+        ..Default::default()
+    };
+    let balance = sapling_balance(
+        options,
+        orchard::builder::BundleType::DEFAULT,
+        1_000_000,
+        100_000,
+    );
+    // One spend and four outputs: 4 logical actions. The first output takes the remainder.
+    assert_eq!(u64::from(balance.fee_required()), 20_000);
+    assert_eq!(
+        change(&balance),
+        vec![(SAPLING, 293_334), (SAPLING, 293_333), (SAPLING, 293_333)]
+    );
+}
+
+#[test]
+fn split_makes_fewer_outputs_than_would_be_dust() {
+    let options = ChangeOptions {
+        split_count: 3,
+        ..Default::default()
+    };
+    let balance = sapling_balance(
+        options,
+        orchard::builder::BundleType::DEFAULT,
+        40_000,
+        10_000,
+    );
+    // Three outputs would get 3_333 each, below the default dust threshold of 5_000
+    assert_eq!(u64::from(balance.fee_required()), 15_000);
+    assert_eq!(change(&balance), vec![(SAPLING, 7_500), (SAPLING, 7_500)]);
+}
+
+#[test]
+fn split_is_skipped_below_the_dust_threshold() {
+    let options = ChangeOptions {
+        split_count: 3,
+        dust_threshold: Some(8_000),
+        ..Default::default()
+    };
+    let balance = sapling_balance(
+        options,
+        orchard::builder::BundleType::DEFAULT,
+        40_000,
+        10_000,
+    );
+    assert_eq!(u64::from(balance.fee_required()), 10_000);
+    assert_eq!(change(&balance), vec![(SAPLING, 20_000)]);
+}
+
+#[test]
+fn unusable_pool_falls_back() {
+    let options = ChangeOptions {
+        pool: Some(ChangePool::Orchard),
+        fallback_pool: ChangePool::Sapling,
+        split_count: 2,
+        ..Default::default()
+    };
+    let balance = sapling_balance(
+        options,
+        orchard::builder::BundleType::DISABLED,
+        1_000_000,
+        100_000,
+    );
+    // One spend and three Sapling outputs: 3 logical actions
+    assert_eq!(u64::from(balance.fee_required()), 15_000);
+    assert_eq!(
+        change(&balance),
+        vec![(SAPLING, 442_500), (SAPLING, 442_500)]
+    );
+}
@@ -0,0 +1,96 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{checkpoint, offline_wallet, SEED_PHRASE};
+use webz_common::Network;
+use webz_wallet::birthday::Birthday;
+use webz_wallet::checkpoints::{Checkpoint, Checkpoints};
+
+const NO_CHECKPOINT: &str = "No checkpoint at or below the birthday 2495000.";
+
+#[test]
+fn bundled_checkpoints_parse() {
+    Checkpoints::bundled(Network::MainNetwork);
+    Checkpoints::bundled(Network::TestNetwork);
+}
+
+#[test]
+fn nearest_checkpoint_at_or_below() {
+    let checkpoints: Checkpoints = [checkpoint(1_000), checkpoint(2_000)].into_iter().collect();
+    let height = |height| checkpoints.at_or_below(height).map(|c| c.height);
+    assert_eq!(height(999), None);
+    assert_eq!(height(1_999), Some(1_000));
+    assert_eq!(height(2_000), Some(2_000));
+    assert_eq!(height(u32::MAX), Some(2_000));
+}
+
+#[test]
+fn nearest_checkpoint_before_a_date() {
+    let at = |height, time| Checkpoint {
+        time,
+        ..checkpoint(height)
+    };
+    let checkpoints: Checkpoints = [at(1_000, 5_000), at(2_000, 9_000)].into_iter().collect();
+    let height = |time| checkpoints.at_or_before_time(time).map(|c| c.height);
+    assert_eq!(height(4_999), None);
+    assert_eq!(height(8_999), Some(1_000));
+    assert_eq!(height(9_000), Some(2_000));
+}
+
+#[test]
+fn lightwalletd_tree_states_can_be_loaded_as_they_are() {
+    let checkpoints = Checkpoints::from_json(
+        r#"[{"height": 2000, "hash": "00ff", "time": 9000, "saplingTree": "01ab"}]"#,
+    )
+    .unwrap();
+    let checkpoint = checkpoints.at_or_below(2_000).unwrap();
+    assert_eq!(checkpoint.sapling_tree, "01ab");
+    assert_eq!(checkpoint.orchard_tree, "");
+
+    let tree_state = checkpoint.to_tree_state(&Network::TestNetwork);
+    assert_eq!(tree_state.network, "test");
+    assert_eq!(tree_state.height, 2_000);
+}
+
+#[tokio::test]
+async fn birthday_is_set_from_a_checkpoint_without_asking_lightwalletd() {
+    let wallet = offline_wallet();
+    wallet.allow_birthday_requests(false);
+    wallet
+        .add_checkpoints([checkpoint(2_490_000)].into_iter().collect())
+        .await;
+    wallet
+        .create_account(SEED_PHRASE, 0, Birthday::Height(2_495_000))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn birthday_without_a_checkpoint_fails_when_requests_are_off() {
+    let wallet = offline_wallet();
+    wallet.allow_birthday_requests(false);
+    // A checkpoint above the birthday doesn't help
+    wallet
+        .add_checkpoints([checkpoint(2_500_000)].into_iter().collect())
+        .await;
+    let err = wallet
+        .create_account(SEED_PHRASE, 0, Birthday::Height(2_495_000))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with(NO_CHECKPOINT));
+}
+
+#[tokio::test]
+async fn lightwalletd_is_asked_by_default() {
+    let wallet = offline_wallet();
+    let err = wallet
+        .create_account(SEED_PHRASE, 0, Birthday::Height(2_495_000))
+        .await
+        .unwrap_err();
+    // The error comes from trying to reach lightwalletd
+    assert!(!err.to_string().starts_with(NO_CHECKPOINT));
+}
@@ -0,0 +1,233 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::HashSet;
+
+use webz_wallet::coin_control::{check_chosen_notes, insufficient_inputs};
+use webz_wallet::diagnostics::ProposalFailure;
+use zcash_client_backend::wallet::NoteId;
+use zcash_client_backend::ShieldedProtocol;
+use zcash_primitives::transaction::TxId;
+
+fn note(tx: u8, protocol: ShieldedProtocol, index: u16) -> NoteId {
+    NoteId::new(TxId::from_bytes([tx; 32]), protocol, index)
+}
+
+#[test]
+fn spendable_notes_can_be_chosen() {
+    let a = note(1, ShieldedProtocol::Sapling, 0);
+    let b = note(2, ShieldedProtocol::Orchard, 3);
+    let spendable = HashSet::from([a, b]);
+    assert!(check_chosen_notes(&[a, b], &spendable, &spendable).is_ok());
+}
+
+#[test]
+fn unconfirmed_note_is_not_spendable_yet() {
+    let confirmed = note(1, ShieldedProtocol::Sapling, 0);
+    let recent = note(2, ShieldedProtocol::Orchard, 3);
+    let err = check_chosen_notes(
+        &[confirmed, recent],
+        &HashSet::from([confirmed]),
+        &HashSet::from([confirmed, recent]),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Cannot spend Orchard output 3 of transaction {}: it doesn't have enough confirmations yet",
+            recent.txid()
+        )
+    );
+}
+
+#[test]
+fn unknown_note_is_not_the_accounts() {
+    let spent = note(1, ShieldedProtocol::Sapling, 1);
+    let err = check_chosen_notes(&[spent], &HashSet::new(), &HashSet::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Cannot spend Sapling output 1 of transaction {}: it is not an unspent note of this account",
+            spent.txid()
+        )
+    );
+}
+
+#[test]
+fn insufficient_funds_are_blamed_on_the_chosen_inputs() {
+    let err = insufficient_inputs(
+        ProposalFailure::InsufficientFunds {
+            available: 40_000,
+            required: 55_000,
+            pools: vec![],
+        }
+        .into(),
+    );
+    assert_eq!(
+        err.to_string(),
+        "The chosen inputs hold 40000 Zatoshis but 55000 are needed to cover the payments and fee"
+    );
+}
+
+#[test]
+fn other_failures_are_kept() {
+    let err = insufficient_inputs(
+        ProposalFailure::NotSynced {
+            fully_scanned: None,
+            chain_tip: None,
+        }
+        .into(),
+    );
+    assert_eq!(
+        err.to_string(),
+        "The wallet must finish syncing before it can make this transaction"
+    );
+}
+
+#[cfg(feature = "native")]
+mod common;
+
+#[cfg(feature = "native")]
+mod wallet {
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::{synced_wallet_with_notes, synced_wallet_with_utxo};
+    use webz_common::Network;
+    use zcash_address::ZcashAddress;
+    use zcash_client_backend::wallet::NoteId;
+    use zcash_client_backend::zip321::{Payment, TransactionRequest};
+    use zcash_client_backend::ShieldedProtocol;
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+    use zcash_primitives::transaction::components::OutPoint;
+    use zcash_primitives::transaction::TxId;
+
+    /// A request paying `value` Zatoshis to someone else's transparent address
+    fn request(value: u64) -> TransactionRequest {
+        let to = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+        TransactionRequest::new(vec![Payment::without_memo(
+            ZcashAddress::try_from_encoded(&to).unwrap(),
+            NonNegativeAmount::const_from_u64(value),
+        )])
+        .unwrap()
+    }
+
+    /// The note the mock lightwalletd mined in `txid`
+    fn sapling_note(txid: TxId) -> NoteId {
+        NoteId::new(txid, ShieldedProtocol::Sapling, 0)
+    }
+
+    #[tokio::test]
+    async fn exactly_the_chosen_notes_are_spent() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, txids) =
+            synced_wallet_with_notes(&lightwalletd, &[200_000, 20_000, 60_000]).await;
+
+        let proposal = wallet
+            .propose_request_from_notes(
+                account,
+                request(30_000),
+                vec![sapling_note(txids[1]), sapling_note(txids[2])],
+                &wallet.change_options(),
+            )
+            .await
+            .unwrap();
+        let mut spent: Vec<_> = proposal
+            .steps()
+            .first()
+            .shielded_inputs()
+            .unwrap()
+            .notes()
+            .iter()
+            .map(|note| (*note.txid(), u64::from(note.note().value())))
+            .collect();
+        spent.sort_by_key(|(_, value)| *value);
+        assert_eq!(spent, vec![(txids[1], 20_000), (txids[2], 60_000)]);
+    }
+
+    #[tokio::test]
+    async fn notes_the_account_does_not_have_are_rejected() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _) = synced_wallet_with_notes(&lightwalletd, &[200_000]).await;
+        let unknown = sapling_note(TxId::from_bytes([7; 32]));
+
+        let err = wallet
+            .propose_request_from_notes(
+                account,
+                request(30_000),
+                vec![unknown],
+                &wallet.change_options(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Cannot spend Sapling output 0 of transaction {}: it is not an unspent note of this account",
+                unknown.txid()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn chosen_notes_must_cover_the_payment() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, txids) =
+            synced_wallet_with_notes(&lightwalletd, &[200_000, 20_000]).await;
+
+        // The account could pay from its other note, but only this one was chosen
+        let err = wallet
+            .propose_request_from_notes(
+                account,
+                request(30_000),
+                vec![sapling_note(txids[1])],
+                &wallet.change_options(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("The chosen inputs hold 20000 Zatoshis but "));
+    }
+
+    #[tokio::test]
+    async fn exactly_the_chosen_outputs_are_shielded() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, address, tx) = synced_wallet_with_utxo(&lightwalletd).await;
+        let outpoint = OutPoint::new(*tx.txid().as_ref(), 0);
+
+        let proposal = wallet
+            .propose_shielding_from_utxos(account, vec![outpoint.clone()], &wallet.change_options())
+            .await
+            .unwrap();
+        let step = proposal.steps().first();
+        assert!(step.is_shielding());
+        let inputs = step.transparent_inputs();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].outpoint(), &outpoint);
+        assert_eq!(inputs[0].recipient_address(), &address);
+    }
+
+    #[tokio::test]
+    async fn outputs_the_account_does_not_have_are_rejected() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _, tx) = synced_wallet_with_utxo(&lightwalletd).await;
+
+        let err = wallet
+            .propose_shielding_from_utxos(
+                account,
+                vec![OutPoint::new(*tx.txid().as_ref(), 1)],
+                &wallet.change_options(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Cannot spend transparent output 1 of transaction {}: it is not an unspent transparent output of this \
+                 account",
+                tx.txid()
+            )
+        );
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! A lightwalletd that answers requests from canned data, for the tests that need one to respond
+//!
+//! Its chain has a block at every height up to the chain tip, hashed with [`block_hash`], so a wallet can sync against
+//! it. The compact blocks only hold the Sapling outputs added with [`MockLightwalletd::add_sapling_output`]. Other
+//! transactions are only found by the full transaction and transparent address requests.
+//!
+//! Tree states are only served below the first Sapling output, which is enough for a wallet to scan the whole chain
+//! in one go.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use futures_util::stream;
+use rand_core::{OsRng, RngCore};
+use sapling::note_encryption::{sapling_note_encryption, SaplingDomain};
+use sapling::value::NoteValue;
+use sapling::{PaymentAddress, Rseed};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, http, BoxFuture, Context, Poll, Service};
+use tonic::server::Grpc;
+use tonic::Status;
+use webz_common::Network;
+use zcash_client_backend::proto::compact_formats::{
+    ChainMetadata, CompactBlock, CompactSaplingOutput, CompactTx,
+};
+use zcash_client_backend::proto::service::{
+    BlockId, BlockRange, ChainSpec, GetAddressUtxosArg, GetAddressUtxosReply,
+    GetAddressUtxosReplyList, GetSubtreeRootsArg, RawTransaction, SubtreeRoot,
+    TransparentAddressBlockFilter, TreeState, TxFilter,
+};
+use zcash_keys::encoding::AddressCodec;
+use zcash_note_encryption::{Domain, COMPACT_NOTE_SIZE};
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::memo::MemoBytes;
+use zcash_primitives::transaction::components::OutPoint;
+use zcash_primitives::transaction::{Transaction, TxId};
+
+const SERVICE: &str = "/cash.z.wallet.sdk.rpc.CompactTxStreamer/";
+
+/// Hash of the mock chain's block at `height`. The bytes are all the same so the hash reads the same either way round.
+pub fn block_hash(height: u32) -> [u8; 32] {
+    [height as u8; 32]
+}
+
+#[derive(Debug, Default)]
+struct State {
+    chain_tip: u32,
+    /// Raw transactions by transaction ID
+    transactions: HashMap<[u8; 32], RawTransaction>,
+    /// Unspent transparent outputs, as `GetAddressUtxos` returns them
+    utxos: Vec<GetAddressUtxosReply>,
+    /// IDs of the transactions that involve each transparent address
+    address_transactions: HashMap<String, Vec<[u8; 32]>>,
+    /// The transactions in the compact blocks, by height
+    compact_transactions: BTreeMap<u32, Vec<CompactTx>>,
+    /// Names of the methods called, in order
+    calls: Vec<String>,
+}
+
+/// Clones share their state, so a test can keep one to stock it and check what the wallet asked for
+#[derive(Debug, Clone, Default)]
+pub struct MockLightwalletd(Arc<Mutex<State>>);
+
+impl MockLightwalletd {
+    pub fn set_chain_tip(&self, height: u32) {
+        self.state().chain_tip = height;
+    }
+
+    /// Serve `tx` as mined at `height`, or as in the mempool if that is `None`
+    pub fn add_transaction(&self, tx: &Transaction, height: Option<u32>) {
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        self.add_raw_transaction(tx.txid(), data, height);
+    }
+
+    /// Serve `data` when asked for the transaction `txid`, whatever it holds
+    pub fn add_raw_transaction(&self, txid: TxId, data: Vec<u8>, height: Option<u32>) {
+        self.state().transactions.insert(
+            *txid.as_ref(),
+            RawTransaction {
+                data,
+                height: height.map_or(0, u64::from),
+            },
+        );
+    }
+
+    /// Serve `tx`, mined at `height`, with its output `index` to `address` unspent
+    pub fn add_utxo(
+        &self,
+        address: &TransparentAddress,
+        tx: &Transaction,
+        index: u32,
+        height: u32,
+    ) {
+        self.add_address_transaction(address, tx, height);
+        let output = &tx.transparent_bundle().unwrap().vout[index as usize];
+        self.state().utxos.push(GetAddressUtxosReply {
+            address: address.encode(&Network::MainNetwork),
+            txid: tx.txid().as_ref().to_vec(),
+            index: index as i32,
+            script: output.script_pubkey.0.clone(),
+            value_zat: u64::from(output.value) as i64,
+            height: height.into(),
+        });
+    }
+
+    /// Serve `tx`, mined at `height`, as spending from `address`. The outputs it spends are no longer unspent.
+    pub fn add_spend(&self, address: &TransparentAddress, tx: &Transaction, height: u32) {
+        self.add_address_transaction(address, tx, height);
+        let spent: Vec<OutPoint> = tx
+            .transparent_bundle()
+            .map(|bundle| {
+                bundle
+                    .vin
+                    .iter()
+                    .map(|input| input.prevout.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.state().utxos.retain(|utxo| {
+            !spent.contains(&OutPoint::new(
+                utxo.txid.as_slice().try_into().unwrap(),
+                utxo.index as u32,
+            ))
+        });
+    }
+
+    /// Mine a transaction with a Sapling output of `value` to `recipient` at `height` and return its ID. Only the
+    /// compact blocks have the output. Asked for the full transaction, lightwalletd gives its height but no data, so
+    /// enhancing it fails without changing what the wallet knows.
+    pub fn add_sapling_output(&self, height: u32, recipient: &PaymentAddress, value: u64) -> TxId {
+        let mut rseed = [0; 32];
+        OsRng.fill_bytes(&mut rseed);
+        let note = sapling::Note::from_parts(
+            *recipient,
+            NoteValue::from_raw(value),
+            Rseed::AfterZip212(rseed),
+        );
+        let cmu = note.cmu().to_bytes().to_vec();
+        let encryptor =
+            sapling_note_encryption(None, note, MemoBytes::empty().into_bytes(), &mut OsRng);
+        let output = CompactSaplingOutput {
+            cmu,
+            ephemeral_key: SaplingDomain::epk_bytes(encryptor.epk()).0.to_vec(),
+            ciphertext: encryptor.encrypt_note_plaintext()[..COMPACT_NOTE_SIZE].to_vec(),
+        };
+
+        let mut state = self.state();
+        let transactions = state.compact_transactions.entry(height).or_default();
+        let mut txid = [0xEE; 32];
+        txid[..4].copy_from_slice(&height.to_le_bytes());
+        txid[4] = transactions.len() as u8;
+        transactions.push(CompactTx {
+            index: transactions.len() as u64,
+            hash: txid.to_vec(),
+            outputs: vec![output],
+            ..Default::default()
+        });
+        state.transactions.insert(
+            txid,
+            RawTransaction {
+                data: vec![],
+                height: height.into(),
+            },
+        );
+        TxId::from_bytes(txid)
+    }
+
+    fn add_address_transaction(&self, address: &TransparentAddress, tx: &Transaction, height: u32) {
+        self.add_transaction(tx, Some(height));
+        self.state()
+            .address_transactions
+            .entry(address.encode(&Network::MainNetwork))
+            .or_default()
+            .push(*tx.txid().as_ref());
+    }
+
+    /// How many times the wallet has called `method`, e.g. `"GetTransaction"`
+    pub fn calls(&self, method: &str) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| *call == method)
+            .count()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.0.lock().unwrap()
+    }
+
+    fn get_transaction(&self, filter: TxFilter) -> Result<RawTransaction, Status> {
+        self.state()
+            .transactions
+            .get(&filter.hash[..])
+            .cloned()
+            .ok_or_else(|| Status::not_found("Transaction not found"))
+    }
+
+    fn get_latest_block(&self, _: ChainSpec) -> Result<BlockId, Status> {
+        let height = self.state().chain_tip;
+        Ok(BlockId {
+            height: height.into(),
+            hash: block_hash(height).to_vec(),
+        })
+    }
+
+    fn get_tree_state(&self, block: BlockId) -> Result<TreeState, Status> {
+        if self.sapling_tree_size(block.height as u32) > 0 {
+            return Err(Status::unimplemented(
+                "The mock only serves tree states below its first Sapling output",
+            ));
+        }
+        Ok(TreeState {
+            network: "main".to_string(),
+            height: block.height,
+            hash: hex::encode(block_hash(block.height as u32)),
+            time: 1_700_000_000,
+            sapling_tree: String::new(),
+            orchard_tree: String::new(),
+        })
+    }
+
+    fn get_block_range(&self, range: BlockRange) -> Result<Vec<CompactBlock>, Status> {
+        let height = |block: Option<BlockId>| block.map_or(0, |block| block.height as u32);
+        let start = height(range.start);
+        let end = height(range.end).min(self.state().chain_tip);
+        Ok((start..=end)
+            .map(|height| {
+                let vtx = self
+                    .state()
+                    .compact_transactions
+                    .get(&height)
+                    .cloned()
+                    .unwrap_or_default();
+                CompactBlock {
+                    height: height.into(),
+                    hash: block_hash(height).to_vec(),
+                    prev_hash: block_hash(height - 1).to_vec(),
+                    time: 1_700_000_000,
+                    vtx,
+                    chain_metadata: Some(ChainMetadata {
+                        sapling_commitment_tree_size: self.sapling_tree_size(height),
+                        orchard_commitment_tree_size: 0,
+                    }),
+                    ..Default::default()
+                }
+            })
+            .collect())
+    }
+
+    /// Size of the Sapling note commitment tree once the block at `height` is added to it
+    fn sapling_tree_size(&self, height: u32) -> u32 {
+        self.state()
+            .compact_transactions
+            .range(..=height)
+            .flat_map(|(_, transactions)| transactions)
+            .map(|tx| tx.outputs.len() as u32)
+            .sum()
+    }
+
+    fn get_subtree_roots(&self, _: GetSubtreeRootsArg) -> Result<Vec<SubtreeRoot>, Status> {
+        Ok(vec![])
+    }
+
+    fn get_address_utxos(
+        &self,
+        arg: GetAddressUtxosArg,
+    ) -> Result<GetAddressUtxosReplyList, Status> {
+        let address_utxos = self
+            .state()
+            .utxos
+            .iter()
+            .filter(|utxo| arg.addresses.contains(&utxo.address) && utxo.height >= arg.start_height)
+            .cloned()
+            .collect();
+        Ok(GetAddressUtxosReplyList { address_utxos })
+    }
+
+    fn get_taddress_txids(
+        &self,
+        filter: TransparentAddressBlockFilter,
+    ) -> Result<Vec<RawTransaction>, Status> {
+        let range = filter.range.unwrap_or_default();
+        let height = |block: Option<BlockId>| block.map_or(0, |block| block.height);
+        let (start, end) = (height(range.start), height(range.end));
+        let state = self.state();
+        Ok(state
+            .address_transactions
+            .get(&filter.address)
+            .into_iter()
+            .flatten()
+            .map(|txid| state.transactions[txid].clone())
+            .filter(|tx| (start..=end).contains(&tx.height))
+            .collect())
+    }
+}
+
+impl Service<http::Request<BoxBody>> for MockLightwalletd {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let mock = self.clone();
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix(SERVICE)
+            .unwrap_or_default()
+            .to_string();
+        mock.state().calls.push(method.clone());
+
+        Box::pin(async move {
+            Ok(match method.as_str() {
+                "GetTransaction" => unary(request, move |req| mock.get_transaction(req)).await,
+                "GetLatestBlock" => unary(request, move |req| mock.get_latest_block(req)).await,
+                "GetTreeState" => unary(request, move |req| mock.get_tree_state(req)).await,
+                "GetAddressUtxos" => unary(request, move |req| mock.get_address_utxos(req)).await,
+                "GetBlockRange" => streaming(request, move |req| mock.get_block_range(req)).await,
+                "GetSubtreeRoots" => {
+                    streaming(request, move |req| mock.get_subtree_roots(req)).await
+                }
+                "GetTaddressTxids" => {
+                    streaming(request, move |req| mock.get_taddress_txids(req)).await
+                }
+                _ => http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", "12")
+                    .header("content-type", "application/grpc")
+                    .body(empty_body())
+                    .unwrap(),
+            })
+        })
+    }
+}
+
+async fn unary<Req, Res, F>(request: http::Request<BoxBody>, handler: F) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+    F: FnMut(Req) -> Result<Res, Status> + Send + 'static,
+{
+    Grpc::new(ProstCodec::default())
+        .unary(Unary(handler), request)
+        .await
+}
+
+async fn streaming<Req, Res, F>(
+    request: http::Request<BoxBody>,
+    mut handler: F,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+    F: FnMut(Req) -> Result<Vec<Res>, Status> + Send + 'static,
+{
+    let handler = move |req| {
+        handler(req).map(|replies| stream::iter(replies.into_iter().map(Ok::<_, Status>)))
+    };
+    Grpc::new(ProstCodec::default())
+        .server_streaming(Unary(handler), request)
+        .await
+}
+
+/// Answers a request with `F`
+struct Unary<F>(F);
+
+impl<Req, Res, F> Service<tonic::Request<Req>> for Unary<F>
+where
+    F: FnMut(Req) -> Result<Res, Status>,
+{
+    type Response = tonic::Response<Res>;
+    type Error = Status;
+    type Future = std::future::Ready<Result<Self::Response, Status>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        std::future::ready((self.0)(request.into_inner()).map(tonic::Response::new))
+    }
+}
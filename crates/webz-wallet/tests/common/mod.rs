@@ -0,0 +1,269 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+//! A wallet backed by a `MemoryWalletDb` for the tests that drive a [`Wallet`]. Each test binary only uses some of
+//! these helpers.
+#![allow(dead_code)]
+
+pub mod lightwalletd;
+
+use std::num::NonZeroU32;
+
+use lightwalletd::MockLightwalletd;
+use tonic::transport::{Channel, Endpoint};
+use webz_common::Network;
+use webz_wallet::checkpoints::Checkpoint;
+use webz_wallet::history::{Owners, TransactionIndex};
+use webz_wallet::wallet::LightwalletdChannel;
+use webz_wallet::{Wallet, PRUNING_DEPTH};
+use zcash_client_backend::data_api::{Account, WalletRead, WalletWrite};
+use zcash_client_backend::wallet::WalletTransparentOutput;
+use zcash_client_memory::MemoryWalletDb;
+use zcash_primitives::consensus::{BlockHeight, BranchId};
+use zcash_primitives::legacy::{Script, TransparentAddress};
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::{transparent, OutPoint, TxIn, TxOut};
+use zcash_primitives::transaction::{Authorized, Transaction, TransactionData, TxId, TxVersion};
+
+pub type TestWallet = Wallet<MemoryWalletDb<Network>, Channel>;
+pub type MockWallet = Wallet<MemoryWalletDb<Network>, MockLightwalletd>;
+pub type AccountId = <MemoryWalletDb<Network> as WalletRead>::AccountId;
+
+pub const SEED_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art";
+
+/// Height the accounts added by [`add_account`] are born at
+pub const BIRTHDAY: u32 = 2_500_000;
+
+/// A channel to a lightwalletd that can't be reached
+pub fn offline_channel() -> Channel {
+    // Nothing listens on port 1. The connection is only attempted by the first request.
+    Endpoint::from_static("http://127.0.0.1:1").connect_lazy()
+}
+
+/// A mainnet wallet whose lightwalletd can't be reached, so anything that needs the network fails
+pub fn offline_wallet() -> TestWallet {
+    Wallet::new(
+        MemoryWalletDb::new(Network::MainNetwork, PRUNING_DEPTH),
+        offline_channel(),
+        Network::MainNetwork,
+        NonZeroU32::new(1).unwrap(),
+    )
+    .unwrap()
+}
+
+/// A checkpoint at `height` of the [`MockLightwalletd`] chain. Its commitment trees are empty, which is enough to
+/// import accounts without lightwalletd.
+pub fn checkpoint(height: u32) -> Checkpoint {
+    Checkpoint {
+        height,
+        hash: hex::encode(lightwalletd::block_hash(height)),
+        time: 1_700_000_000,
+        sapling_tree: String::new(),
+        orchard_tree: String::new(),
+    }
+}
+
+/// A mainnet wallet backed by `db` that talks to `lightwalletd`
+pub fn mock_wallet(db: MemoryWalletDb<Network>, lightwalletd: &MockLightwalletd) -> MockWallet {
+    Wallet::new(
+        db,
+        lightwalletd.clone(),
+        Network::MainNetwork,
+        NonZeroU32::new(1).unwrap(),
+    )
+    .unwrap()
+}
+
+/// Add the account of [`SEED_PHRASE`] at `hd_index`, born at [`BIRTHDAY`]
+pub async fn add_account<T: LightwalletdChannel>(
+    wallet: &Wallet<MemoryWalletDb<Network>, T>,
+    hd_index: u32,
+) -> AccountId {
+    wallet
+        .create_account_from_tree_state(
+            SEED_PHRASE,
+            hd_index,
+            checkpoint(BIRTHDAY - 1).to_tree_state(&Network::MainNetwork),
+        )
+        .await
+        .unwrap()
+}
+
+/// Time of the blocks [`record`] mines transactions in
+pub const BLOCK_TIME: u32 = 1_700_000_000;
+
+/// The database of a wallet with one account, along with the account and its transparent address
+pub async fn wallet_db() -> (MemoryWalletDb<Network>, AccountId, TransparentAddress) {
+    let wallet = offline_wallet();
+    let account = add_account(&wallet, 0).await;
+    let (db, _): (MemoryWalletDb<Network>, _) =
+        postcard::take_from_bytes(&wallet.db_to_bytes().await.unwrap()).unwrap();
+    let address = *db
+        .get_transparent_receivers(account)
+        .unwrap()
+        .keys()
+        .next()
+        .unwrap();
+    (db, account, address)
+}
+
+/// A wallet restored from `db` and `index`, as if it had saved both with [`Wallet::db_to_bytes`]
+pub fn restore(db: &MemoryWalletDb<Network>, index: &TransactionIndex<AccountId>) -> TestWallet {
+    let mut bytes = postcard::to_allocvec(db).unwrap();
+    bytes.extend(index.to_bytes().unwrap());
+    Wallet::from_bytes(
+        &bytes,
+        offline_channel(),
+        Network::MainNetwork,
+        NonZeroU32::new(1).unwrap(),
+    )
+    .unwrap()
+}
+
+/// A transaction from outside the wallet paying `value` to `address`
+pub fn payment(address: &TransparentAddress, value: u64) -> Transaction {
+    transparent_transaction(
+        vec![],
+        vec![TxOut {
+            value: NonNegativeAmount::const_from_u64(value),
+            script_pubkey: address.script(),
+        }],
+    )
+}
+
+/// A transaction with only a transparent part, spending `vin` to `vout`
+pub fn transparent_transaction(
+    vin: Vec<TxIn<transparent::Authorized>>,
+    vout: Vec<TxOut>,
+) -> Transaction {
+    TransactionData::<Authorized>::from_parts(
+        TxVersion::Zip225,
+        BranchId::Nu5,
+        0,
+        BlockHeight::from_u32(0),
+        Some(transparent::Bundle {
+            vin,
+            vout,
+            authorization: transparent::Authorized,
+        }),
+        None,
+        None,
+        None,
+    )
+    .freeze()
+    .unwrap()
+}
+
+/// An input spending `outpoint`
+pub fn input(outpoint: OutPoint) -> TxIn<transparent::Authorized> {
+    TxIn {
+        prevout: outpoint,
+        script_sig: Script(vec![]),
+        sequence: u32::MAX,
+    }
+}
+
+/// Record `tx` in `index`, mined at `mined_height` if it is set
+pub fn record(
+    index: &TransactionIndex<AccountId>,
+    db: &MemoryWalletDb<Network>,
+    tx: &Transaction,
+    mined_height: Option<u32>,
+) {
+    let owners = Owners::read(db).unwrap();
+    index
+        .record_transaction(
+            &Network::MainNetwork,
+            db,
+            &owners,
+            tx,
+            mined_height.map(BlockHeight::from_u32),
+        )
+        .unwrap();
+    if let Some(height) = mined_height {
+        index.insert_block_time(BlockHeight::from_u32(height), BLOCK_TIME);
+    }
+}
+
+/// Chain tip of the wallets made by [`wallet_with_utxo`]
+pub const CHAIN_TIP: u32 = BIRTHDAY + 10;
+/// Height the output of [`wallet_with_utxo`] was mined at
+pub const MINED_HEIGHT: u32 = BIRTHDAY + 1;
+
+/// A wallet whose account received 40,000 zatoshis at its transparent address at [`MINED_HEIGHT`], in the
+/// output 0 of the transaction with ID `[1; 32]`
+pub async fn wallet_with_utxo(min_confirmations: u32) -> (TestWallet, AccountId) {
+    let (mut db, account, address) = wallet_db().await;
+    db.update_chain_tip(BlockHeight::from_u32(CHAIN_TIP))
+        .unwrap();
+    let utxo = WalletTransparentOutput::from_parts(
+        OutPoint::new([1; 32], 0),
+        TxOut {
+            value: NonNegativeAmount::const_from_u64(40_000),
+            script_pubkey: address.script(),
+        },
+        Some(BlockHeight::from_u32(MINED_HEIGHT)),
+    )
+    .unwrap();
+    db.put_received_transparent_utxo(&utxo).unwrap();
+
+    let wallet = Wallet::new(
+        db,
+        offline_channel(),
+        Network::MainNetwork,
+        NonZeroU32::new(min_confirmations).unwrap(),
+    )
+    .unwrap();
+    (wallet, account)
+}
+
+/// A wallet synced against `lightwalletd` once it reaches [`CHAIN_TIP`], whose account found a payment of 40,000
+/// zatoshis to its transparent address mined at [`MINED_HEIGHT`]. Returns the account, its address and the payment.
+pub async fn synced_wallet_with_utxo(
+    lightwalletd: &MockLightwalletd,
+) -> (MockWallet, AccountId, TransparentAddress, Transaction) {
+    let (db, account, address) = wallet_db().await;
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    let tx = payment(&address, 40_000);
+    lightwalletd.add_utxo(&address, &tx, 0, MINED_HEIGHT);
+    let wallet = mock_wallet(db, lightwalletd);
+    wallet.sync().await.unwrap();
+    (wallet, account, address, tx)
+}
+
+/// The Sapling address `account` of `db` receives at by default
+pub fn sapling_address(
+    db: &MemoryWalletDb<Network>,
+    account: AccountId,
+) -> sapling::PaymentAddress {
+    db.get_account(account)
+        .unwrap()
+        .unwrap()
+        .ufvk()
+        .unwrap()
+        .sapling()
+        .unwrap()
+        .default_address()
+        .1
+}
+
+/// A wallet synced against `lightwalletd` once it reaches [`CHAIN_TIP`], whose account found a Sapling note of each of
+/// `values`. The first is mined at [`MINED_HEIGHT`] and each of the others a block later than the one before. Returns
+/// the account and the IDs of the notes' transactions.
+pub async fn synced_wallet_with_notes(
+    lightwalletd: &MockLightwalletd,
+    values: &[u64],
+) -> (MockWallet, AccountId, Vec<TxId>) {
+    let (db, account, _) = wallet_db().await;
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    let address = sapling_address(&db, account);
+    let txids = values
+        .iter()
+        .zip(MINED_HEIGHT..)
+        .map(|(value, height)| lightwalletd.add_sapling_output(height, &address, *value))
+        .collect();
+    let wallet = mock_wallet(db, lightwalletd);
+    wallet.sync().await.unwrap();
+    (wallet, account, txids)
+}
@@ -0,0 +1,134 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use webz_wallet::diagnostics::{classify_shortfall, spendable_height, PoolFunds, ProposalFailure};
+use webz_wallet::history::Pool;
+
+fn pools(sapling_pending: u64, orchard_pending: u64) -> Vec<PoolFunds> {
+    vec![
+        PoolFunds {
+            pool: Pool::Sapling,
+            spendable: 10_000,
+            pending: sapling_pending,
+        },
+        PoolFunds {
+            pool: Pool::Orchard,
+            spendable: 20_000,
+            pending: orchard_pending,
+        },
+    ]
+}
+
+#[test]
+fn pending_funds_that_cover_the_shortfall_are_not_yet_spendable() {
+    let failure = classify_shortfall(30_000, 50_000, pools(5_000, 15_000), 1_000, 1_000, 3);
+    assert_eq!(
+        failure,
+        ProposalFailure::NotYetSpendable {
+            available: 30_000,
+            required: 50_000,
+            spendable_at: None,
+            pools: pools(5_000, 15_000),
+        }
+    );
+}
+
+#[test]
+fn pending_funds_win_over_an_unfinished_scan() {
+    let failure = classify_shortfall(30_000, 50_000, pools(0, 20_000), 900, 1_000, 3);
+    assert!(matches!(failure, ProposalFailure::NotYetSpendable { .. }));
+}
+
+#[test]
+fn unscanned_blocks_mean_not_synced() {
+    // Spending with 3 confirmations at a tip of 1_000 needs the wallet scanned up to 998
+    let failure = classify_shortfall(30_000, 50_000, pools(0, 0), 997, 1_000, 3);
+    assert_eq!(
+        failure,
+        ProposalFailure::NotSynced {
+            fully_scanned: Some(997),
+            chain_tip: Some(1_000),
+        }
+    );
+}
+
+#[test]
+fn scanned_wallet_short_of_funds_is_insufficient() {
+    let failure = classify_shortfall(30_000, 50_000, pools(5_000, 5_000), 998, 1_000, 3);
+    assert_eq!(
+        failure,
+        ProposalFailure::InsufficientFunds {
+            available: 30_000,
+            required: 50_000,
+            pools: pools(5_000, 5_000),
+        }
+    );
+}
+
+#[test]
+fn spendable_once_the_oldest_pending_notes_cover_the_shortfall() {
+    let pending = vec![(1_005, 8_000), (1_001, 5_000), (1_003, 4_000)];
+    assert_eq!(spendable_height(pending.clone(), 9_000, 3), Some(1_005));
+    assert_eq!(spendable_height(pending.clone(), 9_000, 1), Some(1_003));
+    assert_eq!(spendable_height(pending.clone(), 5_000, 3), Some(1_003));
+    assert_eq!(spendable_height(pending, 20_000, 3), None);
+}
+
+#[cfg(feature = "native")]
+mod common;
+
+#[cfg(feature = "native")]
+mod wallet {
+    use std::str::FromStr;
+
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::{add_account, offline_wallet, synced_wallet_with_notes};
+    use webz_common::Network;
+    use zcash_address::ZcashAddress;
+    use zcash_client_backend::zip321::{Payment, TransactionRequest};
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::memo::Memo;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+
+    fn transparent_address() -> String {
+        TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork)
+    }
+
+    #[tokio::test]
+    async fn memos_to_transparent_addresses_are_reported_with_the_address() {
+        let wallet = offline_wallet();
+        let account = add_account(&wallet, 0).await;
+        let to = ZcashAddress::try_from_encoded(&transparent_address()).unwrap();
+        let memo = Memo::from_str("order 1234").unwrap().encode();
+
+        let err = wallet
+            .max_sendable_to_address(account, to, Some(memo))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Cannot send a memo to the transparent address {}",
+                transparent_address()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn a_synced_account_short_of_funds_has_insufficient_funds() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _) = synced_wallet_with_notes(&lightwalletd, &[20_000]).await;
+        let to = ZcashAddress::try_from_encoded(&transparent_address()).unwrap();
+        let request = TransactionRequest::new(vec![Payment::without_memo(
+            to,
+            NonNegativeAmount::const_from_u64(100_000),
+        )])
+        .unwrap();
+
+        let err = wallet.propose_request(account, request).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Insufficient funds: 20000 Zatoshis available but "));
+    }
+}
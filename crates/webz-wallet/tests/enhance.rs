@@ -0,0 +1,93 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::lightwalletd::MockLightwalletd;
+use common::{mock_wallet, payment, wallet_db, BIRTHDAY};
+use webz_wallet::history::{Direction, HistoryFilter, TransactionStatus};
+
+#[tokio::test]
+async fn fetched_transactions_are_stored_and_recorded() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    let wallet = mock_wallet(db, &lightwalletd);
+    let tx = payment(&address, 25_000);
+    lightwalletd.add_transaction(&tx, Some(BIRTHDAY + 5));
+
+    let memos = wallet
+        .get_transaction_memos(account, tx.txid())
+        .await
+        .unwrap();
+    assert!(memos.outputs.is_empty());
+    assert_eq!(lightwalletd.calls("GetTransaction"), 1);
+
+    let page = wallet
+        .get_transaction_history(account, &HistoryFilter::default(), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(page.total, 1);
+    let entry = &page.entries[0];
+    assert_eq!(entry.txid, tx.txid().to_string());
+    assert_eq!(entry.direction, Direction::Received);
+    assert_eq!(entry.value, 25_000);
+    assert!(entry.complete);
+    assert_eq!(entry.status, TransactionStatus::Pending);
+
+    // Now the wallet has it
+    wallet
+        .get_transaction_memos(account, tx.txid())
+        .await
+        .unwrap();
+    assert_eq!(lightwalletd.calls("GetTransaction"), 1);
+}
+
+#[tokio::test]
+async fn transactions_lightwalletd_does_not_know_are_not_found() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    let wallet = mock_wallet(db, &lightwalletd);
+    let tx = payment(&address, 25_000);
+
+    let err = wallet
+        .get_transaction_memos(account, tx.txid())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("Transaction with given txid not found: {}", tx.txid())
+    );
+}
+
+#[tokio::test]
+async fn transactions_are_checked_against_the_requested_id() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    let wallet = mock_wallet(db, &lightwalletd);
+    let requested = payment(&address, 25_000);
+    let served = payment(&address, 30_000);
+    let mut data = vec![];
+    served.write(&mut data).unwrap();
+    lightwalletd.add_raw_transaction(requested.txid(), data, Some(BIRTHDAY + 5));
+
+    let err = wallet
+        .get_transaction_memos(account, requested.txid())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Syncing Error: lightwalletd returned {} when asked for transaction {}",
+            served.txid(),
+            requested.txid()
+        )
+    );
+    // Nothing was recorded
+    let page = wallet
+        .get_transaction_history(account, &HistoryFilter::default(), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(page.total, 0);
+}
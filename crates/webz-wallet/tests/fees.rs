@@ -0,0 +1,151 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#[cfg(feature = "native")]
+mod common;
+
+use std::convert::Infallible;
+
+use futures_util::future::{ready, FutureExt};
+use webz_wallet::fees::{find_max_amount, AmountFit};
+
+/// Proposes like the greedy selector with a ZIP-317 fee of 5_000 per spent note, and at least 10_000: the notes are
+/// taken in order until they cover the amount and the fee for that many notes
+fn propose(notes: &[u64], amount: u64) -> AmountFit {
+    let fee = |count: usize| 5_000 * count.max(2) as u64;
+    let mut selected = 0;
+    for (count, value) in notes.iter().enumerate() {
+        selected += value;
+        if selected >= amount + fee(count + 1) {
+            return AmountFit::Fits;
+        }
+    }
+    AmountFit::Short(amount + fee(notes.len()) - selected)
+}
+
+fn max_amount(notes: &[u64], others: u64) -> Option<u64> {
+    let spendable: u64 = notes.iter().sum();
+    find_max_amount(spendable.saturating_sub(others), |amount| {
+        ready(Ok::<_, Infallible>(propose(notes, amount + others)))
+    })
+    .now_or_never()
+    .unwrap()
+    .unwrap()
+}
+
+#[test]
+fn lowers_the_amount_by_the_fee() {
+    // Spending all three notes costs 15_000
+    assert_eq!(max_amount(&[60_000, 30_000, 20_000], 0), Some(95_000));
+}
+
+#[test]
+fn single_note_pays_the_minimum_fee() {
+    assert_eq!(max_amount(&[100_000], 0), Some(90_000));
+}
+
+#[test]
+fn other_payments_are_left_room() {
+    assert_eq!(max_amount(&[60_000, 30_000, 20_000], 40_000), Some(55_000));
+}
+
+#[test]
+fn nothing_left_after_the_other_payments() {
+    assert_eq!(max_amount(&[60_000], 55_000), Some(0));
+    assert_eq!(max_amount(&[60_000], 70_000), Some(0));
+}
+
+#[test]
+fn gives_up_when_the_amount_never_fits() {
+    let result = find_max_amount(1_000_000, |_| {
+        ready(Ok::<_, Infallible>(AmountFit::Short(0)))
+    })
+    .now_or_never()
+    .unwrap();
+    assert_eq!(result, Ok(None));
+}
+
+#[cfg(feature = "native")]
+mod wallet {
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::{add_account, offline_wallet, synced_wallet_with_utxo};
+    use webz_common::Network;
+    use zcash_address::ZcashAddress;
+    use zcash_client_backend::zip321::{Payment, TransactionRequest};
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+
+    fn recipient() -> ZcashAddress {
+        let address = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+        ZcashAddress::try_from_encoded(&address).unwrap()
+    }
+
+    fn request(amounts: &[u64]) -> TransactionRequest {
+        TransactionRequest::new(
+            amounts
+                .iter()
+                .map(|amount| {
+                    Payment::without_memo(recipient(), NonNegativeAmount::const_from_u64(*amount))
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn transparent_funds_are_not_sendable() {
+        // Transfers only spend shielded notes, so the account has to shield its 40,000 Zatoshis first
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _, _) = synced_wallet_with_utxo(&lightwalletd).await;
+        assert_eq!(
+            wallet
+                .max_sendable_to_address(account, recipient(), None)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            wallet
+                .max_sendable(account, request(&[0, 10_000]), 0)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let err = wallet
+            .estimate_fee(account, request(&[10_000]))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Insufficient funds: 0 Zatoshis available but "));
+    }
+
+    #[tokio::test]
+    async fn a_wallet_that_has_not_synced_can_send_nothing() {
+        let wallet = offline_wallet();
+        let account = add_account(&wallet, 0).await;
+        assert_eq!(
+            wallet
+                .max_sendable_to_address(account, recipient(), None)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn the_payment_to_maximize_must_be_in_the_request() {
+        let wallet = offline_wallet();
+        let account = add_account(&wallet, 0).await;
+        let err = wallet
+            .max_sendable(account, request(&[10_000]), 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "An generic error occurred: Transaction request has no payment with index 1"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{payment, record, restore, wallet_db, AccountId, TestWallet, BIRTHDAY, BLOCK_TIME};
+use webz_wallet::history::{
+    Direction, HistoryFilter, PoolDeltas, TransactionHistoryEntry, TransactionIndex,
+    TransactionStatus,
+};
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::transaction::Transaction;
+
+async fn history(wallet: &TestWallet, account: AccountId) -> Vec<TransactionHistoryEntry> {
+    wallet
+        .get_transaction_history(account, &HistoryFilter::default(), 0, None)
+        .await
+        .unwrap()
+        .entries
+}
+
+#[tokio::test]
+async fn received_payments_are_listed() {
+    let (db, account, address) = wallet_db().await;
+    let index = TransactionIndex::default();
+    let tx = payment(&address, 50_000);
+    record(&index, &db, &tx, Some(BIRTHDAY + 10));
+    // Recording it again, as the wallet does for transactions it fetches, doesn't forget where it was mined
+    record(&index, &db, &tx, None);
+
+    let entries = history(&restore(&db, &index), account).await;
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry.txid, tx.txid().to_string());
+    assert_eq!(
+        entry.status,
+        TransactionStatus::Mined {
+            height: BIRTHDAY + 10
+        }
+    );
+    assert_eq!(entry.block_time, Some(BLOCK_TIME));
+    assert_eq!(entry.direction, Direction::Received);
+    assert!(entry.complete);
+    assert_eq!(entry.value, 50_000);
+    assert_eq!(
+        entry.pool_deltas,
+        PoolDeltas {
+            transparent: 50_000,
+            ..Default::default()
+        }
+    );
+    assert_eq!(entry.fee, None);
+}
+
+#[tokio::test]
+async fn rewinding_unmines_transactions_above_the_rewind_height() {
+    let (db, account, address) = wallet_db().await;
+    let index = TransactionIndex::default();
+    let kept = payment(&address, 1_000);
+    let rewound = payment(&address, 2_000);
+    record(&index, &db, &kept, Some(BIRTHDAY + 10));
+    record(&index, &db, &rewound, Some(BIRTHDAY + 20));
+
+    index.truncate(BlockHeight::from_u32(BIRTHDAY + 10));
+    let entries = history(&restore(&db, &index), account).await;
+    let status = |tx: &Transaction| {
+        entries
+            .iter()
+            .find(|entry| entry.txid == tx.txid().to_string())
+            .map(|entry| (entry.status, entry.block_time))
+            .unwrap()
+    };
+    assert_eq!(
+        status(&kept),
+        (
+            TransactionStatus::Mined {
+                height: BIRTHDAY + 10
+            },
+            Some(BLOCK_TIME)
+        )
+    );
+    assert_eq!(status(&rewound), (TransactionStatus::Pending, None));
+    // Unmined transactions are listed first
+    assert_eq!(entries[0].txid, rewound.txid().to_string());
+
+    // Found again in the new chain
+    record(&index, &db, &rewound, Some(BIRTHDAY + 15));
+    let entries = history(&restore(&db, &index), account).await;
+    assert_eq!(entries[0].txid, rewound.txid().to_string());
+    assert_eq!(
+        entries[0].status,
+        TransactionStatus::Mined {
+            height: BIRTHDAY + 15
+        }
+    );
+}
+
+#[tokio::test]
+async fn height_filters_skip_rewound_transactions() {
+    let (db, account, address) = wallet_db().await;
+    let index = TransactionIndex::default();
+    record(&index, &db, &payment(&address, 1_000), Some(BIRTHDAY + 20));
+    index.truncate(BlockHeight::from_u32(BIRTHDAY + 10));
+
+    let filter = HistoryFilter {
+        from_height: Some(BIRTHDAY),
+        ..Default::default()
+    };
+    let page = restore(&db, &index)
+        .get_transaction_history(account, &filter, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(page.total, 0);
+}
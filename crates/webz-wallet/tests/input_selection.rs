@@ -0,0 +1,191 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use webz_wallet::history::Pool;
+use webz_wallet::input_selection::{select_notes, NoteCandidate, SelectionStrategy};
+
+fn note(pool: Pool, value: u64, mined_height: u32, chain_tip: u32) -> NoteCandidate {
+    NoteCandidate {
+        pool,
+        value,
+        mined_height: Some(mined_height),
+        confirmations: chain_tip - mined_height + 1,
+    }
+}
+
+/// Notes in the order the wallet database returns them
+fn wallet_notes() -> Vec<NoteCandidate> {
+    let tip = 1000;
+    vec![
+        note(Pool::Sapling, 30_000, 990, tip),  // 0
+        note(Pool::Orchard, 10_000, 800, tip),  // 1
+        note(Pool::Sapling, 200_000, 995, tip), // 2
+        note(Pool::Orchard, 50_000, 998, tip),  // 3
+        note(Pool::Orchard, 120_000, 500, tip), // 4
+    ]
+}
+
+#[test]
+fn greedy_keeps_the_wallet_order() {
+    let notes = wallet_notes();
+    assert_eq!(
+        select_notes(&SelectionStrategy::Greedy, &notes, 35_000),
+        vec![0, 1]
+    );
+}
+
+#[test]
+fn minimize_inputs_uses_the_smallest_note_that_covers_the_amount() {
+    let notes = wallet_notes();
+    assert_eq!(
+        select_notes(&SelectionStrategy::MinimizeInputs, &notes, 100_000),
+        vec![4]
+    );
+    assert_eq!(
+        select_notes(&SelectionStrategy::MinimizeInputs, &notes, 250_000),
+        vec![2, 3]
+    );
+}
+
+#[test]
+fn single_pool_prefers_orchard_and_never_mixes_pools() {
+    let notes = wallet_notes();
+    assert_eq!(
+        select_notes(&SelectionStrategy::SinglePool, &notes, 150_000),
+        vec![1, 3, 4]
+    );
+    // Orchard holds 180_000 so only Sapling can cover this
+    assert_eq!(
+        select_notes(&SelectionStrategy::SinglePool, &notes, 200_000),
+        vec![0, 2]
+    );
+    // Neither pool can, so everything in the larger pool is offered and the shortfall is reported against it
+    assert_eq!(
+        select_notes(&SelectionStrategy::SinglePool, &notes, 300_000),
+        vec![0, 2]
+    );
+}
+
+#[test]
+fn oldest_first_orders_by_mined_height() {
+    let notes = wallet_notes();
+    assert_eq!(
+        select_notes(&SelectionStrategy::OldestFirst, &notes, 125_000),
+        vec![4, 1]
+    );
+}
+
+#[test]
+fn prefer_confirmed_spends_newer_notes_only_when_needed() {
+    let notes = wallet_notes();
+    let strategy = SelectionStrategy::PreferConfirmed { confirmations: 10 };
+    assert_eq!(select_notes(&strategy, &notes, 100_000), vec![0, 1, 4]);
+    assert_eq!(select_notes(&strategy, &notes, 200_000), vec![0, 1, 4, 2]);
+}
+
+#[test]
+fn every_note_is_offered_when_the_balance_is_short() {
+    let notes = wallet_notes();
+    for strategy in [
+        SelectionStrategy::Greedy,
+        SelectionStrategy::MinimizeInputs,
+        SelectionStrategy::OldestFirst,
+        SelectionStrategy::PreferConfirmed { confirmations: 10 },
+    ] {
+        let mut selected = select_notes(&strategy, &notes, 1_000_000);
+        selected.sort();
+        assert_eq!(selected, vec![0, 1, 2, 3, 4], "{:?}", strategy);
+    }
+}
+
+#[test]
+fn nothing_is_spent_for_a_zero_amount() {
+    let notes = wallet_notes();
+    assert!(select_notes(&SelectionStrategy::MinimizeInputs, &notes, 0).is_empty());
+}
+
+#[cfg(feature = "native")]
+mod common;
+
+#[cfg(feature = "native")]
+mod wallet {
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::{synced_wallet_with_notes, AccountId, MockWallet, CHAIN_TIP, MINED_HEIGHT};
+    use webz_common::Network;
+    use webz_wallet::input_selection::SelectionStrategy;
+    use zcash_address::ZcashAddress;
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+    use zip321::{Payment, TransactionRequest};
+
+    /// Notes of 200,000, 20,000 and 60,000 Zatoshis, mined in that order
+    const NOTES: [u64; 3] = [200_000, 20_000, 60_000];
+
+    /// The values of the notes `strategy` spends to pay 30,000 Zatoshis
+    async fn spent_notes(
+        wallet: &MockWallet,
+        account: AccountId,
+        strategy: SelectionStrategy,
+    ) -> Vec<u64> {
+        let to = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+        let to = ZcashAddress::try_from_encoded(&to).unwrap();
+        let request = TransactionRequest::new(vec![Payment::without_memo(
+            to,
+            NonNegativeAmount::const_from_u64(30_000),
+        )])
+        .unwrap();
+        let proposal = wallet
+            .propose_request_with(account, request, &wallet.change_options(), strategy)
+            .await
+            .unwrap();
+        let mut values: Vec<u64> = proposal
+            .steps()
+            .first()
+            .shielded_inputs()
+            .unwrap()
+            .notes()
+            .iter()
+            .map(|note| u64::from(note.note().value()))
+            .collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[tokio::test]
+    async fn minimize_inputs_spends_the_smallest_note_that_covers_the_payment() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _) = synced_wallet_with_notes(&lightwalletd, &NOTES).await;
+        assert_eq!(
+            spent_notes(&wallet, account, SelectionStrategy::MinimizeInputs).await,
+            vec![60_000]
+        );
+    }
+
+    #[tokio::test]
+    async fn oldest_first_spends_the_note_mined_first() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _) = synced_wallet_with_notes(&lightwalletd, &NOTES).await;
+        assert_eq!(
+            spent_notes(&wallet, account, SelectionStrategy::OldestFirst).await,
+            vec![200_000]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefer_confirmed_spends_the_notes_with_enough_confirmations() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, _) = synced_wallet_with_notes(&lightwalletd, &NOTES).await;
+        // Only the note mined at MINED_HEIGHT has this many confirmations at the chain tip
+        let confirmations = CHAIN_TIP - MINED_HEIGHT + 1;
+        assert_eq!(
+            spent_notes(
+                &wallet,
+                account,
+                SelectionStrategy::PreferConfirmed { confirmations }
+            )
+            .await,
+            vec![200_000]
+        );
+    }
+}
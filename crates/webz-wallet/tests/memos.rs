@@ -0,0 +1,103 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#[cfg(feature = "native")]
+mod common;
+
+use std::str::FromStr;
+
+use webz_wallet::memos::MemoContent;
+use zcash_primitives::memo::{Memo, MemoBytes};
+
+fn content(bytes: &[u8]) -> MemoContent {
+    (&MemoBytes::from_bytes(bytes).unwrap()).into()
+}
+
+#[test]
+fn memos_are_decoded_as_zip_302_describes() {
+    assert_eq!(content(&[0xF6]), MemoContent::Empty);
+    assert_eq!(
+        MemoContent::from(&Memo::from_str("Thanks!").unwrap().encode()),
+        MemoContent::Text {
+            text: "Thanks!".to_string()
+        }
+    );
+
+    let MemoContent::Arbitrary { data } = content(&[0xFF, 0x01, 0x02]) else {
+        panic!("not an arbitrary data memo");
+    };
+    // The lead byte is left out
+    assert_eq!(data.len(), 511 * 2);
+    assert!(data.starts_with("0102"));
+
+    let MemoContent::Future { data } = content(&[0xF7, 0x01]) else {
+        panic!("not a future memo");
+    };
+    assert_eq!(data.len(), 512 * 2);
+    assert!(data.starts_with("f701"));
+}
+
+#[test]
+fn text_that_is_not_utf8_is_left_to_future_use() {
+    let MemoContent::Future { data } = content(&[0xC3, 0x28]) else {
+        panic!("not a future memo");
+    };
+    assert!(data.starts_with("c328"));
+}
+
+#[test]
+fn memo_contents_are_tagged_with_their_type() {
+    assert_eq!(
+        serde_json::to_value(MemoContent::Text {
+            text: "hi".to_string()
+        })
+        .unwrap(),
+        serde_json::json!({ "type": "Text", "text": "hi" })
+    );
+    assert_eq!(
+        serde_json::to_value(MemoContent::Empty).unwrap(),
+        serde_json::json!({ "type": "Empty" })
+    );
+}
+
+#[cfg(feature = "native")]
+mod wallet {
+    use super::common::{add_account, offline_wallet, payment, record, restore, wallet_db};
+    use webz_wallet::history::TransactionIndex;
+    use zcash_primitives::transaction::TxId;
+
+    #[tokio::test]
+    async fn a_fresh_account_has_no_memos() {
+        let wallet = offline_wallet();
+        let account = add_account(&wallet, 0).await;
+        assert!(wallet.get_account_memos(account).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transparent_transactions_have_no_memos_to_fetch() {
+        let (db, account, address) = wallet_db().await;
+        let index = TransactionIndex::default();
+        let tx = payment(&address, 10_000);
+        record(&index, &db, &tx, None);
+        let wallet = restore(&db, &index);
+
+        // Neither needs lightwalletd, which can't be reached
+        assert!(wallet.get_account_memos(account).await.unwrap().is_empty());
+        let memos = wallet
+            .get_transaction_memos(account, tx.txid())
+            .await
+            .unwrap();
+        assert_eq!(memos.txid, tx.txid().to_string());
+        assert!(memos.outputs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_transactions_are_fetched_from_lightwalletd() {
+        let wallet = offline_wallet();
+        let account = add_account(&wallet, 0).await;
+        assert!(wallet
+            .get_transaction_memos(account, TxId::from_bytes([7; 32]))
+            .await
+            .is_err());
+    }
+}
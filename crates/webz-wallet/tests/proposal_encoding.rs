@@ -0,0 +1,206 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use webz_wallet::proposal_encoding::{decode_proposal, encode_proposal};
+use zcash_client_backend::data_api::{InputSource, SpendableNotes};
+use zcash_client_backend::fees::{ChangeValue, TransactionBalance};
+use zcash_client_backend::proposal::Proposal;
+use zcash_client_backend::wallet::{Note, ReceivedNote, WalletTransparentOutput};
+use zcash_client_backend::zip321::TransactionRequest;
+use zcash_client_backend::ShieldedProtocol;
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::{OutPoint, TxOut};
+use zcash_primitives::transaction::fees::zip317::FeeRule;
+use zcash_primitives::transaction::TxId;
+
+const MIN_TARGET_HEIGHT: u32 = 2_500_000;
+
+/// A wallet holding only the transparent outputs a proposal could spend
+struct Utxos(Vec<WalletTransparentOutput>);
+
+impl InputSource for Utxos {
+    type Error = Infallible;
+    type AccountId = u32;
+    type NoteRef = u32;
+
+    fn get_spendable_note(
+        &self,
+        _txid: &TxId,
+        _protocol: ShieldedProtocol,
+        _index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        Ok(None)
+    }
+
+    fn select_spendable_notes(
+        &self,
+        _account: Self::AccountId,
+        _target_value: NonNegativeAmount,
+        _sources: &[ShieldedProtocol],
+        _anchor_height: BlockHeight,
+        _exclude: &[Self::NoteRef],
+    ) -> Result<SpendableNotes<Self::NoteRef>, Self::Error> {
+        Ok(SpendableNotes::empty())
+    }
+
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        Ok(self
+            .0
+            .iter()
+            .find(|output| output.outpoint() == outpoint)
+            .cloned())
+    }
+
+    fn get_spendable_transparent_outputs(
+        &self,
+        _address: &TransparentAddress,
+        _target_height: BlockHeight,
+        _min_confirmations: u32,
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        Ok(self.0.clone())
+    }
+}
+
+fn utxo() -> WalletTransparentOutput {
+    WalletTransparentOutput::from_parts(
+        OutPoint::new([7; 32], 1),
+        TxOut {
+            value: NonNegativeAmount::const_from_u64(100_000),
+            script_pubkey: TransparentAddress::PublicKeyHash([3; 20]).script(),
+        },
+        Some(BlockHeight::from_u32(MIN_TARGET_HEIGHT - 10)),
+    )
+    .unwrap()
+}
+
+/// Shields the output of [`utxo`] into Orchard
+fn shielding_proposal() -> Proposal<FeeRule, u32> {
+    let balance = TransactionBalance::new(
+        vec![ChangeValue::shielded(
+            ShieldedProtocol::Orchard,
+            NonNegativeAmount::const_from_u64(85_000),
+            None,
+        )],
+        NonNegativeAmount::const_from_u64(15_000),
+    )
+    .unwrap();
+    Proposal::single_step(
+        TransactionRequest::empty(),
+        BTreeMap::new(),
+        vec![utxo()],
+        None,
+        balance,
+        FeeRule::standard(),
+        BlockHeight::from_u32(MIN_TARGET_HEIGHT),
+        true,
+    )
+    .unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let proposal = shielding_proposal();
+    let bytes = encode_proposal(&proposal).unwrap();
+    let decoded = decode_proposal(&Utxos(vec![utxo()]), &bytes, None).unwrap();
+
+    assert_eq!(decoded.min_target_height(), proposal.min_target_height());
+    assert_eq!(decoded.steps().len(), 1);
+    let (step, decoded_step) = (proposal.steps().first(), decoded.steps().first());
+    assert!(decoded_step.is_shielding());
+    assert_eq!(
+        decoded_step
+            .transparent_inputs()
+            .iter()
+            .map(|input| input.outpoint().clone())
+            .collect::<Vec<_>>(),
+        vec![utxo().outpoint().clone()]
+    );
+    assert_eq!(
+        decoded_step.balance().fee_required(),
+        step.balance().fee_required()
+    );
+    assert_eq!(
+        decoded_step.balance().proposed_change(),
+        step.balance().proposed_change()
+    );
+    assert_eq!(encode_proposal(&decoded).unwrap(), bytes);
+}
+
+#[test]
+fn spent_input_is_reported() {
+    let bytes = encode_proposal(&shielding_proposal()).unwrap();
+    let err = decode_proposal(&Utxos(vec![]), &bytes, None).unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("it has been spent or is not in this wallet"));
+}
+
+#[test]
+fn expires_with_its_transactions() {
+    let bytes = encode_proposal(&shielding_proposal()).unwrap();
+    let wallet = Utxos(vec![utxo()]);
+
+    // Transactions expire 40 blocks after the height they were made for
+    let last_valid_tip = BlockHeight::from_u32(MIN_TARGET_HEIGHT + 39);
+    assert!(decode_proposal(&wallet, &bytes, Some(last_valid_tip)).is_ok());
+
+    let err = decode_proposal(&wallet, &bytes, Some(last_valid_tip + 1)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Proposal expired at height {} and the chain is at {}",
+            MIN_TARGET_HEIGHT + 40,
+            MIN_TARGET_HEIGHT + 40
+        )
+    );
+}
+
+#[test]
+fn garbage_is_rejected() {
+    assert!(decode_proposal(&Utxos(vec![utxo()]), &[0xff, 0x01, 0x02], None).is_err());
+}
+
+#[cfg(feature = "native")]
+mod common;
+
+#[cfg(feature = "native")]
+mod wallet {
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::synced_wallet_with_notes;
+    use webz_common::Network;
+    use webz_wallet::proposal_encoding::encode_proposal;
+    use zcash_address::ZcashAddress;
+    use zcash_client_backend::zip321::{Payment, TransactionRequest};
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+
+    #[tokio::test]
+    async fn transfers_are_restored_from_the_wallets_notes() {
+        let lightwalletd = MockLightwalletd::default();
+        let (wallet, account, txids) = synced_wallet_with_notes(&lightwalletd, &[60_000]).await;
+        let address = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+        let request = TransactionRequest::new(vec![Payment::without_memo(
+            ZcashAddress::try_from_encoded(&address).unwrap(),
+            NonNegativeAmount::const_from_u64(30_000),
+        )])
+        .unwrap();
+        let proposal = wallet.propose_request(account, request).await.unwrap();
+
+        let bytes = encode_proposal(&proposal).unwrap();
+        let decoded = wallet.decode_proposal(&bytes).await.unwrap();
+        let notes = decoded.steps().first().shielded_inputs().unwrap().notes();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes.first().txid(), &txids[0]);
+        assert_eq!(u64::from(notes.first().note().value()), 60_000);
+        assert_eq!(encode_proposal(&decoded).unwrap(), bytes);
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::lightwalletd::MockLightwalletd;
+use common::{synced_wallet_with_notes, synced_wallet_with_utxo, CHAIN_TIP};
+use webz_common::Network;
+use webz_wallet::history::Pool;
+use webz_wallet::proposal_summary::{InputSummary, PaymentSummary, ProposalSummary};
+use zcash_address::ZcashAddress;
+use zcash_client_backend::zip321::{Payment, TransactionRequest};
+use zcash_keys::encoding::AddressCodec;
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+
+#[tokio::test]
+async fn shielding_is_summarized_from_its_transparent_inputs() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, _, tx) = synced_wallet_with_utxo(&lightwalletd).await;
+    let proposal = wallet
+        .propose_shielding(account, 10_000, None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let summary = ProposalSummary::from(&proposal);
+    assert_eq!(summary.steps.len(), 1);
+    let step = &summary.steps[0];
+    assert!(step.is_shielding);
+    assert_eq!(step.target_height, CHAIN_TIP + 1);
+    assert_eq!(step.anchor_height, None);
+    assert_eq!(step.min_confirmations, None);
+    assert_eq!(
+        step.inputs,
+        vec![InputSummary {
+            pool: Pool::Transparent,
+            value: 40_000,
+            txid: Some(tx.txid().to_string()),
+            output_index: Some(0),
+            prior_step: None,
+        }]
+    );
+    assert!(step.payments.is_empty());
+    assert!(step.change.iter().all(|change| !change.is_ephemeral));
+    let change: u64 = step.change.iter().map(|change| change.value).sum();
+    assert_eq!(change + step.fee, 40_000);
+    assert_eq!(summary.total_fee, step.fee);
+    assert_eq!(step.logical_actions * 5_000, step.fee);
+}
+
+#[tokio::test]
+async fn transfers_are_summarized_from_their_notes_and_payments() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, txids) = synced_wallet_with_notes(&lightwalletd, &[60_000]).await;
+    let address = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+    let request = TransactionRequest::new(vec![Payment::without_memo(
+        ZcashAddress::try_from_encoded(&address).unwrap(),
+        NonNegativeAmount::const_from_u64(30_000),
+    )])
+    .unwrap();
+    let proposal = wallet.propose_request(account, request).await.unwrap();
+
+    let summary = ProposalSummary::from(&proposal);
+    assert_eq!(summary.steps.len(), 1);
+    let step = &summary.steps[0];
+    assert!(!step.is_shielding);
+    assert_eq!(step.target_height, CHAIN_TIP + 1);
+    let anchor_height = step.anchor_height.unwrap();
+    assert_eq!(
+        step.min_confirmations,
+        Some(step.target_height - anchor_height)
+    );
+    assert_eq!(
+        step.inputs,
+        vec![InputSummary {
+            pool: Pool::Sapling,
+            value: 60_000,
+            txid: Some(txids[0].to_string()),
+            output_index: Some(0),
+            prior_step: None,
+        }]
+    );
+    assert_eq!(
+        step.payments,
+        vec![PaymentSummary {
+            address,
+            amount: 30_000,
+            pool: Pool::Transparent,
+            memo: None,
+        }]
+    );
+    let change: u64 = step.change.iter().map(|change| change.value).sum();
+    assert_eq!(change + step.fee + 30_000, 60_000);
+    assert_eq!(summary.total_fee, step.fee);
+}
@@ -0,0 +1,93 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::str::FromStr;
+
+use common::{restore, wallet_db, TestWallet};
+use webz_common::Network;
+use webz_wallet::history::TransactionIndex;
+use zcash_address::ZcashAddress;
+use zcash_client_backend::data_api::WalletRead;
+use zcash_primitives::memo::Memo;
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zip321::{Payment, TransactionRequest};
+
+/// A testnet address, which a mainnet wallet can't pay
+const TESTNET_ADDRESS: &str = "tmEZhbWHTpdKMw5it8YDspUXSMGQyFwovpU";
+
+/// A wallet that hasn't synced, with one account, and the unified address of that account
+async fn wallet() -> (TestWallet, common::AccountId, ZcashAddress) {
+    let (db, account, _) = wallet_db().await;
+    let address = db
+        .get_current_address(account)
+        .unwrap()
+        .unwrap()
+        .encode(&Network::MainNetwork);
+    let wallet = restore(&db, &TransactionIndex::default());
+    (
+        wallet,
+        account,
+        ZcashAddress::try_from_encoded(&address).unwrap(),
+    )
+}
+
+fn payment(address: ZcashAddress, value: u64, memo: Option<&str>) -> Payment {
+    Payment::new(
+        address,
+        NonNegativeAmount::const_from_u64(value),
+        memo.map(|memo| Memo::from_str(memo).unwrap().encode()),
+        None,
+        None,
+        vec![],
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn payments_to_another_network_are_rejected() {
+    let (wallet, account, _) = wallet().await;
+    let testnet = ZcashAddress::try_from_encoded(TESTNET_ADDRESS).unwrap();
+    let request = TransactionRequest::new(vec![payment(testnet, 10_000, None)]).unwrap();
+
+    let err = wallet.propose_request(account, request).await.unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with(&format!("Cannot pay {}: ", TESTNET_ADDRESS)));
+}
+
+#[tokio::test]
+async fn every_payment_of_a_request_is_checked() {
+    let (wallet, account, address) = wallet().await;
+    let testnet = ZcashAddress::try_from_encoded(TESTNET_ADDRESS).unwrap();
+    let request = TransactionRequest::new(vec![
+        payment(address, 10_000, Some("order 1234")),
+        payment(testnet, 20_000, None),
+    ])
+    .unwrap();
+
+    let err = wallet.propose_request(account, request).await.unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with(&format!("Cannot pay {}: ", TESTNET_ADDRESS)));
+}
+
+#[tokio::test]
+async fn memos_to_shielded_recipients_are_accepted() {
+    let (wallet, account, address) = wallet().await;
+    let request = TransactionRequest::new(vec![
+        payment(address.clone(), 10_000, Some("order 1234")),
+        payment(address, 20_000, Some("order 1235")),
+    ])
+    .unwrap();
+
+    // The request gets as far as choosing notes, which needs the wallet to have synced
+    let err = wallet.propose_request(account, request).await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The wallet must finish syncing before it can make this transaction"
+    );
+}
@@ -0,0 +1,98 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::lightwalletd::MockLightwalletd;
+use common::{add_account, offline_wallet, synced_wallet_with_utxo};
+use webz_common::Network;
+use zcash_keys::encoding::AddressCodec;
+use zcash_primitives::legacy::TransparentAddress;
+
+#[tokio::test]
+async fn confirmed_transparent_funds_are_shielded() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, address, tx) = synced_wallet_with_utxo(&lightwalletd).await;
+
+    let proposal = wallet
+        .propose_shielding(account, 10_000, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(proposal.steps().len(), 1);
+    let step = proposal.steps().first();
+    assert!(step.is_shielding());
+    let inputs = step.transparent_inputs();
+    assert_eq!(inputs.len(), 1);
+    assert_eq!(inputs[0].outpoint().hash(), tx.txid().as_ref());
+    assert_eq!(inputs[0].recipient_address(), &address);
+
+    // Everything but the fee comes back as shielded change
+    let balance = step.balance();
+    let change: u64 = balance
+        .proposed_change()
+        .iter()
+        .map(|change| u64::from(change.value()))
+        .sum();
+    assert_eq!(change + u64::from(balance.fee_required()), 40_000);
+}
+
+#[tokio::test]
+async fn nothing_is_proposed_below_the_threshold() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, _, _) = synced_wallet_with_utxo(&lightwalletd).await;
+    assert!(wallet
+        .propose_shielding(account, 50_000, None)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn nothing_is_proposed_without_transparent_funds() {
+    let wallet = offline_wallet();
+    let account = add_account(&wallet, 0).await;
+    assert!(wallet
+        .propose_shielding(account, 0, None)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn only_the_account_addresses_can_be_shielded_from() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, _, _) = synced_wallet_with_utxo(&lightwalletd).await;
+    let elsewhere = TransparentAddress::PublicKeyHash([9; 20]);
+
+    let err = wallet
+        .propose_shielding(account, 10_000, Some(vec![elsewhere]))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Not a transparent address of this account: {}",
+            elsewhere.encode(&Network::MainNetwork)
+        )
+    );
+}
+
+#[tokio::test]
+async fn auto_shielding_proposes_for_accounts_above_the_threshold() {
+    let lightwalletd = MockLightwalletd::default();
+    let (wallet, account, _, _) = synced_wallet_with_utxo(&lightwalletd).await;
+    add_account(&wallet, 1).await;
+
+    let proposals = wallet.propose_auto_shielding(10_000).await.unwrap();
+    assert_eq!(proposals.len(), 1);
+    assert_eq!(proposals[0].0, account);
+
+    assert!(wallet
+        .propose_auto_shielding(50_000)
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,152 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use webz_wallet::wallet::spending_key_matches;
+use zcash_address::unified::{Container, Encoding, Fvk, Ufvk};
+use zcash_keys::keys::{UnifiedFullViewingKey, UnifiedSpendingKey};
+use zcash_primitives::consensus::MainNetwork;
+use zcash_primitives::zip32::AccountId;
+
+fn usk(seed: u8, account: u32) -> UnifiedSpendingKey {
+    UnifiedSpendingKey::from_seed(
+        &MainNetwork,
+        &[seed; 32],
+        AccountId::try_from(account).unwrap(),
+    )
+    .unwrap()
+}
+
+/// The viewing key of `usk` with only its Orchard component, as an account imported from such a key has
+fn orchard_only(usk: &UnifiedSpendingKey) -> UnifiedFullViewingKey {
+    let encoded = usk.to_unified_full_viewing_key().encode(&MainNetwork);
+    let (network, ufvk) = Ufvk::decode(&encoded).unwrap();
+    let items = ufvk
+        .items()
+        .into_iter()
+        .filter(|item| matches!(item, Fvk::Orchard(_)))
+        .collect();
+    let encoded = Ufvk::try_from_items(items).unwrap().encode(&network);
+    UnifiedFullViewingKey::decode(&MainNetwork, &encoded).unwrap()
+}
+
+#[test]
+fn key_of_the_account_matches() {
+    let key = usk(1, 0);
+    assert!(spending_key_matches(
+        &key.to_unified_full_viewing_key(),
+        &key
+    ));
+}
+
+#[test]
+fn key_of_another_account_does_not_match() {
+    let ufvk = usk(1, 0).to_unified_full_viewing_key();
+    assert!(!spending_key_matches(&ufvk, &usk(1, 1)));
+    assert!(!spending_key_matches(&ufvk, &usk(2, 0)));
+}
+
+#[test]
+fn only_the_accounts_components_are_compared() {
+    let key = usk(1, 0);
+    let ufvk = orchard_only(&key);
+    assert!(ufvk.sapling().is_none());
+    assert!(spending_key_matches(&ufvk, &key));
+    assert!(!spending_key_matches(&ufvk, &usk(2, 0)));
+}
+
+#[cfg(feature = "native")]
+mod common;
+
+#[cfg(feature = "native")]
+mod wallet {
+    use bip0039::{English, Mnemonic};
+    use webz_common::Network;
+    use webz_wallet::history::TransactionIndex;
+    use zcash_address::ZcashAddress;
+    use zcash_client_backend::zip321::{Payment, TransactionRequest};
+    use zcash_client_memory::MemoryWalletDb;
+    use zcash_keys::encoding::AddressCodec;
+    use zcash_keys::keys::UnifiedSpendingKey;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+    use zcash_primitives::zip32;
+
+    use super::common::lightwalletd::MockLightwalletd;
+    use super::common::{
+        add_account, mock_wallet, restore, sapling_address, wallet_db, MockWallet, CHAIN_TIP,
+        MINED_HEIGHT, SEED_PHRASE,
+    };
+
+    /// The spending key of the account of [`SEED_PHRASE`] at `hd_index`
+    fn usk(hd_index: u32) -> UnifiedSpendingKey {
+        let seed = <Mnemonic<English>>::from_phrase(SEED_PHRASE)
+            .unwrap()
+            .to_seed("");
+        UnifiedSpendingKey::from_seed(
+            &Network::MainNetwork,
+            &seed,
+            zip32::AccountId::try_from(hd_index).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn request() -> TransactionRequest {
+        let to = TransparentAddress::PublicKeyHash([9; 20]).encode(&Network::MainNetwork);
+        TransactionRequest::new(vec![Payment::without_memo(
+            ZcashAddress::try_from_encoded(&to).unwrap(),
+            NonNegativeAmount::const_from_u64(30_000),
+        )])
+        .unwrap()
+    }
+
+    async fn db_of(wallet: &MockWallet) -> MemoryWalletDb<Network> {
+        postcard::take_from_bytes(&wallet.db_to_bytes().await.unwrap())
+            .map(|(db, _)| db)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keys_of_other_accounts_are_rejected_without_the_index() {
+        let (db, account, _) = wallet_db().await;
+        let lightwalletd = MockLightwalletd::default();
+        lightwalletd.set_chain_tip(CHAIN_TIP);
+        lightwalletd.add_sapling_output(MINED_HEIGHT, &sapling_address(&db, account), 60_000);
+        let wallet = mock_wallet(db, &lightwalletd);
+        add_account(&wallet, 1).await;
+        wallet.sync().await.unwrap();
+        let proposal = wallet.propose_request(account, request()).await.unwrap();
+
+        // Restored without its index, the wallet finds the owner of the note in its database instead
+        let restored = restore(&db_of(&wallet).await, &TransactionIndex::default());
+        let err = restored
+            .create_proposed_transactions(proposal, &usk(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The spending key does not belong to the account the proposal spends from"
+        );
+    }
+
+    #[tokio::test]
+    async fn proposals_spending_notes_the_wallet_does_not_have_are_rejected() {
+        let (db, account, _) = wallet_db().await;
+        let lightwalletd = MockLightwalletd::default();
+        lightwalletd.set_chain_tip(CHAIN_TIP);
+        lightwalletd.add_sapling_output(MINED_HEIGHT, &sapling_address(&db, account), 60_000);
+        let unsynced = restore(&db, &TransactionIndex::default());
+        let wallet = mock_wallet(db, &lightwalletd);
+        wallet.sync().await.unwrap();
+        let proposal = wallet.propose_request(account, request()).await.unwrap();
+
+        // The same account in a wallet that hasn't synced doesn't know the note, so can't vouch for any key
+        let err = unsynced
+            .create_proposed_transactions(proposal, &usk(0))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The wallet cannot tell which of its accounts the proposal spends from"
+        );
+    }
+}
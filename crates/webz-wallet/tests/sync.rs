@@ -0,0 +1,166 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::json;
+use webz_wallet::sync::{
+    ProgressRatio, RangePriority, SyncControl, SyncEvent, SyncProgress, DEFAULT_POLL_INTERVAL,
+};
+
+#[test]
+fn progress_events_are_tagged_with_their_type() {
+    let event = SyncEvent::Progress(SyncProgress {
+        scan_range: (2_500_000, 2_502_500),
+        scan_priority: RangePriority::ChainTip,
+        blocks_downloaded: 5_000,
+        blocks_scanned: 2_500,
+        scan_progress: Some(ProgressRatio {
+            numerator: 1,
+            denominator: 4,
+        }),
+        recovery_progress: None,
+        estimated_seconds_remaining: Some(12.5),
+        chain_tip_height: 2_510_000,
+    });
+    assert_eq!(
+        serde_json::to_value(event).unwrap(),
+        json!({
+            "type": "Progress",
+            "scan_range": [2_500_000, 2_502_500],
+            "scan_priority": "ChainTip",
+            "blocks_downloaded": 5_000,
+            "blocks_scanned": 2_500,
+            "scan_progress": { "numerator": 1, "denominator": 4 },
+            "recovery_progress": null,
+            "estimated_seconds_remaining": 12.5,
+            "chain_tip_height": 2_510_000,
+        })
+    );
+}
+
+#[test]
+fn synced_events_carry_the_height() {
+    assert_eq!(
+        serde_json::to_value(SyncEvent::Synced { height: 2_510_000 }).unwrap(),
+        json!({ "type": "Synced", "height": 2_510_000 })
+    );
+}
+
+#[test]
+fn progress_ratio_fraction() {
+    let ratio = |numerator, denominator| ProgressRatio {
+        numerator,
+        denominator,
+    };
+    assert_eq!(ratio(1, 4).fraction(), 0.25);
+    assert_eq!(ratio(4, 4).fraction(), 1.0);
+    // Nothing to scan counts as done
+    assert_eq!(ratio(0, 0).fraction(), 1.0);
+}
+
+#[test]
+fn pause_and_resume() {
+    let control = SyncControl::new();
+    control.pause();
+    assert!(control.is_paused());
+    control.resume();
+    assert!(!control.is_paused());
+}
+
+#[test]
+fn cancelling_sticks_until_the_sync_ends() {
+    let control = SyncControl::new();
+    let active = control.start().unwrap();
+    control.cancel();
+    control.resume();
+    control.pause();
+    assert!(control.is_cancelled());
+
+    drop(active);
+    assert!(!control.is_cancelled());
+    assert!(!control.is_active());
+}
+
+#[test]
+fn only_one_sync_at_a_time() {
+    let control = SyncControl::new();
+    let active = control.start().unwrap();
+    assert_eq!(
+        control.start().unwrap_err().to_string(),
+        "A sync of this wallet is already running"
+    );
+    drop(active);
+    assert!(control.start().is_ok());
+}
+
+#[tokio::test]
+async fn failed_sync_releases_the_wallet() {
+    let wallet = common::offline_wallet();
+    assert!(wallet.sync_with_events(|_| {}).await.is_err());
+    assert!(!wallet.sync_control().is_active());
+
+    // A cancel made while no sync is running is cleared when the next one starts
+    wallet.sync_control().cancel();
+    let err = wallet.sync_with_events(|_| {}).await.unwrap_err();
+    assert_ne!(err.to_string(), "Sync was cancelled");
+    assert_ne!(err.to_string(), "A sync of this wallet is already running");
+    assert!(!wallet.sync_control().is_cancelled());
+}
+
+#[tokio::test]
+async fn a_second_sync_or_watch_is_rejected() {
+    let wallet = common::offline_wallet();
+    let _active = wallet.sync_control().start().unwrap();
+
+    let already_running = "A sync of this wallet is already running";
+    assert_eq!(
+        wallet.sync().await.unwrap_err().to_string(),
+        already_running
+    );
+    assert_eq!(
+        wallet
+            .watch(DEFAULT_POLL_INTERVAL, |_| {})
+            .await
+            .unwrap_err()
+            .to_string(),
+        already_running
+    );
+    // The rejected calls leave the running sync's claim alone
+    assert!(wallet.sync_control().is_active());
+}
+
+#[tokio::test]
+async fn watching_stops_when_lightwalletd_fails() {
+    let wallet = common::offline_wallet();
+    let mut events = vec![];
+    let result = wallet
+        .watch(Duration::from_millis(10), |event| events.push(event))
+        .await;
+    assert!(result.is_err());
+    assert!(events.is_empty());
+    assert!(!wallet.sync_control().is_active());
+}
+
+#[tokio::test]
+async fn wallet_is_not_locked_while_syncing() {
+    let wallet = common::offline_wallet();
+    common::add_account(&wallet, 0).await;
+
+    // Reading and writing the wallet while the sync waits on lightwalletd doesn't have to wait for the sync
+    let (sync, summary, _) = tokio::join!(
+        wallet.sync_with_events(|_| {}),
+        wallet.get_wallet_summary(),
+        common::add_account(&wallet, 1),
+    );
+    assert!(sync.is_err());
+    summary.unwrap();
+
+    // Nor does the failed sync leave it locked
+    wallet.get_wallet_summary().await.unwrap();
+    common::add_account(&wallet, 2).await;
+}
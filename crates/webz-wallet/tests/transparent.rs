@@ -0,0 +1,137 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::lightwalletd::MockLightwalletd;
+use common::{
+    input, mock_wallet, payment, transparent_transaction, wallet_db, MockWallet, CHAIN_TIP,
+    MINED_HEIGHT,
+};
+use webz_wallet::history::{Direction, HistoryFilter};
+use webz_wallet::sync::SyncEvent;
+use webz_wallet::wallet::TransparentMaturity;
+use zcash_primitives::legacy::{Script, TransparentAddress};
+use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_primitives::transaction::components::{OutPoint, TxOut};
+
+/// Sync `wallet` and return the transparent outputs the sync reported as found and spent
+async fn sync(wallet: &MockWallet) -> (usize, usize) {
+    let mut detected = (0, 0);
+    wallet
+        .sync_with_events(|event| {
+            if let SyncEvent::TransparentUtxosDetected {
+                new_utxos,
+                spent_utxos,
+            } = event
+            {
+                detected = (new_utxos, spent_utxos);
+            }
+        })
+        .await
+        .unwrap();
+    detected
+}
+
+fn output(address: &TransparentAddress, value: u64) -> TxOut {
+    TxOut {
+        value: NonNegativeAmount::const_from_u64(value),
+        script_pubkey: address.script(),
+    }
+}
+
+#[tokio::test]
+async fn outputs_to_transparent_receivers_are_found_by_sync() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    let tx = payment(&address, 40_000);
+    lightwalletd.add_utxo(&address, &tx, 0, MINED_HEIGHT);
+    let wallet = mock_wallet(db, &lightwalletd);
+
+    assert_eq!(sync(&wallet).await, (1, 0));
+    let unspent = wallet.list_unspent(account).await.unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].txid, tx.txid().to_string());
+    assert_eq!(unspent[0].value, 40_000);
+    assert_eq!(unspent[0].mined_height, Some(MINED_HEIGHT));
+
+    let page = wallet
+        .get_transaction_history(account, &HistoryFilter::default(), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(page.total, 1);
+    assert_eq!(page.entries[0].direction, Direction::Received);
+    assert_eq!(page.entries[0].value, 40_000);
+
+    // Outputs the wallet already has aren't reported again
+    assert_eq!(sync(&wallet).await, (0, 0));
+}
+
+#[tokio::test]
+async fn outputs_spent_elsewhere_are_marked_spent() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    let tx = payment(&address, 40_000);
+    lightwalletd.add_utxo(&address, &tx, 0, MINED_HEIGHT);
+    let wallet = mock_wallet(db, &lightwalletd);
+    sync(&wallet).await;
+
+    // Another wallet with the same seed spends the output
+    let elsewhere = TransparentAddress::PublicKeyHash([9; 20]);
+    let spend = transparent_transaction(
+        vec![input(OutPoint::new(*tx.txid().as_ref(), 0))],
+        vec![output(&elsewhere, 30_000)],
+    );
+    lightwalletd.add_spend(&address, &spend, MINED_HEIGHT + 2);
+
+    assert_eq!(sync(&wallet).await, (0, 1));
+    assert!(wallet.list_unspent(account).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn coinbase_outputs_wait_to_mature() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    let coinbase = transparent_transaction(
+        vec![input(OutPoint::new([0; 32], u32::MAX))],
+        vec![output(&address, 40_000)],
+    );
+    assert!(coinbase.transparent_bundle().unwrap().is_coinbase());
+    lightwalletd.add_utxo(&address, &coinbase, 0, MINED_HEIGHT);
+    let wallet = mock_wallet(db, &lightwalletd);
+
+    assert_eq!(sync(&wallet).await, (1, 0));
+    let maturity = wallet.transparent_maturity().await.unwrap();
+    assert_eq!(
+        maturity[&account],
+        TransparentMaturity {
+            spendable: 0,
+            immature: 40_000,
+        }
+    );
+}
+
+#[tokio::test]
+async fn outputs_to_other_scripts_are_ignored() {
+    let (db, account, address) = wallet_db().await;
+    let lightwalletd = MockLightwalletd::default();
+    lightwalletd.set_chain_tip(CHAIN_TIP);
+    // lightwalletd lists the output under the wallet's address but its script pays no address at all
+    let tx = transparent_transaction(
+        vec![],
+        vec![TxOut {
+            value: NonNegativeAmount::const_from_u64(40_000),
+            script_pubkey: Script(vec![0x6a]),
+        }],
+    );
+    lightwalletd.add_utxo(&address, &tx, 0, MINED_HEIGHT);
+    let wallet = mock_wallet(db, &lightwalletd);
+
+    assert_eq!(sync(&wallet).await, (0, 0));
+    assert!(wallet.list_unspent(account).await.unwrap().is_empty());
+}
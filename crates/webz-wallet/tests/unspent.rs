@@ -0,0 +1,49 @@
+// Copyright 2024 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{add_account, offline_wallet, wallet_with_utxo, CHAIN_TIP, MINED_HEIGHT};
+use webz_wallet::history::Pool;
+use zcash_primitives::transaction::TxId;
+
+#[tokio::test]
+async fn a_fresh_account_has_nothing_unspent() {
+    let wallet = offline_wallet();
+    let account = add_account(&wallet, 0).await;
+    assert!(wallet.list_unspent(account).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn confirmed_transparent_outputs_are_spendable() {
+    let (wallet, account) = wallet_with_utxo(1).await;
+    let unspent = wallet.list_unspent(account).await.unwrap();
+    assert_eq!(unspent.len(), 1);
+    let output = &unspent[0];
+    assert_eq!(output.txid, TxId::from_bytes([1; 32]).to_string());
+    assert_eq!(output.pool, Pool::Transparent);
+    assert_eq!(output.output_index, 0);
+    assert_eq!(output.value, 40_000);
+    assert_eq!(output.mined_height, Some(MINED_HEIGHT));
+    assert_eq!(output.confirmations, CHAIN_TIP - MINED_HEIGHT + 1);
+    assert!(output.spendable);
+    assert!(!output.is_change);
+    assert_eq!(output.memo, None);
+}
+
+#[tokio::test]
+async fn outputs_waiting_for_confirmations_are_listed_but_not_spendable() {
+    let (wallet, account) = wallet_with_utxo(CHAIN_TIP - MINED_HEIGHT + 2).await;
+    let unspent = wallet.list_unspent(account).await.unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert!(!unspent[0].spendable);
+}
+
+#[tokio::test]
+async fn other_accounts_outputs_are_not_listed() {
+    let (wallet, _) = wallet_with_utxo(1).await;
+    let other = add_account(&wallet, 1).await;
+    assert!(wallet.list_unspent(other).await.unwrap().is_empty());
+}
@@ -0,0 +1,49 @@
+#!/bin/bash
+#
+# Regenerates the birthday checkpoints bundled with webz-wallet by querying a lightwalletd instance for the
+# tree state every INTERVAL blocks from Sapling activation up to the current chain tip.
+#
+# Usage: ./scripts/update_checkpoints.sh <main|test> [lightwalletd host:port] [interval]
+#
+# Requires grpcurl and jq.
+
+set -euo pipefail
+
+NETWORK=${1:?"network must be main or test"}
+case "$NETWORK" in
+  main)
+    SERVER=${2:-"zec.rocks:443"}
+    START=419200
+    OUT=crates/webz-wallet/checkpoints/mainnet.json
+    ;;
+  test)
+    SERVER=${2:-"testnet.zec.rocks:443"}
+    START=280000
+    OUT=crates/webz-wallet/checkpoints/testnet.json
+    ;;
+  *)
+    echo "network must be main or test" >&2
+    exit 1
+    ;;
+esac
+INTERVAL=${3:-10000}
+
+call() {
+  grpcurl -import-path protos -proto service.proto -d "$2" "$SERVER" "cash.z.wallet.sdk.rpc.CompactTxStreamer/$1"
+}
+
+TIP=$(call GetLatestBlock '{}' | jq -r '.height')
+
+# Checkpoints are taken at multiples of INTERVAL so they don't reveal anything about the accounts created from them
+HEIGHT=$(( (START / INTERVAL + 1) * INTERVAL ))
+TMP=$(mktemp)
+while [ "$HEIGHT" -le "$TIP" ]; do
+  echo "Fetching tree state at $HEIGHT" >&2
+  call GetTreeState "{\"height\": $HEIGHT}" \
+    | jq -c '{height: (.height | tonumber), hash, time, saplingTree: (.saplingTree // ""), orchardTree: (.orchardTree // "")}' >> "$TMP"
+  HEIGHT=$(( HEIGHT + INTERVAL ))
+done
+
+jq -s '.' "$TMP" > "$OUT"
+rm "$TMP"
+echo "Wrote $(jq length "$OUT") checkpoints to $OUT" >&2